@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_table::{ClickhouseTableParts, Row, Table, TableColumn};
+
+const BATCH_SIZE: usize = 10_000;
+
+fn users_table() -> Table {
+    let columns = vec![
+        ("id", "UInt64"),
+        ("name", "String"),
+        ("email", "String"),
+        ("age", "UInt32"),
+        ("status", "Enum8('ACTIVE' = 1, 'DISABLED' = 2)"),
+        ("created_at", "DateTime"),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (name, r#type))| {
+        TableColumn::try_from(ClickhouseTableColumnRow {
+            name: name.to_string(),
+            position: i as u64 + 1,
+            r#type: r#type.to_string(),
+            default_expression: String::new(),
+        })
+        .unwrap()
+    })
+    .collect();
+
+    Table::new(
+        ClickhouseTableParts::from_string("default.users").unwrap(),
+        columns,
+    )
+}
+
+fn users(count: usize) -> Vec<Row> {
+    (0..count)
+        .map(|i| {
+            let mut row: Row = BTreeMap::new();
+            row.insert(0, Cell::UInt(i as u64));
+            row.insert(1, Cell::String(format!("User O'Brien {}?", i)));
+            row.insert(2, Cell::String(format!("user{}@example.com", i)));
+            row.insert(3, Cell::UInt((i % 90) as u64));
+            row.insert(
+                4,
+                Cell::Enum {
+                    name: Some("ACTIVE".to_string()),
+                    number: 1,
+                },
+            );
+            row.insert(
+                5,
+                Cell::DateTime {
+                    seconds: 1_600_000_000 + i as i64,
+                    nanos: 0,
+                },
+            );
+            row
+        })
+        .collect()
+}
+
+fn insert_format(c: &mut Criterion) {
+    let table = users_table();
+    let columns: Vec<usize> = (0..table.columns.len()).collect();
+    let rows = users(BATCH_SIZE);
+
+    let mut group = c.benchmark_group("insert_format");
+
+    group.bench_function("values", |b| {
+        b.iter(|| table.construct_batch(&columns, &rows).unwrap())
+    });
+
+    group.bench_function("row_binary", |b| {
+        b.iter(|| table.encode_batch(&columns, &rows).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, insert_format);
+criterion_main!(benches);
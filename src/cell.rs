@@ -0,0 +1,148 @@
+use crate::config::BytesEncoding;
+
+/// A value decoded from a message field. Cells keep the type of the proto value
+/// and are only serialized by the clickhouse output layer, which converts them to
+/// the type of the column they are written to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    /// An enum value with the name of the number, if the number is defined.
+    Enum {
+        name: Option<String>,
+        number: i64,
+    },
+    Array(Vec<Cell>),
+    Map(Vec<(Cell, Cell)>),
+    Tuple(Vec<Cell>),
+    /// A nested message by field name, in the order the fields were decoded.
+    Message(Vec<(String, Cell)>),
+    /// A point in time as seconds and nanoseconds since the unix epoch.
+    DateTime {
+        seconds: i64,
+        nanos: u32,
+    },
+    /// A span of time as seconds and nanoseconds of the same sign.
+    Duration {
+        seconds: i64,
+        nanos: i32,
+    },
+    /// A fixed point number, `value` scaled by 10 to the power of `scale`.
+    Decimal {
+        value: i128,
+        scale: u32,
+    },
+}
+
+impl Cell {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Cell::Null)
+    }
+
+    /// Encodes bytes, and the bytes in arrays, as text with the encoding.
+    pub fn encode_bytes(self, encoding: BytesEncoding) -> Cell {
+        match (self, encoding) {
+            (Cell::Bytes(bytes), BytesEncoding::Hex) => Cell::String(hex(&bytes)),
+            (Cell::Bytes(bytes), BytesEncoding::Base64) => Cell::String(base64(&bytes)),
+            (Cell::Array(values), _) => Cell::Array(
+                values
+                    .into_iter()
+                    .map(|value| value.encode_bytes(encoding))
+                    .collect(),
+            ),
+            (value, _) => value,
+        }
+    }
+}
+
+/// Lower case hexadecimal digits of the bytes.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A UUID in its hyphenated form, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+pub fn uuid_text(bytes: &[u8; 16]) -> String {
+    let hex = hex(bytes);
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 of the bytes, with padding.
+pub fn base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 4 / 3 + 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+impl From<&str> for Cell {
+    fn from(value: &str) -> Self {
+        Cell::String(value.to_string())
+    }
+}
+
+impl From<String> for Cell {
+    fn from(value: String) -> Self {
+        Cell::String(value)
+    }
+}
+
+impl From<i64> for Cell {
+    fn from(value: i64) -> Self {
+        Cell::Int(value)
+    }
+}
+
+impl From<u64> for Cell {
+    fn from(value: u64) -> Self {
+        Cell::UInt(value)
+    }
+}
+
+impl From<f64> for Cell {
+    fn from(value: f64) -> Self {
+        Cell::Float(value)
+    }
+}
+
+impl From<bool> for Cell {
+    fn from(value: bool) -> Self {
+        Cell::Bool(value)
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => Cell::Null,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use foundationdb::{tuple::Subspace, FdbError};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::config::FdbCliConfig;
+use crate::error::Error;
+use crate::fdb::FdbClient;
+use crate::result::Result;
+
+/// Progress of a mapping export, recorded after every batch that has been
+/// successfully written to clickhouse.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub last_key: Vec<u8>,
+    pub messages_written: usize,
+    #[serde(default)]
+    pub completed: bool,
+    /// Ranges of the shards of a sharded export, recorded once before exporting
+    /// them so a resume reads the same shards.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shards: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The batch after `last_key` when it failed after being written to some of
+    /// its routes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial: Option<PartialBatch>,
+}
+
+/// A batch written to some of its routes only, which a resume writes to the
+/// other routes.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct PartialBatch {
+    /// The last key of the batch.
+    pub last_key: Vec<u8>,
+    /// Indexes of the routes the batch was written to.
+    pub routes: Vec<usize>,
+}
+
+impl Checkpoint {
+    /// The first key that has not been exported yet.
+    pub fn resume_key(&self) -> Vec<u8> {
+        key_after(&self.last_key)
+    }
+}
+
+/// Returns the smallest key that sorts after `key`.
+pub fn key_after(key: &[u8]) -> Vec<u8> {
+    let mut next = key.to_vec();
+    next.push(0x00);
+    next
+}
+
+pub enum CheckpointStore {
+    File(FileCheckpointStore),
+    Fdb(FdbCheckpointStore),
+}
+
+impl CheckpointStore {
+    pub fn from_config(config: &FdbCliConfig, client: Arc<FdbClient>) -> Result<CheckpointStore> {
+        Ok(match &config.checkpoint_subspace {
+            Some(prefix) => {
+                debug!("Using fdb checkpoint subspace: {}", prefix);
+                CheckpointStore::Fdb(FdbCheckpointStore::new(client, prefix.as_bytes()))
+            }
+            None => {
+                debug!("Using checkpoint file path: {}", &config.checkpoint_file);
+                CheckpointStore::File(FileCheckpointStore::open(&config.checkpoint_file)?)
+            }
+        })
+    }
+
+    pub async fn get(&self, mapping: &str) -> Result<Option<Checkpoint>> {
+        match self {
+            CheckpointStore::File(store) => Ok(store.get(mapping)),
+            CheckpointStore::Fdb(store) => store.get(mapping).await,
+        }
+    }
+
+    pub async fn save(&self, mapping: &str, checkpoint: &Checkpoint) -> Result<()> {
+        match self {
+            CheckpointStore::File(store) => store.save(mapping, checkpoint),
+            CheckpointStore::Fdb(store) => store.save(mapping, checkpoint).await,
+        }
+    }
+
+    pub async fn clear(&self, mapping: &str) -> Result<()> {
+        match self {
+            CheckpointStore::File(store) => store.clear(mapping),
+            CheckpointStore::Fdb(store) => store.clear(mapping).await,
+        }
+    }
+}
+
+/// Keeps checkpoints for every mapping in a local json state file.
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    checkpoints: Mutex<HashMap<String, Checkpoint>>,
+}
+
+impl FileCheckpointStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<FileCheckpointStore> {
+        let path = path.into();
+
+        let checkpoints = if path.exists() {
+            let data = std::fs::read_to_string(&path)?;
+            serde_json::from_str(&data)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(FileCheckpointStore {
+            path,
+            checkpoints: Mutex::new(checkpoints),
+        })
+    }
+
+    pub fn get(&self, mapping: &str) -> Option<Checkpoint> {
+        self.checkpoints.lock().unwrap().get(mapping).cloned()
+    }
+
+    pub fn save(&self, mapping: &str, checkpoint: &Checkpoint) -> Result<()> {
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        checkpoints.insert(mapping.to_string(), checkpoint.clone());
+
+        self.persist(&checkpoints)
+    }
+
+    pub fn clear(&self, mapping: &str) -> Result<()> {
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        if checkpoints.remove(mapping).is_none() {
+            return Ok(());
+        }
+
+        self.persist(&checkpoints)
+    }
+
+    fn persist(&self, checkpoints: &HashMap<String, Checkpoint>) -> Result<()> {
+        let data = serde_json::to_string(checkpoints)?;
+
+        // Write to a temporary file first so a crash mid-write never leaves a truncated state file
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, data).map_err(Error::UnableToWriteCheckpoint)?;
+        std::fs::rename(&tmp, &self.path).map_err(Error::UnableToWriteCheckpoint)?;
+
+        Ok(())
+    }
+}
+
+/// Keeps checkpoints for every mapping under a subspace of the exported cluster.
+pub struct FdbCheckpointStore {
+    client: Arc<FdbClient>,
+    subspace: Subspace,
+}
+
+impl FdbCheckpointStore {
+    pub fn new(client: Arc<FdbClient>, prefix: &[u8]) -> FdbCheckpointStore {
+        FdbCheckpointStore {
+            client,
+            subspace: Subspace::from_bytes(prefix),
+        }
+    }
+
+    pub async fn get(&self, mapping: &str) -> Result<Option<Checkpoint>> {
+        let tx = self.client.begin_tx().await?;
+
+        match tx.get(&self.subspace.pack(&mapping), false).await? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn save(&self, mapping: &str, checkpoint: &Checkpoint) -> Result<()> {
+        let tx = self.client.begin_tx().await?;
+
+        tx.set(
+            &self.subspace.pack(&mapping),
+            &serde_json::to_vec(checkpoint)?,
+        );
+        tx.commit().await.map_err(FdbError::from)?;
+
+        Ok(())
+    }
+
+    pub async fn clear(&self, mapping: &str) -> Result<()> {
+        let tx = self.client.begin_tx().await?;
+
+        tx.clear(&self.subspace.pack(&mapping));
+        tx.commit().await.map_err(FdbError::from)?;
+
+        Ok(())
+    }
+}
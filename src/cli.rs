@@ -1,12 +1,20 @@
 use structopt::StructOpt;
 
+use crate::clickhouse::InsertFormat;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "fdb-cli", about = "foundation db cli tool")]
 pub enum Opts {
     // Setup a foundation db instance
     Setup(Setup),
 
-    Export,
+    Export(Export),
+
+    // Export and then keep exporting the changes recorded in each mapping changelog
+    Sync(Sync),
+
+    // Generate the clickhouse tables of proto messages
+    Schema(Schema),
 }
 
 #[derive(Debug, StructOpt)]
@@ -27,8 +35,135 @@ pub struct Set {
     #[structopt(long, help = "Clickhouse url")]
     pub clickhouse_url: Option<String>,
 
+    #[structopt(long, help = "Clickhouse insert format (RowBinary or Values)")]
+    pub insert_format: Option<InsertFormat>,
+
     #[structopt(long, help = "Path to the mapping")]
     pub mapping_file: Option<String>,
+
+    #[structopt(long, help = "Path to the export checkpoint state file")]
+    pub checkpoint_file: Option<String>,
+
+    #[structopt(long, help = "Fdb subspace prefix to store export checkpoints in")]
+    pub checkpoint_subspace: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Export {
+    #[structopt(long, help = "Resume each mapping from its last checkpoint")]
+    pub resume: bool,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of shards each mapping range is split into and exported concurrently. Resuming requires the same number of workers"
+    )]
+    pub workers: usize,
+
+    #[structopt(
+        long,
+        default_value = "4",
+        help = "Maximum number of mappings exported at once"
+    )]
+    pub concurrency: usize,
+
+    #[structopt(long, help = "Stop exporting as soon as a mapping fails")]
+    pub fail_fast: bool,
+
+    #[structopt(
+        long,
+        help = "Decode the messages and report the rows they generate without writing them or their checkpoints"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Sync {
+    #[structopt(
+        long,
+        default_value = "1000",
+        help = "Milliseconds to wait between reads of the changelogs"
+    )]
+    pub interval: u64,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of shards each mapping range is split into for the initial export, which resumes the last export and so must match its --workers"
+    )]
+    pub workers: usize,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Schema {
+    // Print the CREATE TABLE query of a table holding the messages of a type
+    Generate(Generate),
+
+    // Compare the tables of the mappings with the fields of their messages
+    Diff(Diff),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Generate {
+    #[structopt(long, help = "Full name of the message, e.g. protos.User")]
+    pub proto: String,
+
+    #[structopt(long, help = "Table to create, as <database>.<table_name>")]
+    pub table: String,
+
+    #[structopt(long, help = "Table engine, MergeTree by default")]
+    pub engine: Option<String>,
+
+    #[structopt(long, help = "Sorting key of a MergeTree table, tuple() by default")]
+    pub order_by: Option<String>,
+
+    #[structopt(long, help = "Partition key of a MergeTree table")]
+    pub partition_by: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Write wrappers and the fields of nested messages to Nullable columns"
+    )]
+    pub nullable: bool,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "Depth of the nested messages whose fields get their own columns, deeper messages are written as JSON"
+    )]
+    pub flatten: usize,
+
+    #[structopt(long, help = "Create the table on the clickhouse server")]
+    pub apply: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Diff {
+    #[structopt(
+        long,
+        help = "Write wrappers and the fields of nested messages to Nullable columns"
+    )]
+    pub nullable: bool,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "Depth of the nested messages whose fields get their own columns, deeper messages are written as JSON"
+    )]
+    pub flatten: usize,
+
+    #[structopt(
+        long,
+        help = "Run the statements adding columns and modifying them without losing values"
+    )]
+    pub apply: bool,
+
+    #[structopt(
+        long,
+        requires = "apply",
+        help = "Also run the statements modifying columns that may lose values"
+    )]
+    pub lossy: bool,
 }
 
 pub fn parse() -> Opts {
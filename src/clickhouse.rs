@@ -1,7 +1,10 @@
-use crate::clickhouse_table::ClickhouseTableParts;
+use crate::clickhouse_table::{ClickhouseTableParts, Row, Table};
+use crate::error::Error;
 use crate::result::Result;
+use hyper::{client::HttpConnector, Body, Method, Request};
 use serde::{Deserialize, Serialize};
 use tracing::debug;
+use url::Url;
 
 #[derive(Debug, clickhouse::Row, Serialize, Deserialize, Clone)]
 pub struct ClickhouseTableColumnRow {
@@ -11,13 +14,43 @@ pub struct ClickhouseTableColumnRow {
     pub default_expression: String,
 }
 
+/// Format used to send inserted rows to clickhouse.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum InsertFormat {
+    /// Rows encoded in the binary format of each column type.
+    #[default]
+    RowBinary,
+    /// Rows rendered as literals of an `INSERT ... VALUES` query.
+    Values,
+}
+
+impl std::str::FromStr for InsertFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "RowBinary" => Ok(InsertFormat::RowBinary),
+            "Values" => Ok(InsertFormat::Values),
+            _ => Err(Error::ParseError(format!("Unknown insert format: {}", s))),
+        }
+    }
+}
+
 pub struct Client {
     pub client: clickhouse::Client,
+    http: hyper::Client<HttpConnector>,
+    url: String,
+    format: InsertFormat,
 }
 
 impl Client {
-    pub fn new(client: clickhouse::Client) -> Self {
-        Self { client }
+    pub fn new(url: &str, format: InsertFormat) -> Self {
+        Self {
+            client: clickhouse::Client::default().with_url(url),
+            http: hyper::Client::new(),
+            url: url.to_string(),
+            format,
+        }
     }
 
     pub async fn table_columns(
@@ -40,13 +73,81 @@ impl Client {
         Ok(rows)
     }
 
-    pub async fn write_batch(&self, query: String) -> Result<()> {
-        debug!("writing batch: {}", &query);
+    /// Inserts the given columns of every row into the table, with a query for each set of columns
+    /// the rows are written with (see [`Table::split_by_columns`]).
+    pub async fn insert_batch(&self, table: &Table, columns: &[usize], rows: &[Row]) -> Result<()> {
+        for (columns, rows) in table.split_by_columns(columns, rows) {
+            self.insert_rows(table, &columns, rows).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn insert_rows(&self, table: &Table, columns: &[usize], rows: Vec<&Row>) -> Result<()> {
+        // Columns without a binary encoding are inserted as literals in any format
+        match self.format {
+            InsertFormat::RowBinary if table.has_binary_encoding(columns) => {
+                let query = table.insert_statement(columns, "RowBinary");
+                let body = table.encode_batch(columns, rows)?;
+
+                debug!("writing batch: {} ({} bytes)", &query, body.len());
+
+                self.post(Some(&query), body)
+                    .await
+                    .map_err(|e| Error::ClickhouseInsert(format!("inserting batch: {}", e)))
+            }
+            _ => {
+                let query = table.construct_batch(columns, rows)?;
+
+                debug!("writing batch: {}", &query);
+
+                self.post(None, query.into_bytes())
+                    .await
+                    .map_err(|e| Error::ClickhouseInsert(format!("inserting batch: {}", e)))
+            }
+        }
+    }
+
+    /// Runs a query that returns no rows, e.g. `CREATE TABLE`.
+    pub async fn execute(&self, query: &str) -> Result<()> {
+        debug!("executing: {}", query);
+
+        self.post(None, query.as_bytes().to_vec())
+            .await
+            .map_err(Error::ClickhouseQuery)
+    }
+
+    /// Sends a request to the http interface. Without a `query` the body is the query.
+    async fn post(&self, query: Option<&str>, body: Vec<u8>) -> std::result::Result<(), String> {
+        let mut url = Url::parse(&self.url).map_err(|e| format!("invalid url: {}", e))?;
+        if let Some(query) = query {
+            url.query_pairs_mut().append_pair("query", query);
+        }
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(url.as_str())
+            .body(Body::from(body))
+            .map_err(|e| e.to_string())?;
+
+        let response = self
+            .http
+            .request(request)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| e.to_string())?;
 
-        self.client.query(&query).execute().await.map_err(|e| {
-            format!("inserting batch: {}", &e);
-            e
-        })?;
+            return Err(format!(
+                "{}: {}",
+                status,
+                String::from_utf8_lossy(&body).trim()
+            ));
+        }
 
         Ok(())
     }
@@ -0,0 +1,631 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::{
+    cell::{uuid_text, Cell},
+    clickhouse_type::ColumnType,
+    error::Error,
+    result::Result,
+};
+
+/// Appends `value` to `buf` in the RowBinary format of the column type. `Null`
+/// is only accepted by `Nullable` columns.
+pub fn encode_value(buf: &mut Vec<u8>, r#type: &ColumnType, value: &Cell) -> Result<()> {
+    if let ColumnType::Nullable(inner) = r#type {
+        if value.is_null() {
+            buf.push(1);
+            return Ok(());
+        }
+
+        buf.push(0);
+        return encode_value(buf, inner, value);
+    }
+
+    if let ColumnType::LowCardinality(inner) = r#type {
+        return encode_value(buf, inner, value);
+    }
+
+    if let Some(element) = r#type.element() {
+        let values = array_values(r#type, value)?;
+
+        write_varint(buf, values.len() as u64);
+        for value in values {
+            encode_value(buf, &element, value)?;
+        }
+
+        return Ok(());
+    }
+
+    if value.is_null() {
+        return Err(Error::ParseError(format!(
+            "NULL is not allowed in a {} column",
+            r#type
+        )));
+    }
+
+    match r#type {
+        ColumnType::Map(key_type, value_type) => {
+            let entries = map_entries(r#type, value)?;
+
+            write_varint(buf, entries.len() as u64);
+            for (key, value) in entries {
+                encode_value(buf, key_type, key)?;
+                encode_value(buf, value_type, value)?;
+            }
+        }
+        ColumnType::Tuple(elements) => {
+            for ((_, element_type), value) in elements.iter().zip(tuple_values(r#type, value)?) {
+                encode_value(buf, element_type, value)?;
+            }
+        }
+        ColumnType::String => write_bytes(buf, &string_bytes(r#type, value)?),
+        ColumnType::Bool | ColumnType::UInt8 => buf.push(integer_value(r#type, value)? as u8),
+        ColumnType::UInt16 => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as u16).to_le_bytes())
+        }
+        ColumnType::UInt32 | ColumnType::DateTime(_) => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as u32).to_le_bytes())
+        }
+        ColumnType::UInt64 => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as u64).to_le_bytes())
+        }
+        ColumnType::Int8 => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as i8).to_le_bytes())
+        }
+        ColumnType::Int16 => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as i16).to_le_bytes())
+        }
+        ColumnType::Int32 => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as i32).to_le_bytes())
+        }
+        ColumnType::Int64 => {
+            buf.extend_from_slice(&(integer_value(r#type, value)? as i64).to_le_bytes())
+        }
+        ColumnType::Float32 => {
+            buf.extend_from_slice(&(float_value(r#type, value)? as f32).to_le_bytes())
+        }
+        ColumnType::Float64 => buf.extend_from_slice(&float_value(r#type, value)?.to_le_bytes()),
+        ColumnType::Uuid => buf.extend_from_slice(&uuid_value(r#type, value)?),
+        ColumnType::Ipv4 => {
+            buf.extend_from_slice(&u32::from(ipv4_value(r#type, value)?).to_le_bytes())
+        }
+        ColumnType::Ipv6 => buf.extend_from_slice(&ipv6_value(r#type, value)?.octets()),
+        ColumnType::Decimal { precision, .. } => {
+            let size = match precision {
+                1..=9 => 4,
+                10..=18 => 8,
+                19..=38 => 16,
+                _ => return Err(unsupported(r#type)),
+            };
+            buf.extend_from_slice(&decimal_value(r#type, value)?.to_le_bytes()[..size]);
+        }
+        ColumnType::DateTime64 { .. } => {
+            buf.extend_from_slice(&datetime64_ticks(r#type, value)?.to_le_bytes())
+        }
+        ColumnType::Date => {
+            buf.extend_from_slice(&(date_days(r#type, value)? as u16).to_le_bytes())
+        }
+        ColumnType::Date32 => {
+            buf.extend_from_slice(&(date_days(r#type, value)? as i32).to_le_bytes())
+        }
+        ColumnType::Enum8(_) => {
+            buf.extend_from_slice(&(enum_number(r#type, value)? as i8).to_le_bytes())
+        }
+        ColumnType::Enum16(_) => {
+            buf.extend_from_slice(&(enum_number(r#type, value)? as i16).to_le_bytes())
+        }
+        ColumnType::FixedString(size) => {
+            let bytes = string_bytes(r#type, value)?;
+            if bytes.len() > *size {
+                return Err(Error::ParseError(format!(
+                    "Value of {} bytes is too long for {}",
+                    bytes.len(),
+                    r#type
+                )));
+            }
+
+            buf.extend_from_slice(&bytes);
+            buf.resize(buf.len() + size - bytes.len(), 0);
+        }
+        _ => return Err(unsupported(r#type)),
+    }
+
+    Ok(())
+}
+
+/// Renders `value` as a literal of the column type for an `INSERT ... VALUES` query.
+/// Values of types without a binary encoding are sent as quoted text.
+pub fn sql_literal(r#type: &ColumnType, value: &Cell) -> Result<String> {
+    if value.is_null() {
+        return Ok("NULL".to_string());
+    }
+
+    let inner = r#type.inner();
+
+    if let Some(element) = inner.element() {
+        let values = array_values(inner, value)?
+            .iter()
+            .map(|value| sql_literal(&element, value))
+            .collect::<Result<Vec<String>>>()?;
+
+        return Ok(format!("[{}]", values.join(",")));
+    }
+
+    Ok(match inner {
+        ColumnType::Map(key_type, value_type) => {
+            let entries = map_entries(inner, value)?
+                .iter()
+                .map(|(key, value)| {
+                    Ok(format!(
+                        "{}:{}",
+                        sql_literal(key_type, key)?,
+                        sql_literal(value_type, value)?
+                    ))
+                })
+                .collect::<Result<Vec<String>>>()?;
+
+            format!("{{{}}}", entries.join(","))
+        }
+        ColumnType::Tuple(elements) => {
+            let values = elements
+                .iter()
+                .zip(tuple_values(inner, value)?)
+                .map(|((_, element_type), value)| sql_literal(element_type, value))
+                .collect::<Result<Vec<String>>>()?;
+
+            format!("({})", values.join(","))
+        }
+        ColumnType::DateTime64 { precision, .. } => {
+            let ticks = datetime64_ticks(inner, value)?;
+            let scale = 10u64.pow(*precision);
+
+            // The fraction counts from the whole seconds towards zero, e.g. -1.5 s
+            let sign = if ticks < 0 { "-" } else { "" };
+            let ticks = ticks.unsigned_abs();
+
+            match precision {
+                0 => format!("'{}{}'", sign, ticks),
+                _ => format!(
+                    "'{}{}.{:0width$}'",
+                    sign,
+                    ticks / scale,
+                    ticks % scale,
+                    width = *precision as usize
+                ),
+            }
+        }
+        ColumnType::Uuid => quote(uuid_text(&uuid_number(inner, value)?.to_be_bytes()).as_bytes()),
+        ColumnType::Ipv4 => quote(ipv4_value(inner, value)?.to_string().as_bytes()),
+        ColumnType::Ipv6 => quote(ipv6_value(inner, value)?.to_string().as_bytes()),
+        ColumnType::DateTime(_) => format!("'{}'", integer_value(inner, value)?),
+        ColumnType::Date | ColumnType::Date32 => {
+            format!("'{}'", date_text(date_days(inner, value)?))
+        }
+        ColumnType::Decimal { scale, .. } => decimal_text(decimal_value(inner, value)?, *scale),
+        ColumnType::Enum8(values) | ColumnType::Enum16(values) => {
+            let number = enum_number(inner, value)?;
+
+            match values.iter().find(|(_, n)| *n as i128 == number) {
+                Some((name, _)) => quote(name.as_bytes()),
+                None => number.to_string(),
+            }
+        }
+        ColumnType::Float32 | ColumnType::Float64 => float_value(inner, value)?.to_string(),
+        _ if inner.integer_range().is_some() => integer_value(inner, value)?.to_string(),
+        _ => quote(&string_bytes(inner, value)?),
+    })
+}
+
+/// Renders a cell as JSON, used when a nested value is written to a `String` column.
+pub fn json_value(value: &Cell) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Cell::Null => Value::Null,
+        Cell::Bool(v) => Value::from(*v),
+        Cell::Int(v) => Value::from(*v),
+        Cell::UInt(v) => Value::from(*v),
+        Cell::Float(v) => Value::from(*v),
+        Cell::String(v) => Value::from(v.as_str()),
+        Cell::Bytes(v) => Value::from(String::from_utf8_lossy(v)),
+        Cell::Enum { name, number } => match name {
+            Some(name) => Value::from(name.as_str()),
+            None => Value::from(*number),
+        },
+        Cell::Array(values) | Cell::Tuple(values) => {
+            Value::Array(values.iter().map(json_value).collect())
+        }
+        Cell::Map(entries) => Value::Object(
+            entries
+                .iter()
+                .map(|(key, value)| {
+                    let key = match json_value(key) {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    (key, json_value(value))
+                })
+                .collect(),
+        ),
+        Cell::Message(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), json_value(value)))
+                .collect(),
+        ),
+        Cell::DateTime { seconds, nanos } => match nanos {
+            0 => Value::from(*seconds),
+            _ => Value::from(*seconds as f64 + *nanos as f64 / 1e9),
+        },
+        Cell::Duration { seconds, nanos } => Value::from(duration_text(*seconds, *nanos)),
+        // As text, which keeps every digit
+        Cell::Decimal { value, scale } => Value::from(decimal_text(*value, *scale)),
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Quotes bytes as a string literal, escaping anything that is not printable ASCII.
+fn quote(bytes: &[u8]) -> String {
+    let mut literal = String::with_capacity(bytes.len() + 2);
+    literal.push('\'');
+
+    match std::str::from_utf8(bytes) {
+        Ok(value) => literal.push_str(&value.replace('\\', "\\\\").replace('\'', "\\'")),
+        Err(_) => {
+            for byte in bytes {
+                match byte {
+                    b'\\' => literal.push_str("\\\\"),
+                    b'\'' => literal.push_str("\\'"),
+                    0x20..=0x7e => literal.push(*byte as char),
+                    _ => literal.push_str(&format!("\\x{:02X}", byte)),
+                }
+            }
+        }
+    }
+
+    literal.push('\'');
+    literal
+}
+
+fn mismatch(r#type: &ColumnType, value: &Cell) -> Error {
+    Error::ParseError(format!("Invalid {} value: {:?}", r#type, value))
+}
+
+fn unsupported(r#type: &ColumnType) -> Error {
+    Error::ParseError(format!("Unsupported column type: {}", r#type))
+}
+
+fn parse<T: FromStr>(r#type: &ColumnType, value: &str) -> Result<T> {
+    value
+        .parse::<T>()
+        .map_err(|_e| Error::ParseError(format!("Invalid {} value: {}", r#type, value)))
+}
+
+fn array_values<'c>(r#type: &ColumnType, value: &'c Cell) -> Result<&'c [Cell]> {
+    match value {
+        Cell::Array(values) => Ok(values),
+        _ => Err(mismatch(r#type, value)),
+    }
+}
+
+fn map_entries<'c>(r#type: &ColumnType, value: &'c Cell) -> Result<&'c [(Cell, Cell)]> {
+    match value {
+        Cell::Map(entries) => Ok(entries),
+        _ => Err(mismatch(r#type, value)),
+    }
+}
+
+fn tuple_values<'c>(r#type: &ColumnType, value: &'c Cell) -> Result<&'c [Cell]> {
+    match (r#type, value) {
+        (ColumnType::Tuple(elements), Cell::Tuple(values)) if values.len() == elements.len() => {
+            Ok(values)
+        }
+        _ => Err(mismatch(r#type, value)),
+    }
+}
+
+/// The bytes written to a `String` or `FixedString` column.
+fn string_bytes(r#type: &ColumnType, value: &Cell) -> Result<Vec<u8>> {
+    Ok(match value {
+        Cell::String(v) => v.as_bytes().to_vec(),
+        Cell::Bytes(v) => v.clone(),
+        Cell::Bool(v) => v.to_string().into_bytes(),
+        Cell::Int(v) => v.to_string().into_bytes(),
+        Cell::UInt(v) => v.to_string().into_bytes(),
+        Cell::Float(v) => v.to_string().into_bytes(),
+        Cell::Enum { name, number } => match name {
+            Some(name) => name.clone().into_bytes(),
+            None => number.to_string().into_bytes(),
+        },
+        Cell::DateTime { seconds, .. } => seconds.to_string().into_bytes(),
+        Cell::Duration { seconds, nanos } => duration_text(*seconds, *nanos).into_bytes(),
+        Cell::Decimal { value, scale } => decimal_text(*value, *scale).into_bytes(),
+        Cell::Array(_) | Cell::Map(_) | Cell::Tuple(_) | Cell::Message(_) => {
+            json_value(value).to_string().into_bytes()
+        }
+        Cell::Null => return Err(mismatch(r#type, value)),
+    })
+}
+
+/// Converts a cell to an integer within the range of the column type.
+fn integer_value(r#type: &ColumnType, value: &Cell) -> Result<i128> {
+    let number = match value {
+        Cell::Bool(v) => *v as i128,
+        Cell::Int(v) => *v as i128,
+        Cell::UInt(v) => *v as i128,
+        Cell::Enum { number, .. } => *number as i128,
+        Cell::DateTime { seconds, .. } => *seconds as i128,
+        // Durations are written to integer columns as nanoseconds
+        Cell::Duration { seconds, nanos } => *seconds as i128 * 1_000_000_000 + *nanos as i128,
+        Cell::Float(v) if v.fract() == 0.0 => *v as i128,
+        Cell::Decimal { value, scale } if value % 10i128.pow(*scale) == 0 => {
+            value / 10i128.pow(*scale)
+        }
+        Cell::String(v) => match v.as_str() {
+            "true" => 1,
+            "false" => 0,
+            v => parse::<i128>(r#type, v)?,
+        },
+        _ => return Err(mismatch(r#type, value)),
+    };
+
+    match r#type.integer_range() {
+        Some((min, max)) if number < min || number > max => Err(Error::ParseError(format!(
+            "Value {} is out of range for {}",
+            number, r#type
+        ))),
+        _ => Ok(number),
+    }
+}
+
+fn float_value(r#type: &ColumnType, value: &Cell) -> Result<f64> {
+    match value {
+        Cell::Float(v) => Ok(*v),
+        Cell::Int(v) => Ok(*v as f64),
+        Cell::UInt(v) => Ok(*v as f64),
+        Cell::Bool(v) => Ok(*v as u8 as f64),
+        // and to float columns as seconds
+        Cell::Duration { seconds, nanos } => Ok(*seconds as f64 + *nanos as f64 / 1e9),
+        Cell::Decimal { value, scale } => Ok(*value as f64 / 10f64.powi(*scale as i32)),
+        Cell::String(v) => parse::<f64>(r#type, v),
+        _ => Err(mismatch(r#type, value)),
+    }
+}
+
+/// A duration in the proto3 JSON format, e.g. `1.500s`.
+fn duration_text(seconds: i64, nanos: i32) -> String {
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    let (seconds, nanos) = (seconds.unsigned_abs(), nanos.unsigned_abs());
+
+    match nanos {
+        0 => format!("{}{}s", sign, seconds),
+        _ if nanos % 1_000_000 == 0 => format!("{}{}.{:03}s", sign, seconds, nanos / 1_000_000),
+        _ if nanos % 1_000 == 0 => format!("{}{}.{:06}s", sign, seconds, nanos / 1_000),
+        _ => format!("{}{}.{:09}s", sign, seconds, nanos),
+    }
+}
+
+/// Converts a cell to the scaled integer of a decimal column. Digits beyond the
+/// scale of the column are truncated, as clickhouse does.
+fn decimal_value(r#type: &ColumnType, value: &Cell) -> Result<i128> {
+    let (precision, scale) = match r#type {
+        ColumnType::Decimal { precision, scale } if *precision <= 38 => (*precision, *scale),
+        _ => return Err(unsupported(r#type)),
+    };
+
+    let out_of_range =
+        || Error::ParseError(format!("Value {:?} is out of range for {}", value, r#type));
+
+    // Digits dropped by a smaller scale may be more than an i128 holds, leaving 0
+    let rescale = |number: i128, from: u32| {
+        if from > scale {
+            Ok(10i128
+                .checked_pow(from - scale)
+                .map_or(0, |unit| number / unit))
+        } else {
+            10i128
+                .checked_pow(scale - from)
+                .and_then(|unit| number.checked_mul(unit))
+                .ok_or_else(out_of_range)
+        }
+    };
+
+    let number = match value {
+        Cell::Decimal { value, scale } => rescale(*value, *scale)?,
+        Cell::Int(v) => rescale(*v as i128, 0)?,
+        Cell::UInt(v) => rescale(*v as i128, 0)?,
+        Cell::Float(v) => (v * 10f64.powi(scale as i32)).trunc() as i128,
+        Cell::String(v) => {
+            let (whole, fraction) = v.split_once('.').unwrap_or((v, ""));
+            let digits = format!("{}{}", whole, fraction);
+            rescale(parse::<i128>(r#type, &digits)?, fraction.len() as u32)?
+        }
+        _ => return Err(mismatch(r#type, value)),
+    };
+
+    if number.unsigned_abs() >= 10u128.pow(precision) {
+        return Err(out_of_range());
+    }
+
+    Ok(number)
+}
+
+/// A decimal with all the digits of its scale, e.g. `-1.50` for -150 at scale 2.
+fn decimal_text(value: i128, scale: u32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let unit = 10u128.pow(scale);
+    let (whole, fraction) = (value.unsigned_abs() / unit, value.unsigned_abs() % unit);
+
+    match scale {
+        0 => format!("{}{}", sign, whole),
+        _ => format!(
+            "{}{}.{:0width$}",
+            sign,
+            whole,
+            fraction,
+            width = scale as usize
+        ),
+    }
+}
+
+/// Converts a cell to ticks of the precision of a `DateTime64` column.
+fn datetime64_ticks(r#type: &ColumnType, value: &Cell) -> Result<i64> {
+    let precision = match r#type {
+        ColumnType::DateTime64 { precision, .. } => *precision,
+        _ => return Err(unsupported(r#type)),
+    };
+
+    let (seconds, nanos) = match value {
+        Cell::DateTime { seconds, nanos } => (*seconds, *nanos as i64),
+        Cell::Int(v) => (*v, 0),
+        Cell::UInt(v) => (*v as i64, 0),
+        Cell::String(v) => (parse::<i64>(r#type, v)?, 0),
+        _ => return Err(mismatch(r#type, value)),
+    };
+
+    seconds
+        .checked_mul(10i64.pow(precision))
+        .and_then(|ticks| ticks.checked_add(nanos / 10i64.pow(9 - precision)))
+        .ok_or_else(|| {
+            Error::ParseError(format!("Value {:?} is out of range for {}", value, r#type))
+        })
+}
+
+/// Converts a cell to days since 1970-01-01 for a `Date` or `Date32` column. Times
+/// are written as their day, and numbers as a number of days.
+fn date_days(r#type: &ColumnType, value: &Cell) -> Result<i64> {
+    let (min, max) = match r#type {
+        ColumnType::Date => (0, u16::MAX as i64),
+        // 1900-01-01 to 2299-12-31
+        ColumnType::Date32 => (-25_567, 120_529),
+        _ => return Err(unsupported(r#type)),
+    };
+
+    let days = match value {
+        Cell::DateTime { seconds, .. } => seconds.div_euclid(86_400) as i128,
+        value => integer_value(r#type, value)?,
+    };
+
+    if days < min as i128 || days > max as i128 {
+        return Err(Error::ParseError(format!(
+            "Value {:?} is out of range for {}",
+            value, r#type
+        )));
+    }
+
+    Ok(days as i64)
+}
+
+/// The `YYYY-MM-DD` text of a number of days since 1970-01-01, in the proleptic
+/// Gregorian calendar.
+fn date_text(days: i64) -> String {
+    // Days since 0000-03-01, counted in eras of 400 years so leap years repeat
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months from March, so the leap day is the last day of the year
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = match month {
+        0..=9 => (era * 400 + year_of_era, month + 3),
+        _ => (era * 400 + year_of_era + 1, month - 9),
+    };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn enum_number(r#type: &ColumnType, value: &Cell) -> Result<i128> {
+    let name = match value {
+        Cell::Enum {
+            name: Some(name), ..
+        } => Some(name),
+        Cell::String(name) => Some(name),
+        _ => None,
+    };
+
+    if let Some(name) = name {
+        if let Some((_, number)) = r#type
+            .enum_values()
+            .unwrap_or_default()
+            .iter()
+            .find(|(n, _)| n == name)
+        {
+            return Ok(*number as i128);
+        }
+    }
+
+    integer_value(r#type, value)
+}
+
+/// RowBinary stores a UUID as its two 64 bit halves in little endian order.
+fn uuid_value(r#type: &ColumnType, value: &Cell) -> Result<[u8; 16]> {
+    let number = uuid_number(r#type, value)?;
+
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&((number >> 64) as u64).to_le_bytes());
+    bytes[8..].copy_from_slice(&(number as u64).to_le_bytes());
+
+    Ok(bytes)
+}
+
+/// Reads a UUID from its text, or from its 16 bytes in network order.
+fn uuid_number(r#type: &ColumnType, value: &Cell) -> Result<u128> {
+    let value = match value {
+        Cell::String(value) => value,
+        Cell::Bytes(bytes) if bytes.len() == 16 => {
+            let mut number = [0u8; 16];
+            number.copy_from_slice(bytes);
+            return Ok(u128::from_be_bytes(number));
+        }
+        _ => return Err(mismatch(r#type, value)),
+    };
+
+    let hex: String = value.chars().filter(|c| *c != '-').collect();
+    let number = match hex.len() {
+        32 => u128::from_str_radix(&hex, 16).ok(),
+        _ => None,
+    };
+
+    match number {
+        Some(number) => Ok(number),
+        None => Err(Error::ParseError(format!("Invalid UUID value: {}", value))),
+    }
+}
+
+fn ipv4_value(r#type: &ColumnType, value: &Cell) -> Result<Ipv4Addr> {
+    match value {
+        Cell::String(v) => parse::<Ipv4Addr>(r#type, v),
+        Cell::UInt(v) if *v <= u32::MAX as u64 => Ok(Ipv4Addr::from(*v as u32)),
+        Cell::Bytes(bytes) if bytes.len() == 4 => {
+            Ok(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+        }
+        _ => Err(mismatch(r#type, value)),
+    }
+}
+
+fn ipv6_value(r#type: &ColumnType, value: &Cell) -> Result<Ipv6Addr> {
+    match value {
+        Cell::String(v) => parse::<Ipv6Addr>(r#type, v),
+        Cell::Bytes(bytes) if bytes.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Ok(Ipv6Addr::from(octets))
+        }
+        _ => Err(mismatch(r#type, value)),
+    }
+}
@@ -1,14 +1,23 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
 
 use protofish::{
-    context::{MessageField, MessageInfo, ValueType},
-    prelude::{Context, MessageValue},
+    context::{MessageField, MessageInfo, Multiplicity, ValueType},
+    prelude::{Context, FieldValue, MessageValue, Value},
 };
 
 use crate::{
-    clickhouse_table::{Table, TableColumn},
+    cell::Cell,
+    clickhouse_table::{Row, Table, TableColumn},
+    clickhouse_type::ColumnType,
+    coercion::{check_enum, check_field, enum_cell, message_tuple},
+    config::{BytesEncoding, FieldPath, Mapping, UnknownEnum},
+    converter::Converters,
     error::Error,
-    protobuf::value_to_string,
+    expression::{Expression, Scope},
+    key::{element_at, element_to_cell, unpack_key},
+    protobuf::{any_type, is_wrapper, proto_default, unwrap_any, value_to_cell, ANY},
 };
 
 use crate::result::Result;
@@ -16,11 +25,24 @@ use tracing::*;
 
 pub struct MessageBinding<'a> {
     pub r#type: &'a MessageInfo,
+    pub converters: &'a Converters,
     pub table: Table,
     pub message_mappings: HashMap<usize, PreparedMessageField<'a>>,
+    pub expressions: HashMap<usize, Expression<'a>>,
+    pub key_separator: String,
+    /// Index paths of the key tuple elements bound to columns.
+    pub key_elements: HashMap<usize, Vec<usize>>,
+    /// Prefix of the directory holding the keys, before their tuple elements.
+    pub key_prefix: Vec<u8>,
 }
 
-pub fn bind_proto_message(message: &MessageInfo, table: Table) -> Result<MessageBinding> {
+pub fn bind_proto_message<'a>(
+    ctx: &'a Context,
+    converters: &'a Converters,
+    message: &'a MessageInfo,
+    table: Table,
+    mapping: &Mapping,
+) -> Result<MessageBinding<'a>> {
     info!(
         "binding {} to {}. num columns: {}",
         &message.full_name,
@@ -28,103 +50,673 @@ pub fn bind_proto_message(message: &MessageInfo, table: Table) -> Result<Message
         table.columns.len()
     );
 
+    let key_columns = match &mapping.key {
+        Some(key) => key.columns.keys().collect(),
+        None => vec![],
+    };
+
+    for name in mapping
+        .columns
+        .keys()
+        .chain(mapping.expressions.keys())
+        .chain(key_columns.iter().cloned())
+    {
+        if !table.columns.iter().any(|c| &c.name == name) {
+            return Err(Error::InvalidMappingConfig(format!(
+                "Column {} is not in table {}",
+                name, &table.parts
+            )));
+        }
+
+        let bindings = [
+            mapping.columns.contains_key(name),
+            mapping.expressions.contains_key(name),
+            key_columns.contains(&name),
+        ];
+        if bindings.iter().filter(|bound| **bound).count() > 1 {
+            return Err(Error::InvalidMappingConfig(format!(
+                "Column {} is bound more than once",
+                name
+            )));
+        }
+    }
+
+    for name in mapping.bytes.keys() {
+        if !table.columns.iter().any(|c| &c.name == name) {
+            return Err(Error::InvalidMappingConfig(format!(
+                "Column {} is not in table {}",
+                name, &table.parts
+            )));
+        }
+    }
+
     let mut column_fields: HashMap<usize, PreparedMessageField> = HashMap::new();
+    let mut incompatible = vec![];
+    let mut expressions: HashMap<usize, Expression> = HashMap::new();
+    let mut key_elements: HashMap<usize, Vec<usize>> = HashMap::new();
 
     for column in &table.columns {
-        match message.iter_fields().find(|f| &f.name == &column.name) {
-            Some(field) => {
+        if let Some(expression) = mapping.expressions.get(&column.name) {
+            expressions.insert(
+                (column.position - 1) as usize,
+                Expression::parse(ctx, message, expression)?,
+            );
+            continue;
+        }
+
+        if !mapping.columns.contains_key(&column.name) {
+            if let Some(path) = mapping
+                .key
+                .as_ref()
+                .and_then(|key| key.column_path(&column.name))
+            {
+                key_elements.insert((column.position - 1) as usize, path?);
+                continue;
+            }
+        }
+
+        let path = match mapping.columns.get(&column.name) {
+            Some(path) => match mapped_field(ctx, message, path, mapping) {
+                Some(path) => Some(path),
+                None => {
+                    return Err(Error::InvalidMappingConfig(format!(
+                        "No field {} in {} for column {}",
+                        path, &message.full_name, &column.name
+                    )))
+                }
+            },
+            None => field_path(ctx, message, &column.name, &[], mapping),
+        };
+
+        match path {
+            Some((parents, field)) => {
+                let repeated = matches!(
+                    field.multiplicity,
+                    Multiplicity::Repeated | Multiplicity::RepeatedPacked
+                );
+                let array = column.column_type.element().is_some();
+                let entry = if repeated {
+                    map_entry(ctx, field)
+                } else {
+                    None
+                };
+                let bytes = mapping.bytes.get(&column.name).cloned();
+
+                let checked = check_field(ctx, converters, field, &column.column_type, bytes);
+                if let Err(reason) = checked {
+                    incompatible.push(format!(
+                        "{} to {} {}: {}",
+                        &field.name, &column.name, &column.r#type, reason
+                    ));
+                    continue;
+                }
+
+                match check_enum(ctx, field, &column.column_type, mapping.unknown_enums) {
+                    Ok(missing) if !missing.is_empty() => warn!(
+                        "Values {} of {} are not values of column {}, they are written as unknown values",
+                        missing.join(", "),
+                        &field.name,
+                        &column.name
+                    ),
+                    Ok(_) => {}
+                    Err(reason) => {
+                        incompatible.push(format!(
+                            "{} to {} {}: {}",
+                            &field.name, &column.name, &column.r#type, reason
+                        ));
+                        continue;
+                    }
+                }
+
+                // Nested messages written to Tuple columns are converted to tuples of their fields
+                let tuple = match field.field_type {
+                    ValueType::Message(msg_ref) if entry.is_none() => {
+                        let element = column.column_type.element();
+                        let element = element.as_ref().unwrap_or(&column.column_type);
+                        if matches!(element.inner(), ColumnType::Tuple(_)) {
+                            Some(ctx.resolve_message(msg_ref))
+                        } else {
+                            None
+                        }
+                    }
+                    _ => None,
+                };
+
+                // Enum values are matched against the values of the column
+                let enums = match (&field.field_type, &entry) {
+                    (_, Some((_, ValueType::Enum(_)))) | (ValueType::Enum(_), _) => {
+                        Some(mapping.unknown_enums)
+                    }
+                    _ if tuple.is_some() => Some(mapping.unknown_enums),
+                    _ => None,
+                };
+
+                // Messages wrapped in an Any are decoded with the type the mapping declares
+                let parents = parents
+                    .iter()
+                    .enumerate()
+                    .map(|(i, parent)| {
+                        let wrapped = if is_any(ctx, parent) {
+                            nested_message(ctx, &parents[..=i], mapping)
+                        } else {
+                            None
+                        };
+                        (*parent, wrapped)
+                    })
+                    .collect();
+
                 column_fields.insert(
                     (column.position - 1) as usize,
                     PreparedMessageField {
+                        converters,
+                        parents,
                         desc: field,
                         kind: field.field_type.clone(),
+                        repeated,
+                        array,
+                        entry,
+                        bytes,
+                        tuple,
+                        enums,
                         column: column.clone(),
                     },
                 );
             }
-            None => continue,
+            None => warn!(
+                "No field of {} is bound to column {}, it will be left to its default",
+                &message.full_name, &column.name
+            ),
         };
     }
 
+    if !incompatible.is_empty() {
+        return Err(Error::InvalidMappingConfig(format!(
+            "Fields of {} can't be written to {}: {}",
+            &message.full_name,
+            &table.parts,
+            incompatible.join("; ")
+        )));
+    }
+
+    let unmapped: Vec<&str> = message
+        .iter_fields()
+        .filter(|field| {
+            // A message field counts as bound when any of its fields is bound
+            !column_fields.values().any(|bound| {
+                let outermost = bound.parents.first().map_or(bound.desc, |parent| parent.0);
+                outermost.number == field.number
+            })
+        })
+        .map(|field| field.name.as_str())
+        .collect();
+
+    if !unmapped.is_empty() {
+        warn!(
+            "Fields of {} not bound to any column of {}: {}",
+            &message.full_name,
+            &table.parts,
+            unmapped.join(", ")
+        );
+    }
+
     Ok(MessageBinding {
         r#type: message,
+        converters,
         table,
         message_mappings: column_fields,
+        expressions,
+        key_separator: mapping.key_separator().to_string(),
+        key_elements,
+        key_prefix: vec![],
     })
 }
 
 impl<'a> MessageBinding<'a> {
-    pub fn prepare(&self, ctx: &Context, message: &[u8]) -> Result<BTreeMap<usize, String>> {
+    /// Indexes of the bound columns, in table order.
+    pub fn columns(&self) -> Vec<usize> {
+        let mut columns: Vec<usize> = self
+            .message_mappings
+            .keys()
+            .chain(self.expressions.keys())
+            .chain(self.key_elements.keys())
+            .cloned()
+            .collect();
+        columns.sort_unstable();
+        columns
+    }
+
+    pub fn prepare(&self, ctx: &Context, key: &[u8], message: &[u8]) -> Result<Row> {
         let data = self.r#type.decode(message, ctx);
 
-        let mut results: BTreeMap<usize, String> = BTreeMap::new();
+        self.prepare_decoded(ctx, key, &data)
+    }
+
+    /// Prepares a message like [`prepare`](Self::prepare), along with the names of
+    /// the columns whose bound field is not set in the message, and are written
+    /// with their default value.
+    pub fn prepare_with_missing(
+        &self,
+        ctx: &Context,
+        key: &[u8],
+        message: &[u8],
+    ) -> Result<(Row, Vec<&str>)> {
+        let data = self.r#type.decode(message, ctx);
+        let row = self.prepare_decoded(ctx, key, &data)?;
+
+        let mut missing: Vec<&str> = self
+            .message_mappings
+            .values()
+            .filter(|field| !field.is_set(ctx, &data))
+            .map(|field| field.column.name.as_str())
+            .collect();
+        missing.sort_unstable();
+
+        Ok((row, missing))
+    }
+
+    fn prepare_decoded(&self, ctx: &Context, key: &[u8], data: &MessageValue) -> Result<Row> {
+        let mut results: Row = BTreeMap::new();
 
         for (idx, field) in &self.message_mappings {
-            let value = match field.prepare_field_value(ctx, &data) {
-                Ok(v) => v.replace("?", "QUESTION_MARK"),
+            // Left out of the insert, for clickhouse to compute the default expression
+            let column = &field.column;
+            if column.has_default_expression() && !column.nullable && !field.is_set(ctx, data) {
+                continue;
+            }
+
+            let value = match field.prepare_field_value(ctx, data) {
+                Ok(v) => v,
                 Err(e) => {
                     if let Error::UnknownValueType = e {
                         warn!(
-                            "Skipping field with unknown value type: {}",
+                            "Writing default for field with unknown value type: {}",
                             field.desc.name
                         );
-                        continue;
+                        field.default_value(ctx, true)?
+                    } else {
+                        return Err(e);
                     }
-                    return Err(e);
                 }
             };
 
+            let value = match field.bytes {
+                Some(encoding) => value.encode_bytes(encoding),
+                None => value,
+            };
+            let value = match field.tuple {
+                Some(info) => {
+                    message_tuple(ctx, self.converters, info, &field.column.column_type, value)
+                }
+                None => value,
+            };
+            let value = match field.enums {
+                Some(unknown) => enum_cell(value, &field.column.column_type, unknown)?,
+                None => value,
+            };
+
             results.insert(idx.clone(), value);
         }
 
+        let scope = Scope {
+            key,
+            key_separator: &self.key_separator,
+            message: data,
+            converters: self.converters,
+            now: SystemTime::now(),
+        };
+
+        for (idx, expression) in &self.expressions {
+            results.insert(*idx, expression.evaluate(ctx, &scope)?);
+        }
+
+        if !self.key_elements.is_empty() {
+            let elements = unpack_key(key, &self.key_prefix)?;
+
+            for (idx, path) in &self.key_elements {
+                let column = &self.table.columns[*idx];
+                let value = match element_at(&elements, path) {
+                    Some(element) => element_to_cell(element),
+                    None if column.has_default_expression() && !column.nullable => continue,
+                    None => column.default().unwrap_or(Cell::Null),
+                };
+
+                results.insert(*idx, value);
+            }
+        }
+
         Ok(results)
     }
 }
 
 pub struct PreparedMessageField<'a> {
+    converters: &'a Converters,
+    /// Message fields holding the field, outermost first, along with the message
+    /// type of the google.protobuf.Any fields.
+    parents: Vec<(&'a MessageField, Option<&'a MessageInfo>)>,
     desc: &'a MessageField,
     kind: ValueType,
+    repeated: bool,
+    array: bool,
+    /// Key and value types of a map field.
+    entry: Option<(ValueType, ValueType)>,
+    /// Encoding of the bytes written to the column.
+    bytes: Option<BytesEncoding>,
+    /// Type of the message written as a tuple.
+    tuple: Option<&'a MessageInfo>,
+    /// What is written in place of unknown enum values, for fields holding enums.
+    enums: Option<UnknownEnum>,
     column: TableColumn,
 }
 
 impl<'a> PreparedMessageField<'a> {
-    pub fn prepare_field_value(&self, ctx: &Context, message: &MessageValue) -> Result<String> {
-        match message.fields.iter().find(|f| f.number == self.desc.number) {
-            Some(field_value) => {
-                let field_value = field_value.value.clone();
-                let value = value_to_string(ctx, &field_value)?;
-
-                Ok(match self.kind {
-                    ValueType::Message(_) => format!("'{}'", value),
-                    _ => value,
-                })
+    pub fn prepare_field_value(&self, ctx: &Context, message: &MessageValue) -> Result<Cell> {
+        // Without the messages holding the field it is written like an unset field
+        let parent_fields = self.parent_fields(ctx, message);
+        let parent_set = parent_fields.is_some();
+        let fields = parent_fields.unwrap_or_default();
+
+        let mut occurrences = fields.iter().filter(|f| f.number == self.desc.number);
+
+        if let Some((key_kind, value_kind)) = &self.entry {
+            let mut entries = vec![];
+            for field_value in occurrences {
+                let entry = match &field_value.value {
+                    Value::Message(entry) => entry,
+                    _ => return Err(Error::ParseError("Invalid map entry".into())),
+                };
+
+                // Keys and values equal to their default are left out of the entry
+                let mut key = proto_default(ctx, self.converters, key_kind);
+                let mut value = proto_default(ctx, self.converters, value_kind);
+                for entry_field in &entry.fields {
+                    match entry_field.number {
+                        1 => key = value_to_cell(ctx, self.converters, &entry_field.value)?,
+                        2 => value = value_to_cell(ctx, self.converters, &entry_field.value)?,
+                        _ => {}
+                    }
+                }
+
+                entries.push((key, value));
+            }
+
+            if self.array {
+                return Ok(Cell::Array(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| Cell::Tuple(vec![key, value]))
+                        .collect(),
+                ));
+            }
+
+            return Ok(Cell::Map(entries));
+        }
+
+        if self.repeated {
+            let mut values = vec![];
+            for field_value in occurrences {
+                let value = value_to_cell(ctx, self.converters, &field_value.value)?;
+                match (&field_value.value, value) {
+                    // A packed encoding holds any number of occurrences in one value
+                    (Value::Packed(_), Cell::Array(packed)) => values.extend(packed),
+                    (_, value) => values.push(value),
+                }
+            }
+
+            return Ok(Cell::Array(values));
+        }
+
+        match occurrences.next() {
+            // A message of a type missing from the proto files is written like an unset field
+            Some(field_value) if self.is_unknown_any(ctx, &field_value.value) => {
+                self.default_value(ctx, parent_set)
             }
-            None => match self.column.default() {
-                Some(value) => return Ok(value),
-                None => Ok(match self.kind {
-                    ValueType::Bool => "false".to_string(),
-                    ValueType::String => "''".to_string(),
-                    ValueType::Message(_) => "{}".to_string(),
-                    ValueType::Enum(_) => "''".to_string(),
-                    ValueType::Double
-                    | ValueType::Float
-                    | ValueType::Int32
-                    | ValueType::Int64
-                    | ValueType::UInt32
-                    | ValueType::UInt64
-                    | ValueType::SInt32
-                    | ValueType::SInt64
-                    | ValueType::Fixed32
-                    | ValueType::Fixed64
-                    | ValueType::SFixed32 => "0".to_string(),
-                    _ => {
-                        return Err(Error::NoProtoDefault(format!(
-                            "For '{}' in message",
-                            self.desc.name
-                        )))
+            Some(field_value) if self.array => {
+                let value = value_to_cell(ctx, self.converters, &field_value.value)?;
+                Ok(Cell::Array(vec![value]))
+            }
+            Some(field_value) => value_to_cell(ctx, self.converters, &field_value.value),
+            None if self.array => Ok(Cell::Array(vec![])),
+            None => self.default_value(ctx, parent_set),
+        }
+    }
+
+    /// Whether the value of the field is a google.protobuf.Any wrapping a message
+    /// of a type missing from the context.
+    fn is_unknown_any(&self, ctx: &Context, value: &Value) -> bool {
+        match value {
+            Value::Message(any) if is_any(ctx, self.desc) => any_type(ctx, any).is_none(),
+            _ => false,
+        }
+    }
+
+    /// Whether the message holds a value for the field.
+    pub fn is_set(&self, ctx: &Context, message: &MessageValue) -> bool {
+        match self.parent_fields(ctx, message) {
+            Some(fields) => fields.iter().any(|f| f.number == self.desc.number),
+            None => false,
+        }
+    }
+
+    /// Fields of the message holding the field, if every parent message is set.
+    /// Messages wrapped in an Any are only read when they have the bound type.
+    fn parent_fields<'m>(
+        &self,
+        ctx: &Context,
+        message: &'m MessageValue,
+    ) -> Option<Cow<'m, [FieldValue]>> {
+        let mut fields = Cow::Borrowed(&message.fields[..]);
+
+        for (parent, wrapped) in &self.parents {
+            let nested: Cow<MessageValue> = match fields {
+                Cow::Borrowed(fields) => match fields.iter().find(|f| f.number == parent.number) {
+                    Some(FieldValue {
+                        value: Value::Message(message),
+                        ..
+                    }) => Cow::Borrowed(message),
+                    _ => return None,
+                },
+                Cow::Owned(fields) => {
+                    match fields.into_iter().find(|f| f.number == parent.number) {
+                        Some(FieldValue {
+                            value: Value::Message(message),
+                            ..
+                        }) => Cow::Owned(*message),
+                        _ => return None,
                     }
-                }),
-            },
+                }
+            };
+
+            fields = match wrapped {
+                Some(wrapped) => match unwrap_any(ctx, &nested) {
+                    (_, Some((any_type, message))) if any_type.full_name == wrapped.full_name => {
+                        Cow::Owned(message.fields)
+                    }
+                    (type_url, _) => {
+                        debug!("Skipping {} wrapped in {}", type_url, &parent.name);
+                        return None;
+                    }
+                },
+                None => match nested {
+                    Cow::Borrowed(message) => Cow::Borrowed(&message.fields[..]),
+                    Cow::Owned(message) => Cow::Owned(message.fields),
+                },
+            };
+        }
+
+        Some(fields)
+    }
+
+    /// The value written when the message has no value for the field, `parent_set`
+    /// telling whether the messages holding the field are set.
+    pub fn default_value(&self, ctx: &Context, parent_set: bool) -> Result<Cell> {
+        // Fields without presence are written with their default even to Nullable columns
+        if self.column.nullable {
+            if !parent_set || self.has_presence(ctx) {
+                return Ok(Cell::Null);
+            }
+        } else if let Some(value) = self.column.default() {
+            return Ok(value);
+        }
+
+        Ok(proto_default(ctx, self.converters, &self.kind))
+    }
+
+    /// Whether a value that is not set differs from the default value: for wrappers
+    /// and `optional` fields.
+    fn has_presence(&self, ctx: &Context) -> bool {
+        if self.desc.multiplicity == Multiplicity::Optional {
+            return true;
+        }
+
+        match self.kind {
+            ValueType::Message(msg_ref) => is_wrapper(ctx.resolve_message(msg_ref)),
+            _ => false,
+        }
+    }
+}
+
+/// The field a column is bound to by the `columns` of the mapping or by name, with
+/// the fields holding it, outermost first.
+pub fn bound_field<'a>(
+    ctx: &'a Context,
+    message: &'a MessageInfo,
+    column: &str,
+    mapping: &Mapping,
+) -> Option<(Vec<&'a MessageField>, &'a MessageField)> {
+    match mapping.columns.get(column) {
+        Some(path) => mapped_field(ctx, message, path, mapping),
+        None => field_path(ctx, message, column, &[], mapping),
+    }
+}
+
+/// Finds the field bound to a column and the message fields holding it, e.g. `city`
+/// held by `address` for the column `address_city`. Field names may contain the
+/// separator themselves.
+fn field_path<'a>(
+    ctx: &'a Context,
+    message: &'a MessageInfo,
+    name: &str,
+    parents: &[&'a MessageField],
+    mapping: &Mapping,
+) -> Option<(Vec<&'a MessageField>, &'a MessageField)> {
+    if let Some(field) = message
+        .iter_fields()
+        .find(|f| mapping.column_name(&f.name) == name)
+    {
+        return Some((parents.to_vec(), field));
+    }
+
+    for field in message.iter_fields() {
+        let rest = match name
+            .strip_prefix(mapping.column_name(&field.name).as_str())
+            .and_then(|rest| rest.strip_prefix(mapping.separator()))
+        {
+            Some(rest) => rest,
+            None => continue,
+        };
+
+        let path = [parents, &[field]].concat();
+        let nested = match nested_message(ctx, &path, mapping) {
+            Some(nested) => nested,
+            None => continue,
+        };
+
+        if let Some(found) = field_path(ctx, nested, rest, &path, mapping) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+/// Finds the field of a path from the `columns` of a mapping.
+fn mapped_field<'a>(
+    ctx: &'a Context,
+    message: &'a MessageInfo,
+    path: &FieldPath,
+    mapping: &Mapping,
+) -> Option<(Vec<&'a MessageField>, &'a MessageField)> {
+    let path = match path {
+        FieldPath::Number(number) => return Some((vec![], message.get_field(*number)?)),
+        FieldPath::Path(path) => path,
+    };
+
+    let mut parents = vec![];
+    let mut message = message;
+    let mut segments = path.split('.').peekable();
+
+    while let Some(segment) = segments.next() {
+        let field = match segment.parse::<u64>() {
+            Ok(number) => message.get_field(number)?,
+            Err(_) => message.get_field_by_name(segment)?,
+        };
+
+        if segments.peek().is_none() {
+            return Some((parents, field));
+        }
+
+        parents.push(field);
+        message = nested_message(ctx, &parents, mapping)?;
+    }
+
+    None
+}
+
+/// The message holding the nested fields of the last field of a path: the type of
+/// a message field, or for a google.protobuf.Any field the type of the message it
+/// wraps according to the `any` types of the mapping.
+pub fn nested_message<'a>(
+    ctx: &'a Context,
+    path: &[&'a MessageField],
+    mapping: &Mapping,
+) -> Option<&'a MessageInfo> {
+    let field = path.last()?;
+    let nested = match field.field_type {
+        ValueType::Message(nested) if field.multiplicity != Multiplicity::Repeated => {
+            ctx.resolve_message(nested)
+        }
+        _ => return None,
+    };
+
+    if nested.full_name != ANY {
+        return Some(nested);
+    }
+
+    let names: Vec<&str> = path.iter().map(|field| field.name.as_str()).collect();
+    let wrapped = mapping.any.get(&names.join("."))?;
+
+    match ctx.get_message(wrapped) {
+        Some(wrapped) => Some(wrapped),
+        None => {
+            warn!("No message {} wrapped in {}", wrapped, names.join("."));
+            None
+        }
+    }
+}
+
+fn is_any(ctx: &Context, field: &MessageField) -> bool {
+    match field.field_type {
+        ValueType::Message(nested) => ctx.resolve_message(nested).full_name == ANY,
+        _ => false,
+    }
+}
+
+/// Returns the key and value types of a map field. Map fields are decoded as repeated
+/// messages with a `key` and a `value` field.
+pub fn map_entry(ctx: &Context, field: &MessageField) -> Option<(ValueType, ValueType)> {
+    let entry = match field.field_type {
+        ValueType::Message(entry_ref) => ctx.resolve_message(entry_ref),
+        _ => return None,
+    };
+
+    if !entry.name.ends_with("Entry") || entry.iter_fields().count() != 2 {
+        return None;
+    }
+
+    match (entry.get_field(1), entry.get_field(2)) {
+        (Some(key), Some(value)) if key.name == "key" && value.name == "value" => {
+            Some((key.field_type.clone(), value.field_type.clone()))
         }
+        _ => None,
     }
 }
@@ -1,16 +1,16 @@
 use std::collections::BTreeMap;
 
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tracing::*;
 
-use crate::{clickhouse::ClickhouseTableColumnRow, error::Error, result::Result};
-use lazy_static::lazy_static;
-
-lazy_static! {
-    static ref ENUM_REGEX: Regex = Regex::new(r"Enum(8|16)\(").unwrap();
-    static ref INT_REGEX: Regex = Regex::new(r"(U)?Int(8|16|32|64)").unwrap();
-}
+use crate::{
+    cell::Cell,
+    clickhouse::ClickhouseTableColumnRow,
+    clickhouse_format::{encode_value, sql_literal},
+    clickhouse_type::ColumnType,
+    error::Error,
+    result::Result,
+};
 
 #[derive(Clone)]
 pub struct ClickhouseTableParts {
@@ -37,6 +37,16 @@ impl ClickhouseTableParts {
             table: parts[1].to_string(),
         })
     }
+
+    /// The database and table names, quoted for use in queries.
+    pub fn quoted(&self) -> String {
+        format!("{}.{}", quote_name(&self.database), quote_name(&self.table))
+    }
+}
+
+/// A database, table or column name quoted for use in queries.
+pub fn quote_name(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -47,76 +57,46 @@ pub struct TableColumn {
     pub default_expression: String,
 
     pub nullable: bool,
-    pub _int_size: i32,
+    pub column_type: ColumnType,
 }
 
+/// Values of a prepared message by column index.
+pub type Row = BTreeMap<usize, Cell>;
+
 impl TableColumn {
-    pub fn default(&self) -> Option<String> {
+    /// The value written when a bound field has no value. Columns that are not bound
+    /// at all are left out of inserts so clickhouse applies their default expression,
+    /// as are the columns with a default expression when their field is not set.
+    pub fn default(&self) -> Option<Cell> {
         if self.nullable {
-            return Some("NULL".to_string());
-        }
-        if self.default_expression != "" {
-            return Some(self.default_expression.clone());
+            return Some(Cell::Null);
         }
-        match self.r#type.as_ref() {
-            "String" => {
-                return Some("''".to_string());
-            }
+        match self.column_type {
+            ColumnType::String => Some(Cell::String(String::new())),
             _ => None,
         }
     }
+
+    /// Whether clickhouse computes the value of the column when an insert leaves
+    /// it out.
+    pub fn has_default_expression(&self) -> bool {
+        !self.default_expression.is_empty()
+    }
 }
 
 impl TryFrom<ClickhouseTableColumnRow> for TableColumn {
     type Error = crate::error::Error;
 
     fn try_from(value: ClickhouseTableColumnRow) -> Result<Self> {
-        let nullable = value.name.starts_with("Nullable(");
-
-        let mut int_size = 0;
-
-        let matches: Vec<regex::Captures> = INT_REGEX.captures_iter(&value.name).collect();
-        if matches.len() == 1 {
-            match matches[0].get(2) {
-                Some(entry) => {
-                    int_size = match entry.as_str().parse::<i32>() {
-                        Ok(i) => i,
-                        Err(_e) => return Err(Error::ParseError("Invalid integer prefix".into())),
-                    };
-                    entry
-                }
-                None => return Err(Error::ParseError("Unable to parse integer type".into())),
-            };
-
-            if let Some(delimiter) = matches[0].get(1) {
-                if delimiter.as_str() == "U" {
-                    int_size = int_size * -1;
-                }
-            }
-        }
-
-        let matches: Vec<regex::Captures> = ENUM_REGEX.captures_iter(&value.name).collect();
-        if matches.len() == 1 {
-            match matches[0].get(1) {
-                Some(entry) => {
-                    int_size = match entry.as_str().parse::<i32>() {
-                        Ok(i) => i,
-                        Err(_e) => return Err(Error::ParseError("Invalid integer prefix".into())),
-                    };
-                }
-                None => return Err(Error::ParseError("Unable to parse integer type".into())),
-            };
-
-            int_size *= -1;
-        }
+        let column_type: ColumnType = value.r#type.parse()?;
 
         Ok(TableColumn {
             name: value.name,
             default_expression: value.default_expression,
             r#type: value.r#type,
             position: value.position,
-            nullable,
-            _int_size: int_size,
+            nullable: column_type.is_nullable(),
+            column_type,
         })
     }
 }
@@ -134,50 +114,72 @@ impl Table {
         }
     }
 
-    pub fn column_values(&self) -> Vec<String> {
-        self.columns.iter().map(|e| e.name.clone()).collect()
+    /// Names of the columns at the given indexes, quoted for use in queries.
+    pub fn column_names(&self, columns: &[usize]) -> Vec<String> {
+        columns
+            .iter()
+            .map(|i| quote_name(&self.columns[*i].name))
+            .collect()
     }
 
-    pub fn construct_query(&self, fields: BTreeMap<usize, String>) -> Result<String> {
-        let names = self.column_values();
-
-        let values = fields.values().cloned().collect::<Vec<String>>();
+    /// Whether every given column can be written in the RowBinary format.
+    pub fn has_binary_encoding(&self, columns: &[usize]) -> bool {
+        columns
+            .iter()
+            .all(|i| self.columns[*i].column_type.has_binary_encoding())
+    }
 
-        Ok(format!(
-            "INSERT INTO {} ({}) VALUES ({})",
-            self.parts.to_string(),
-            names.join(","),
-            values.join(",")
-        ))
+    pub fn insert_statement(&self, columns: &[usize], format: &str) -> String {
+        format!(
+            "INSERT INTO {} ({}) FORMAT {}",
+            self.parts.quoted(),
+            self.column_names(columns).join(","),
+            format
+        )
     }
 
-    pub fn construct_batch(&self, entries: Vec<BTreeMap<usize, String>>) -> Result<String> {
-        let names = self.column_values();
+    /// Groups rows by the given columns they are written with: columns with a
+    /// default expression are left out of the rows without a value for them, so
+    /// clickhouse computes it.
+    pub fn split_by_columns<'r>(
+        &self,
+        columns: &[usize],
+        entries: &'r [Row],
+    ) -> Vec<(Vec<usize>, Vec<&'r Row>)> {
+        let mut groups: Vec<(Vec<usize>, Vec<&'r Row>)> = vec![];
+
+        for entry in entries {
+            let written: Vec<usize> = columns
+                .iter()
+                .copied()
+                .filter(|i| entry.contains_key(i) || !self.columns[*i].has_default_expression())
+                .collect();
+
+            match groups.iter_mut().find(|(group, _)| *group == written) {
+                Some((_, rows)) => rows.push(entry),
+                None => groups.push((written, vec![entry])),
+            }
+        }
 
+        groups
+    }
+
+    /// Builds an `INSERT ... VALUES` query writing the given columns of every row.
+    pub fn construct_batch<'r>(
+        &self,
+        columns: &[usize],
+        entries: impl IntoIterator<Item = &'r Row>,
+    ) -> Result<String> {
         let mut parts: Vec<String> = vec![];
 
-        for (_idx, entry) in entries.iter().enumerate() {
+        for entry in entries {
             let mut current_part: Vec<String> = vec![];
 
-            for i in 0..self.columns.len() {
-                let column = &self.columns[i];
-
-                let value = match entry.get(&i) {
-                    Some(value) => value.clone(),
-                    None => match column.default() {
-                        Some(default) => default,
-                        None => {
-                            info!(
-                                "Discarded message, missing column value: table={} col={}",
-                                &self.parts.to_string(),
-                                &column.name
-                            );
-                            break;
-                        }
-                    },
-                };
-
-                current_part.push(value);
+            for i in columns {
+                let column = &self.columns[*i];
+                let value = self.column_value(column, *i, entry)?;
+
+                current_part.push(sql_literal(&column.column_type, value)?);
             }
 
             parts.push(format!("({})", current_part.join(",")));
@@ -185,9 +187,45 @@ impl Table {
 
         Ok(format!(
             "INSERT INTO {} ({}) VALUES {}",
-            self.parts.to_string(),
-            names.join(","),
+            self.parts.quoted(),
+            self.column_names(columns).join(","),
             parts.join(",")
         ))
     }
+
+    /// Encodes the given columns of every row in the RowBinary format.
+    pub fn encode_batch<'r>(
+        &self,
+        columns: &[usize],
+        entries: impl IntoIterator<Item = &'r Row>,
+    ) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+
+        for entry in entries {
+            for i in columns {
+                let column = &self.columns[*i];
+                let value = self.column_value(column, *i, entry)?;
+
+                if let Err(e) = encode_value(&mut buf, &column.column_type, value) {
+                    error!(
+                        "Unable to encode column value: table={} col={}",
+                        &self.parts, &column.name
+                    );
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(buf)
+    }
+
+    fn column_value<'r>(&self, column: &TableColumn, i: usize, entry: &'r Row) -> Result<&'r Cell> {
+        match entry.get(&i) {
+            Some(value) => Ok(value),
+            None => Err(Error::NoAvailableColumnBinding(format!(
+                "table={} col={}",
+                &self.parts, &column.name
+            ))),
+        }
+    }
 }
@@ -0,0 +1,584 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, result::Result};
+
+/// A column type, as clickhouse lists it in `system.columns`, e.g.
+/// `Nullable(DateTime64(3, 'UTC'))`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ColumnType {
+    Bool,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    Decimal {
+        precision: u32,
+        scale: u32,
+    },
+    String,
+    FixedString(usize),
+    Uuid,
+    Ipv4,
+    Ipv6,
+    Date,
+    Date32,
+    DateTime(Option<String>),
+    DateTime64 {
+        precision: u32,
+        timezone: Option<String>,
+    },
+    /// An `Enum8` with its values by name.
+    Enum8(Vec<(String, i64)>),
+    /// An `Enum16` with its values by name.
+    Enum16(Vec<(String, i64)>),
+    Json,
+    /// An `Object` type, e.g. `Object('json')`.
+    Object(String),
+    Nullable(Box<ColumnType>),
+    LowCardinality(Box<ColumnType>),
+    Array(Box<ColumnType>),
+    Map(Box<ColumnType>, Box<ColumnType>),
+    /// A tuple of elements, which may be named.
+    Tuple(Vec<(Option<String>, ColumnType)>),
+    /// Columns nested in an array of rows, written like an `Array` of `Tuple`s.
+    Nested(Vec<(String, ColumnType)>),
+    /// A type that is not written by the exporter, e.g. `AggregateFunction(...)`.
+    Other(String),
+}
+
+impl ColumnType {
+    /// The type of the values of the column, without `Nullable` and `LowCardinality`.
+    pub fn inner(&self) -> &ColumnType {
+        match self {
+            ColumnType::Nullable(inner) | ColumnType::LowCardinality(inner) => inner.inner(),
+            _ => self,
+        }
+    }
+
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            ColumnType::Nullable(_) => true,
+            ColumnType::LowCardinality(inner) => inner.is_nullable(),
+            _ => false,
+        }
+    }
+
+    /// The type of the elements of an `Array` column, or the tuple of the columns
+    /// of a `Nested` column.
+    pub fn element(&self) -> Option<ColumnType> {
+        match self.inner() {
+            ColumnType::Array(element) => Some(*element.clone()),
+            ColumnType::Nested(columns) => Some(ColumnType::Tuple(
+                columns
+                    .iter()
+                    .map(|(name, r#type)| (Some(name.clone()), r#type.clone()))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// The range of the values of an integer, `Bool`, `DateTime` or `Enum` type.
+    pub fn integer_range(&self) -> Option<(i128, i128)> {
+        Some(match self {
+            ColumnType::Bool => (0, 1),
+            ColumnType::UInt8 => (0, u8::MAX as i128),
+            ColumnType::UInt16 => (0, u16::MAX as i128),
+            ColumnType::UInt32 => (0, u32::MAX as i128),
+            ColumnType::UInt64 => (0, u64::MAX as i128),
+            ColumnType::Int8 => (i8::MIN as i128, i8::MAX as i128),
+            ColumnType::Int16 => (i16::MIN as i128, i16::MAX as i128),
+            ColumnType::Int32 => (i32::MIN as i128, i32::MAX as i128),
+            ColumnType::Int64 => (i64::MIN as i128, i64::MAX as i128),
+            ColumnType::DateTime(_) => (0, u32::MAX as i128),
+            ColumnType::Enum8(_) => (i8::MIN as i128, i8::MAX as i128),
+            ColumnType::Enum16(_) => (i16::MIN as i128, i16::MAX as i128),
+            _ => return None,
+        })
+    }
+
+    /// Whether the values of the type have a RowBinary encoding. `JSON` and
+    /// `Object` values are inserted as text, in `INSERT ... VALUES` queries.
+    pub fn has_binary_encoding(&self) -> bool {
+        match self {
+            ColumnType::Json | ColumnType::Object(_) | ColumnType::Other(_) => false,
+            ColumnType::Nullable(inner)
+            | ColumnType::LowCardinality(inner)
+            | ColumnType::Array(inner) => inner.has_binary_encoding(),
+            ColumnType::Map(key, value) => key.has_binary_encoding() && value.has_binary_encoding(),
+            ColumnType::Tuple(elements) => elements.iter().all(|(_, e)| e.has_binary_encoding()),
+            ColumnType::Nested(columns) => columns.iter().all(|(_, c)| c.has_binary_encoding()),
+            _ => true,
+        }
+    }
+
+    /// The values of an `Enum8` or `Enum16` type by name.
+    pub fn enum_values(&self) -> Option<&[(String, i64)]> {
+        match self {
+            ColumnType::Enum8(values) | ColumnType::Enum16(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<ColumnType> {
+        let mut parser = Parser { text, pos: 0 };
+
+        let r#type = parser.parse_type()?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("unexpected text after the type"));
+        }
+
+        Ok(r#type)
+    }
+}
+
+impl TryFrom<String> for ColumnType {
+    type Error = Error;
+
+    fn try_from(text: String) -> Result<ColumnType> {
+        text.parse()
+    }
+}
+
+impl From<ColumnType> for String {
+    fn from(r#type: ColumnType) -> String {
+        r#type.to_string()
+    }
+}
+
+struct Parser<'t> {
+    text: &'t str,
+    pos: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn parse_type(&mut self) -> Result<ColumnType> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let name = self.identifier()?;
+
+        Ok(match name {
+            "Bool" | "Boolean" => ColumnType::Bool,
+            "UInt8" => ColumnType::UInt8,
+            "UInt16" => ColumnType::UInt16,
+            "UInt32" => ColumnType::UInt32,
+            "UInt64" => ColumnType::UInt64,
+            "Int8" => ColumnType::Int8,
+            "Int16" => ColumnType::Int16,
+            "Int32" => ColumnType::Int32,
+            "Int64" => ColumnType::Int64,
+            "Float32" => ColumnType::Float32,
+            "Float64" => ColumnType::Float64,
+            "String" => ColumnType::String,
+            "UUID" => ColumnType::Uuid,
+            "IPv4" => ColumnType::Ipv4,
+            "IPv6" => ColumnType::Ipv6,
+            "Date" => ColumnType::Date,
+            "Date32" => ColumnType::Date32,
+            "JSON" => ColumnType::Json,
+
+            "Decimal" => {
+                self.expect('(')?;
+                let precision = self.number()? as u32;
+                self.expect(',')?;
+                let scale = self.number()? as u32;
+                self.expect(')')?;
+                self.decimal(precision, scale)?
+            }
+            "Decimal32" | "Decimal64" | "Decimal128" | "Decimal256" => {
+                let precision = match name {
+                    "Decimal32" => 9,
+                    "Decimal64" => 18,
+                    "Decimal128" => 38,
+                    _ => 76,
+                };
+                self.expect('(')?;
+                let scale = self.number()? as u32;
+                self.expect(')')?;
+                self.decimal(precision, scale)?
+            }
+            "FixedString" => {
+                self.expect('(')?;
+                let size = self.number()? as usize;
+                self.expect(')')?;
+                ColumnType::FixedString(size)
+            }
+            "DateTime" => {
+                let mut timezone = None;
+                if self.accept('(') {
+                    timezone = Some(self.string()?);
+                    self.expect(')')?;
+                }
+                ColumnType::DateTime(timezone)
+            }
+            "DateTime64" => {
+                self.expect('(')?;
+                let precision = self.number()? as u32;
+                if precision > 9 {
+                    return Err(self.error("the precision of a DateTime64 is at most 9"));
+                }
+                let mut timezone = None;
+                if self.accept(',') {
+                    timezone = Some(self.string()?);
+                }
+                self.expect(')')?;
+                ColumnType::DateTime64 {
+                    precision,
+                    timezone,
+                }
+            }
+            "Enum8" | "Enum16" => {
+                let mut values = vec![];
+                self.expect('(')?;
+                loop {
+                    let name = self.string()?;
+                    self.expect('=')?;
+                    values.push((name, self.signed_number()?));
+                    if !self.accept(',') {
+                        break;
+                    }
+                }
+                self.expect(')')?;
+
+                if name == "Enum8" {
+                    ColumnType::Enum8(values)
+                } else {
+                    ColumnType::Enum16(values)
+                }
+            }
+            "Object" => {
+                self.expect('(')?;
+                let schema = self.string()?;
+                self.expect(')')?;
+                ColumnType::Object(schema)
+            }
+            "Nullable" | "LowCardinality" | "Array" => {
+                self.expect('(')?;
+                let inner = Box::new(self.parse_type()?);
+                self.expect(')')?;
+
+                match name {
+                    "Nullable" => ColumnType::Nullable(inner),
+                    "LowCardinality" => ColumnType::LowCardinality(inner),
+                    _ => ColumnType::Array(inner),
+                }
+            }
+            "Map" => {
+                self.expect('(')?;
+                let key = Box::new(self.parse_type()?);
+                self.expect(',')?;
+                let value = Box::new(self.parse_type()?);
+                self.expect(')')?;
+                ColumnType::Map(key, value)
+            }
+            "Tuple" => {
+                let mut elements = vec![];
+                self.expect('(')?;
+                loop {
+                    elements.push(self.element()?);
+                    if !self.accept(',') {
+                        break;
+                    }
+                }
+                self.expect(')')?;
+                ColumnType::Tuple(elements)
+            }
+            "Nested" => {
+                let mut columns = vec![];
+                self.expect('(')?;
+                loop {
+                    match self.element()? {
+                        (Some(name), r#type) => columns.push((name, r#type)),
+                        (None, _) => return Err(self.error("the columns of Nested need a name")),
+                    }
+                    if !self.accept(',') {
+                        break;
+                    }
+                }
+                self.expect(')')?;
+                ColumnType::Nested(columns)
+            }
+
+            _ => {
+                self.skip_arguments()?;
+                ColumnType::Other(self.text[start..self.pos].to_string())
+            }
+        })
+    }
+
+    /// A tuple element, `name Type` or `Type`.
+    fn element(&mut self) -> Result<(Option<String>, ColumnType)> {
+        self.skip_whitespace();
+        let start = self.pos;
+
+        let name = if self.peek() == Some('`') {
+            Some(self.quoted('`')?)
+        } else {
+            let name = self.identifier()?.to_string();
+            self.skip_whitespace();
+            match self.peek() {
+                // Just a type, without arguments
+                Some(',') | Some(')') | Some('(') => {
+                    self.pos = start;
+                    None
+                }
+                _ => Some(name),
+            }
+        };
+
+        Ok((name, self.parse_type()?))
+    }
+
+    fn decimal(&self, precision: u32, scale: u32) -> Result<ColumnType> {
+        if precision == 0 || precision > 76 || scale > precision {
+            return Err(self.error("invalid precision or scale"));
+        }
+
+        Ok(ColumnType::Decimal { precision, scale })
+    }
+
+    fn identifier(&mut self) -> Result<&'t str> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_alphanumeric() && c != '_' {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        if self.pos == start {
+            return Err(self.error("expected a type"));
+        }
+
+        Ok(&self.text[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u64> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.pos += 1;
+        }
+
+        self.text[start..self.pos]
+            .parse()
+            .map_err(|_e| self.error("expected a number"))
+    }
+
+    fn signed_number(&mut self) -> Result<i64> {
+        self.skip_whitespace();
+        let negative = self.accept('-');
+        let number = self.number()? as i64;
+
+        Ok(if negative { -number } else { number })
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        self.quoted('\'')
+    }
+
+    /// Text quoted with `quote`, where a backslash escapes the next character.
+    fn quoted(&mut self, quote: char) -> Result<String> {
+        if !self.accept(quote) {
+            return Err(self.error("expected a quoted string"));
+        }
+
+        let mut value = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                _ if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                _ => value.push(c),
+            }
+        }
+
+        Err(self.error("unterminated string"))
+    }
+
+    /// Skips the arguments of a type that isn't parsed, if any.
+    fn skip_arguments(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.skip_whitespace();
+        if self.peek() != Some('(') {
+            self.pos = start;
+            return Ok(());
+        }
+
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '\'' | '`' => {
+                    self.quoted(c)?;
+                    continue;
+                }
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.pos += c.len_utf8();
+        }
+
+        Err(self.error("unbalanced parentheses"))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    /// Skips the whitespace clickhouse allows in types, which is ASCII only.
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn accept(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.accept(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::ParseError(format!(
+            "Invalid type {}: {} at {}",
+            self.text, reason, self.pos
+        ))
+    }
+}
+
+impl Display for ColumnType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnType::Bool => write!(f, "Bool"),
+            ColumnType::UInt8 => write!(f, "UInt8"),
+            ColumnType::UInt16 => write!(f, "UInt16"),
+            ColumnType::UInt32 => write!(f, "UInt32"),
+            ColumnType::UInt64 => write!(f, "UInt64"),
+            ColumnType::Int8 => write!(f, "Int8"),
+            ColumnType::Int16 => write!(f, "Int16"),
+            ColumnType::Int32 => write!(f, "Int32"),
+            ColumnType::Int64 => write!(f, "Int64"),
+            ColumnType::Float32 => write!(f, "Float32"),
+            ColumnType::Float64 => write!(f, "Float64"),
+            ColumnType::Decimal { precision, scale } => {
+                write!(f, "Decimal({}, {})", precision, scale)
+            }
+            ColumnType::String => write!(f, "String"),
+            ColumnType::FixedString(size) => write!(f, "FixedString({})", size),
+            ColumnType::Uuid => write!(f, "UUID"),
+            ColumnType::Ipv4 => write!(f, "IPv4"),
+            ColumnType::Ipv6 => write!(f, "IPv6"),
+            ColumnType::Date => write!(f, "Date"),
+            ColumnType::Date32 => write!(f, "Date32"),
+            ColumnType::DateTime(None) => write!(f, "DateTime"),
+            ColumnType::DateTime(Some(timezone)) => {
+                write!(f, "DateTime({})", quote(timezone, '\''))
+            }
+            ColumnType::DateTime64 {
+                precision,
+                timezone: None,
+            } => write!(f, "DateTime64({})", precision),
+            ColumnType::DateTime64 {
+                precision,
+                timezone: Some(timezone),
+            } => write!(f, "DateTime64({}, {})", precision, quote(timezone, '\'')),
+            ColumnType::Enum8(values) | ColumnType::Enum16(values) => {
+                let size = if matches!(self, ColumnType::Enum8(_)) {
+                    8
+                } else {
+                    16
+                };
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", quote(name, '\''), value))
+                    .collect();
+                write!(f, "Enum{}({})", size, values.join(", "))
+            }
+            ColumnType::Json => write!(f, "JSON"),
+            ColumnType::Object(schema) => write!(f, "Object({})", quote(schema, '\'')),
+            ColumnType::Nullable(inner) => write!(f, "Nullable({})", inner),
+            ColumnType::LowCardinality(inner) => write!(f, "LowCardinality({})", inner),
+            ColumnType::Array(element) => write!(f, "Array({})", element),
+            ColumnType::Map(key, value) => write!(f, "Map({}, {})", key, value),
+            ColumnType::Tuple(elements) => {
+                let elements: Vec<String> = elements
+                    .iter()
+                    .map(|(name, r#type)| match name {
+                        Some(name) => format!("{} {}", identifier(name), r#type),
+                        None => r#type.to_string(),
+                    })
+                    .collect();
+                write!(f, "Tuple({})", elements.join(", "))
+            }
+            ColumnType::Nested(columns) => {
+                let columns: Vec<String> = columns
+                    .iter()
+                    .map(|(name, r#type)| format!("{} {}", identifier(name), r#type))
+                    .collect();
+                write!(f, "Nested({})", columns.join(", "))
+            }
+            ColumnType::Other(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+fn quote(text: &str, quote: char) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace(quote, &format!("\\{}", quote));
+
+    format!("{}{}{}", quote, escaped, quote)
+}
+
+/// A name, quoted with backticks unless it is a plain identifier.
+fn identifier(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if plain {
+        name.to_string()
+    } else {
+        quote(name, '`')
+    }
+}
@@ -0,0 +1,530 @@
+use protofish::{
+    context::{MessageField, MessageInfo, Multiplicity, ValueType},
+    prelude::Context,
+};
+
+use crate::{
+    cell::Cell,
+    clickhouse_message_binding::map_entry,
+    clickhouse_type::ColumnType,
+    config::{BytesEncoding, UnknownEnum},
+    converter::Converters,
+    error::Error,
+    protobuf::{is_wrapper, proto_default},
+    result::Result,
+};
+
+/// Name of the value written in place of unknown enum values by `UnknownEnum::Unknown`.
+pub const UNKNOWN_ENUM: &str = "UNKNOWN";
+
+/// Checks that the values of a field can be written to a column of the type,
+/// returning why they can't. Values are still checked against the range of the
+/// column when they are written.
+pub fn check_field(
+    ctx: &Context,
+    converters: &Converters,
+    field: &MessageField,
+    r#type: &ColumnType,
+    bytes: Option<BytesEncoding>,
+) -> std::result::Result<(), String> {
+    let column = r#type.inner();
+
+    // Anything is written to a String column, nested values as JSON
+    if text_column(column) {
+        return Ok(());
+    }
+
+    if let Some((key_kind, value_kind)) = map_entry(ctx, field) {
+        let entry_types = match (column, column.element()) {
+            (ColumnType::Map(key_type, value_type), _) => {
+                Some(((**key_type).clone(), (**value_type).clone()))
+            }
+            (_, Some(element)) => match element.inner() {
+                ColumnType::Tuple(elements) if elements.len() == 2 => {
+                    Some((elements[0].1.clone(), elements[1].1.clone()))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        return match entry_types {
+            Some((key_type, value_type)) => {
+                check_kind(ctx, converters, &key_kind, &key_type, bytes)?;
+                check_kind(ctx, converters, &value_kind, &value_type, bytes)
+            }
+            None => Err("map fields need a Map, an Array of Tuples or a String column".to_string()),
+        };
+    }
+
+    let repeated = matches!(
+        field.multiplicity,
+        Multiplicity::Repeated | Multiplicity::RepeatedPacked
+    );
+
+    match column.element() {
+        Some(element) => check_kind(ctx, converters, &field.field_type, &element, bytes),
+        None if repeated => Err("repeated fields need an Array or a String column".to_string()),
+        None => check_kind(ctx, converters, &field.field_type, column, bytes),
+    }
+}
+
+/// Checks that single values of a kind can be written to a column of the type.
+fn check_kind(
+    ctx: &Context,
+    converters: &Converters,
+    kind: &ValueType,
+    r#type: &ColumnType,
+    bytes: Option<BytesEncoding>,
+) -> std::result::Result<(), String> {
+    let column = r#type.inner();
+    if text_column(column) {
+        return Ok(());
+    }
+
+    let incompatible = || {
+        Err(format!(
+            "{} values can't be written to {}",
+            kind_name(ctx, kind),
+            column
+        ))
+    };
+
+    match kind {
+        ValueType::Message(msg_ref) => {
+            check_message(ctx, converters, ctx.resolve_message(*msg_ref), column)
+        }
+
+        ValueType::Bytes => match bytes {
+            Some(BytesEncoding::Hex | BytesEncoding::Base64) => Err(format!(
+                "bytes encoded as text can't be written to {}",
+                column
+            )),
+            _ if matches!(
+                column,
+                ColumnType::Uuid | ColumnType::Ipv4 | ColumnType::Ipv6
+            ) =>
+            {
+                Ok(())
+            }
+            _ => incompatible(),
+        },
+
+        ValueType::Double | ValueType::Float => {
+            if float_column(column) || decimal_column(column) {
+                Ok(())
+            } else {
+                incompatible()
+            }
+        }
+
+        ValueType::String => {
+            // Times are not parsed from text, e.g. ISO 8601 dates, so go to String columns
+            if matches!(
+                column,
+                ColumnType::Uuid | ColumnType::Ipv4 | ColumnType::Ipv6
+            ) || column.enum_values().is_some()
+                || decimal_column(column)
+            {
+                Ok(())
+            } else {
+                incompatible()
+            }
+        }
+
+        ValueType::Enum(enum_ref) => {
+            if column.enum_values().is_some() {
+                return Ok(());
+            }
+
+            let numbers: Vec<i128> = ctx
+                .resolve_enum(*enum_ref)
+                .iter_fields()
+                .map(|field| field.value as i128)
+                .collect();
+            match (numbers.iter().min(), numbers.iter().max()) {
+                (Some(min), Some(max)) => fits(ctx, kind, (*min, *max), column),
+                _ => fits(ctx, kind, (0, 0), column),
+            }
+        }
+
+        _ => {
+            if float_column(column)
+                || decimal_column(column)
+                || time_column(column)
+                || column.enum_values().is_some()
+            {
+                return Ok(());
+            }
+
+            match kind_range(kind) {
+                Some(range) => fits(ctx, kind, range, column),
+                None => incompatible(),
+            }
+        }
+    }
+}
+
+/// Checks a message written to a column that is not a String column.
+fn check_message(
+    ctx: &Context,
+    converters: &Converters,
+    info: &MessageInfo,
+    column: &ColumnType,
+) -> std::result::Result<(), String> {
+    // What a converter returns is only known once it has converted a message
+    if converters.get(&info.full_name).is_some() {
+        return Ok(());
+    }
+
+    let compatible = match info.full_name.as_str() {
+        "google.protobuf.Timestamp" | "protos.Timestamp" => {
+            time_column(column) || column.integer_range().is_some()
+        }
+        "google.protobuf.Duration" => column.integer_range().is_some() || float_column(column),
+        _ if is_wrapper(info) => {
+            return match info.get_field(1) {
+                Some(field) => check_kind(ctx, converters, &field.field_type, column, None),
+                None => Ok(()),
+            }
+        }
+        "google.protobuf.FieldMask" | "google.protobuf.ListValue" => {
+            matches!(column.element(), Some(element) if text_column(element.inner()))
+        }
+        _ => match column {
+            ColumnType::Tuple(elements) => {
+                let fields: Vec<&MessageField> = info.iter_fields().collect();
+                if fields.len() != elements.len() {
+                    return Err(format!(
+                        "{} has {} fields for the {} elements of {}",
+                        info.full_name,
+                        fields.len(),
+                        elements.len(),
+                        column
+                    ));
+                }
+
+                for (field, (_, element)) in fields.iter().zip(elements) {
+                    check_field(ctx, converters, field, element, None).map_err(|reason| {
+                        format!("{}.{}: {}", info.full_name, field.name, reason)
+                    })?;
+                }
+
+                true
+            }
+            _ => false,
+        },
+    };
+
+    if compatible {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} messages can't be written to {}, only to String, JSON or Tuple columns",
+            info.full_name, column
+        ))
+    }
+}
+
+/// Checks that every value in `range` fits in an integer column.
+fn fits(
+    ctx: &Context,
+    kind: &ValueType,
+    range: (i128, i128),
+    column: &ColumnType,
+) -> std::result::Result<(), String> {
+    match column.integer_range() {
+        Some((min, max)) if range.0 >= min && range.1 <= max => Ok(()),
+        Some(_) => Err(format!(
+            "{} values don't fit in {}",
+            kind_name(ctx, kind),
+            column
+        )),
+        None => Err(format!(
+            "{} values can't be written to {}",
+            kind_name(ctx, kind),
+            column
+        )),
+    }
+}
+
+/// Writes the enum values of a cell by name to `String` columns and to `Enum`
+/// columns, whose value of the same name is written, and by number to other
+/// columns. Values with no name, or whose name is not a value of the `Enum`
+/// column, are replaced as `unknown` says, failing with `SkippedMessage` when the
+/// message is skipped.
+pub fn enum_cell(value: Cell, r#type: &ColumnType, unknown: UnknownEnum) -> Result<Cell> {
+    let column = r#type.inner();
+
+    match value {
+        Cell::Enum { name, number } => {
+            let known = match (column, &name) {
+                (ColumnType::Enum8(values) | ColumnType::Enum16(values), Some(name)) => values
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(name, number)| enum_value(name, *number)),
+                (_, Some(name)) if text_column(column) => Some(Cell::String(name.clone())),
+                (_, None) if text_column(column) => None,
+                _ if column.enum_values().is_none() => Some(Cell::Enum { name, number }),
+                _ => None,
+            };
+
+            match known {
+                Some(value) => Ok(value),
+                None => unknown_enum(r#type, number, unknown),
+            }
+        }
+        Cell::Array(values) => {
+            let element = match column.element() {
+                Some(element) => element,
+                None => return Ok(Cell::Array(values)),
+            };
+
+            Ok(Cell::Array(
+                values
+                    .into_iter()
+                    .map(|value| enum_cell(value, &element, unknown))
+                    .collect::<Result<Vec<Cell>>>()?,
+            ))
+        }
+        Cell::Map(entries) => {
+            let value_type = match column {
+                ColumnType::Map(_, value_type) => value_type,
+                _ => return Ok(Cell::Map(entries)),
+            };
+
+            Ok(Cell::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, enum_cell(value, value_type, unknown)?)))
+                    .collect::<Result<Vec<(Cell, Cell)>>>()?,
+            ))
+        }
+        Cell::Tuple(values) => {
+            let elements = match column {
+                ColumnType::Tuple(elements) if elements.len() == values.len() => elements,
+                _ => return Ok(Cell::Tuple(values)),
+            };
+
+            Ok(Cell::Tuple(
+                values
+                    .into_iter()
+                    .zip(elements)
+                    .map(|(value, (_, element))| enum_cell(value, element, unknown))
+                    .collect::<Result<Vec<Cell>>>()?,
+            ))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Checks that the unknown values of an enum field can be written to the `Enum`
+/// column it is bound to, returning the values of the field that are not values of
+/// the column.
+pub fn check_enum(
+    ctx: &Context,
+    field: &MessageField,
+    r#type: &ColumnType,
+    unknown: UnknownEnum,
+) -> std::result::Result<Vec<String>, String> {
+    let column = r#type.inner();
+
+    let (kind, column) = match map_entry(ctx, field) {
+        Some((_, value_kind)) => match (column, column.element()) {
+            (ColumnType::Map(_, value_type), _) => (value_kind, (**value_type).clone()),
+            (_, Some(ColumnType::Tuple(elements))) if elements.len() == 2 => {
+                (value_kind, elements[1].1.clone())
+            }
+            _ => return Ok(vec![]),
+        },
+        None => (
+            field.field_type.clone(),
+            column.element().unwrap_or_else(|| column.clone()),
+        ),
+    };
+
+    let (enum_ref, values) = match (kind, column.inner().enum_values()) {
+        (ValueType::Enum(enum_ref), Some(values)) => (enum_ref, values),
+        _ => return Ok(vec![]),
+    };
+
+    if unknown == UnknownEnum::Unknown && !values.iter().any(|(name, _)| name == UNKNOWN_ENUM) {
+        return Err(format!(
+            "unknown values are written as '{}', which is not a value of {}",
+            UNKNOWN_ENUM, column
+        ));
+    }
+
+    Ok(ctx
+        .resolve_enum(enum_ref)
+        .iter_fields()
+        .filter(|field| !values.iter().any(|(name, _)| name == &field.name))
+        .map(|field| field.name.clone())
+        .collect())
+}
+
+/// Converts a nested message to the tuple of its fields in declaration order, for
+/// a `Tuple` column or an `Array` of them. Fields that are not set hold their
+/// default value.
+pub fn message_tuple(
+    ctx: &Context,
+    converters: &Converters,
+    info: &MessageInfo,
+    r#type: &ColumnType,
+    value: Cell,
+) -> Cell {
+    let column = r#type.inner();
+
+    if let Some(element) = column.element() {
+        return match value {
+            Cell::Array(values) => Cell::Array(
+                values
+                    .into_iter()
+                    .map(|value| message_tuple(ctx, converters, info, &element, value))
+                    .collect(),
+            ),
+            value => value,
+        };
+    }
+
+    let (elements, mut fields) = match (column, value) {
+        (ColumnType::Tuple(elements), Cell::Message(fields)) => (elements, fields),
+        (_, value) => return value,
+    };
+
+    let mut tuple = vec![];
+    for (i, field) in info.iter_fields().enumerate() {
+        let repeated = matches!(
+            field.multiplicity,
+            Multiplicity::Repeated | Multiplicity::RepeatedPacked
+        );
+
+        // Each occurrence of a repeated field is a field of the message
+        let mut occurrences = vec![];
+        while let Some(position) = fields.iter().position(|(name, _)| name == &field.name) {
+            occurrences.push(fields.remove(position).1);
+        }
+
+        let value = if repeated {
+            Cell::Array(
+                occurrences
+                    .into_iter()
+                    .flat_map(|value| match value {
+                        Cell::Array(packed) => packed,
+                        value => vec![value],
+                    })
+                    .collect(),
+            )
+        } else {
+            match occurrences.pop() {
+                Some(value) => value,
+                None => proto_default(ctx, converters, &field.field_type),
+            }
+        };
+
+        tuple.push(match (&field.field_type, elements.get(i)) {
+            (ValueType::Message(msg_ref), Some((_, element))) => message_tuple(
+                ctx,
+                converters,
+                ctx.resolve_message(*msg_ref),
+                element,
+                value,
+            ),
+            _ => value,
+        });
+    }
+
+    Cell::Tuple(tuple)
+}
+
+fn unknown_enum(r#type: &ColumnType, number: i64, unknown: UnknownEnum) -> Result<Cell> {
+    let column = r#type.inner();
+
+    let value = match (unknown, column.enum_values()) {
+        (UnknownEnum::Skip, _) => {
+            return Err(Error::SkippedMessage(format!(
+                "unknown enum value {} for {}",
+                number, r#type
+            )))
+        }
+        (UnknownEnum::Number, Some(values)) => values
+            .iter()
+            .find(|(_, n)| *n == number)
+            .map(|(name, number)| enum_value(name, *number)),
+        (UnknownEnum::Number, None) => Some(Cell::Enum { name: None, number }),
+        (UnknownEnum::Unknown, Some(values)) => values
+            .iter()
+            .find(|(name, _)| name == UNKNOWN_ENUM)
+            .map(|(name, number)| enum_value(name, *number)),
+        (UnknownEnum::Unknown, None) => Some(Cell::String(UNKNOWN_ENUM.to_string())),
+        (UnknownEnum::Default, _) if r#type.is_nullable() => Some(Cell::Null),
+        (UnknownEnum::Default, Some(values)) => values
+            .first()
+            .map(|(name, number)| enum_value(name, *number)),
+        (UnknownEnum::Default, None) => Some(Cell::String(String::new())),
+    };
+
+    match value {
+        Some(value) => Ok(value),
+        None => Err(Error::ParseError(format!(
+            "Unknown enum value {} is not a value of {}",
+            number, r#type
+        ))),
+    }
+}
+
+fn enum_value(name: &str, number: i64) -> Cell {
+    Cell::Enum {
+        name: Some(name.to_string()),
+        number,
+    }
+}
+
+fn text_column(column: &ColumnType) -> bool {
+    matches!(
+        column,
+        ColumnType::String | ColumnType::Json | ColumnType::FixedString(_) | ColumnType::Object(_)
+    )
+}
+
+fn float_column(column: &ColumnType) -> bool {
+    matches!(column, ColumnType::Float32 | ColumnType::Float64)
+}
+
+fn decimal_column(column: &ColumnType) -> bool {
+    matches!(column, ColumnType::Decimal { .. })
+}
+
+fn time_column(column: &ColumnType) -> bool {
+    matches!(
+        column,
+        ColumnType::Date
+            | ColumnType::Date32
+            | ColumnType::DateTime(_)
+            | ColumnType::DateTime64 { .. }
+    )
+}
+
+/// The range of the values of an integer kind.
+fn kind_range(kind: &ValueType) -> Option<(i128, i128)> {
+    Some(match kind {
+        ValueType::Bool => (0, 1),
+        ValueType::Int32 | ValueType::SInt32 | ValueType::SFixed32 => {
+            (i32::MIN as i128, i32::MAX as i128)
+        }
+        ValueType::Int64 | ValueType::SInt64 | ValueType::SFixed64 => {
+            (i64::MIN as i128, i64::MAX as i128)
+        }
+        ValueType::UInt32 | ValueType::Fixed32 => (0, u32::MAX as i128),
+        ValueType::UInt64 | ValueType::Fixed64 => (0, u64::MAX as i128),
+        _ => return None,
+    })
+}
+
+fn kind_name(ctx: &Context, kind: &ValueType) -> String {
+    match kind {
+        ValueType::Message(msg_ref) => ctx.resolve_message(*msg_ref).full_name.clone(),
+        ValueType::Enum(enum_ref) => ctx.resolve_enum(*enum_ref).full_name.clone(),
+        kind => format!("{:?}", kind).to_lowercase(),
+    }
+}
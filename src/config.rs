@@ -1,5 +1,6 @@
-use std::{fs::File, io::Read};
+use std::{collections::HashMap, fs::File, io::Read};
 
+use crate::clickhouse::InsertFormat;
 use crate::error::Error;
 use crate::result::Result;
 use lazy_static::lazy_static;
@@ -13,6 +14,12 @@ lazy_static! {
 
 const VERSION: &str = "0.1.0";
 
+const DEFAULT_CHECKPOINT_FILE: &str = "fdb-ch-proto-export.checkpoints.json";
+
+const DEFAULT_SEPARATOR: &str = "_";
+
+const DEFAULT_KEY_SEPARATOR: &str = "/";
+
 pub fn load_config() -> Result<FdbCliConfig> {
     let config = match confy::load::<FdbCliConfig>(&CONFIGURATION_PATH.to_string()) {
         Ok(res) => {
@@ -79,12 +86,320 @@ pub fn load_config() -> Result<FdbCliConfig> {
     Ok(config)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Mapping {
-    pub from: String,
-    pub to: String,
+    // first key of the range, relative to the directory and prefix
+    #[serde(default)]
+    pub from: Option<KeySpec>,
+
+    // end of the range (excluded), relative to the directory and prefix
+    #[serde(default)]
+    pub to: Option<KeySpec>,
+
+    // prefix of the subspace holding the range, the whole subspace without from and to
+    #[serde(default)]
+    pub prefix: Option<KeySpec>,
+
+    // path of the directory holding the range in the directory layer
+    #[serde(default)]
+    pub directory: Option<Vec<String>>,
+
+    // message bound to the table, for the messages not matched by a route
+    #[serde(default)]
+    pub proto: Option<String>,
+    #[serde(default)]
+    pub table: Option<String>,
+
+    // messages of other types in the range, bound to their own tables
+    #[serde(default)]
+    pub routes: Vec<Route>,
+
+    // logs the keys of the messages matched by no route and no proto
+    #[serde(default)]
+    pub log_unmatched: bool,
+
+    // prefix of a versionstamped subspace holding the keys changed in this range
+    #[serde(default)]
+    pub changelog: Option<KeySpec>,
+
+    // separator between the field names of a column bound to a nested field
+    #[serde(default)]
+    pub separator: Option<String>,
+
+    // fields bound to columns by column name, instead of the field of the same name
+    #[serde(default)]
+    pub columns: HashMap<String, FieldPath>,
+
+    // naming convention of the column names, field names are converted to it
+    #[serde(default)]
+    pub naming: Option<Naming>,
+
+    // expressions computing the value of columns, e.g. `lower(email)`
+    #[serde(default)]
+    pub expressions: HashMap<String, String>,
+
+    // encoding of the bytes fields written to columns by column name, raw by default
+    #[serde(default)]
+    pub bytes: HashMap<String, BytesEncoding>,
+
+    // what is written in place of enum values with no name, or missing from the
+    // values of an Enum column
+    #[serde(default)]
+    pub unknown_enums: UnknownEnum,
+
+    // messages wrapped in google.protobuf.Any fields by field path, whose fields
+    // are bound to columns like the fields of nested messages
+    #[serde(default)]
+    pub any: HashMap<String, String>,
+
+    // separator between the parts of a key read by `$key_part[n]`
+    #[serde(default)]
+    pub key_separator: Option<String>,
+
+    // decodes keys with the tuple layer to bind their elements to columns
+    #[serde(default)]
+    pub key: Option<KeyTuple>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KeyTuple {
+    // names of the key elements by index, bound to the columns of the same name
+    #[serde(default)]
+    pub elements: Vec<String>,
+
+    // key elements bound to columns by column name
+    #[serde(default)]
+    pub columns: HashMap<String, ElementPath>,
+}
+
+/// A message type of a mapping and the table it is written to. Messages are
+/// routed to the first route matching their key and type, every condition set
+/// having to match.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Route {
     pub proto: String,
     pub table: String,
+
+    // regular expression matching the bytes of the key, e.g. `^\x02click\x00`
+    #[serde(default)]
+    pub key_pattern: Option<String>,
+
+    // value of an element of the key encoded with the tuple layer
+    #[serde(default)]
+    pub key_element: Option<ElementMatch>,
+
+    // type of messages wrapped in a google.protobuf.Any, by url or full name
+    #[serde(default)]
+    pub type_url: Option<String>,
+
+    // fields bound to columns by column name, as in the mapping
+    #[serde(default)]
+    pub columns: HashMap<String, FieldPath>,
+
+    // expressions computing the value of columns, as in the mapping
+    #[serde(default)]
+    pub expressions: HashMap<String, String>,
+
+    // encoding of the bytes written to columns, as in the mapping
+    #[serde(default)]
+    pub bytes: HashMap<String, BytesEncoding>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElementMatch {
+    pub element: ElementPath,
+    pub value: serde_json::Value,
+}
+
+/// Bytes of a key: a string where `\xNN` escapes a byte and `\\` a backslash,
+/// e.g. `users\xFF`, a tuple encoded from an array, e.g. `["users", 42]`, or hex
+/// digits, e.g. `{"hex": "7573657273ff"}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum KeySpec {
+    Escaped(String),
+    Tuple(Vec<serde_json::Value>),
+    Hex { hex: String },
+}
+
+/// A key element by index or by name. Elements of nested tuples are selected by
+/// index after a `.`, e.g. `location.0` or `2.0`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ElementPath {
+    Index(usize),
+    Path(String),
+}
+
+impl std::fmt::Display for ElementPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ElementPath::Index(index) => write!(f, "{}", index),
+            ElementPath::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// A field by name or number. Names of nested fields are separated by `.`, e.g.
+/// `address.city`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum FieldPath {
+    Number(u64),
+    Path(String),
+}
+
+impl std::fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FieldPath::Number(number) => write!(f, "{}", number),
+            FieldPath::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// How the bytes of a field are written to a column.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BytesEncoding {
+    Raw,
+    Hex,
+    Base64,
+}
+
+/// What is written in place of an unknown enum value: a value with no name in
+/// the proto enum, or whose name is not a value of the `Enum` column.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownEnum {
+    /// The number of the value, which has to be a value of an `Enum` column.
+    #[default]
+    Number,
+    /// The value named `UNKNOWN`.
+    Unknown,
+    /// The default of the column, the first value of an `Enum` column.
+    Default,
+    /// Nothing, the message is skipped.
+    Skip,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Naming {
+    #[serde(rename = "snake_case")]
+    SnakeCase,
+    #[serde(rename = "camelCase")]
+    CamelCase,
+}
+
+impl Naming {
+    /// Converts a field name to the naming convention.
+    pub fn apply(&self, name: &str) -> String {
+        let mut converted = String::with_capacity(name.len() + 4);
+
+        match self {
+            Naming::SnakeCase => {
+                let chars: Vec<char> = name.chars().collect();
+                for (i, &c) in chars.iter().enumerate() {
+                    // Words start at an uppercase letter after a lowercase one or a
+                    // digit, or at the last letter of an acronym, e.g. `HTTPServer`
+                    let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+                    let next_lowercase = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+                    let word_start = match previous {
+                        Some(p) if c.is_uppercase() => {
+                            p.is_lowercase()
+                                || p.is_ascii_digit()
+                                || (p.is_uppercase() && next_lowercase)
+                        }
+                        _ => false,
+                    };
+
+                    if word_start {
+                        converted.push('_');
+                    }
+                    converted.extend(c.to_lowercase());
+                }
+            }
+            Naming::CamelCase => {
+                let mut uppercase_next = false;
+                for c in name.chars() {
+                    if c == '_' && !converted.is_empty() {
+                        uppercase_next = true;
+                    } else if uppercase_next {
+                        converted.extend(c.to_uppercase());
+                        uppercase_next = false;
+                    } else {
+                        converted.push(c);
+                    }
+                }
+            }
+        }
+
+        converted
+    }
+}
+
+impl Mapping {
+    /// Identifies the mapping in the checkpoint store, by its messages and tables
+    /// and the range they are read from, e.g.
+    /// `protos.User:default.users@directory=app/users,from=["users",1]`.
+    pub fn id(&self) -> String {
+        let mut ids = vec![];
+        if let (Some(proto), Some(table)) = (&self.proto, &self.table) {
+            ids.push(format!("{}:{}", proto, table));
+        }
+        for route in &self.routes {
+            ids.push(format!("{}:{}", route.proto, route.table));
+        }
+
+        let mut range = vec![];
+        if let Some(directory) = &self.directory {
+            range.push(format!("directory={}", directory.join("/")));
+        }
+        for (name, key) in [
+            ("prefix", &self.prefix),
+            ("from", &self.from),
+            ("to", &self.to),
+        ] {
+            if let Some(key) = key {
+                let key = serde_json::to_string(key).unwrap_or_default();
+                range.push(format!("{}={}", name, key));
+            }
+        }
+
+        format!("{}@{}", ids.join(","), range.join(","))
+    }
+
+    /// The mapping of the messages of a route, binding them with the columns and
+    /// expressions of the route and the other settings of the mapping.
+    pub fn route_mapping(&self, route: &Route) -> Mapping {
+        Mapping {
+            proto: Some(route.proto.clone()),
+            table: Some(route.table.clone()),
+            routes: vec![],
+            columns: route.columns.clone(),
+            expressions: route.expressions.clone(),
+            bytes: route.bytes.clone(),
+            ..self.clone()
+        }
+    }
+
+    pub fn separator(&self) -> &str {
+        self.separator.as_deref().unwrap_or(DEFAULT_SEPARATOR)
+    }
+
+    pub fn key_separator(&self) -> &str {
+        self.key_separator
+            .as_deref()
+            .unwrap_or(DEFAULT_KEY_SEPARATOR)
+    }
+
+    /// The name of a field in the naming convention of the columns.
+    pub fn column_name(&self, field_name: &str) -> String {
+        match self.naming {
+            Some(naming) => naming.apply(field_name),
+            None => field_name.to_string(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -98,11 +413,76 @@ pub struct FdbCliConfig {
     // clickhouse url
     pub clickhouse_url: String,
 
+    // format used to insert rows into clickhouse
+    #[serde(default)]
+    pub insert_format: InsertFormat,
+
     // path to the protobuf file
     pub proto_file: Option<String>,
 
     // path to mapping proto config
     pub mapping_file: Option<String>,
+
+    // path to the export checkpoint state file
+    #[serde(default = "default_checkpoint_file")]
+    pub checkpoint_file: String,
+
+    // fdb subspace prefix to store checkpoints in instead of the state file
+    #[serde(default)]
+    pub checkpoint_subspace: Option<String>,
+
+    // conversions of messages to native values by full message name
+    #[serde(default)]
+    pub converters: HashMap<String, Conversion>,
+}
+
+/// A conversion of the fields of a message to a native value, e.g. a timestamp
+/// message to a `DateTime64`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "conversion", rename_all = "snake_case")]
+pub enum Conversion {
+    // seconds and nanoseconds since the unix epoch, to a point in time
+    #[serde(rename = "datetime")]
+    DateTime {
+        #[serde(default = "default_seconds_field")]
+        seconds: String,
+        #[serde(default = "default_nanos_field")]
+        nanos: String,
+    },
+
+    // whole units and nanoseconds of a unit, to a decimal with 9 digits of scale
+    Decimal {
+        #[serde(default = "default_units_field")]
+        units: String,
+        #[serde(default = "default_nanos_field")]
+        nanos: String,
+    },
+
+    // 16 bytes, or their text, to a UUID
+    Uuid {
+        #[serde(default = "default_value_field")]
+        field: String,
+    },
+}
+
+fn default_seconds_field() -> String {
+    "seconds".to_string()
+}
+
+fn default_nanos_field() -> String {
+    "nanos".to_string()
+}
+
+fn default_units_field() -> String {
+    "units".to_string()
+}
+
+fn default_value_field() -> String {
+    "value".to_string()
+}
+
+fn default_checkpoint_file() -> String {
+    DEFAULT_CHECKPOINT_FILE.to_string()
 }
 
 impl std::default::Default for FdbCliConfig {
@@ -113,8 +493,12 @@ impl std::default::Default for FdbCliConfig {
             version: VERSION.to_string(),
             cluster_file: String::from(path),
             clickhouse_url: "http://localhost:8083".to_string(),
+            insert_format: InsertFormat::default(),
             proto_file: None,
             mapping_file: None,
+            checkpoint_file: default_checkpoint_file(),
+            checkpoint_subspace: None,
+            converters: HashMap::new(),
         }
     }
 }
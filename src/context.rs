@@ -1,31 +1,46 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::checkpoint::CheckpointStore;
 use crate::clickhouse::Client as ClickhouseClient;
-use crate::clickhouse_message_binding::MessageBinding;
+use crate::converter::Converters;
 use crate::fdb::FdbClient;
+use crate::router::Router;
 use tracing::*;
 
-pub type Registry<'a> = HashMap<String, MessageBinding<'a>>;
+/// Routers of the messages of each mapping, by mapping id.
+pub type Registry<'a> = HashMap<String, Router<'a>>;
 
 pub struct AppContext<'a> {
     pub fdb_client: Arc<FdbClient>,
     pub ch_client: ClickhouseClient,
+    pub checkpoints: CheckpointStore,
+    /// Converters of the config, which the bindings of the registry decode with.
+    pub converters: &'a Converters,
     pub proto_registry: Registry<'a>,
 }
 
 impl<'a> AppContext<'a> {
-    pub fn new(fdb_client: Arc<FdbClient>, ch_client: ClickhouseClient) -> AppContext<'a> {
+    pub fn new(
+        fdb_client: Arc<FdbClient>,
+        ch_client: ClickhouseClient,
+        checkpoints: CheckpointStore,
+        converters: &'a Converters,
+    ) -> AppContext<'a> {
         AppContext {
             fdb_client,
             ch_client,
+            checkpoints,
+            converters,
             proto_registry: HashMap::new(),
         }
     }
 
     pub fn to_string(&self) {
-        for (key, value) in &self.proto_registry {
-            info!("{}: {}", key, value.table.parts.to_string());
+        for (key, router) in &self.proto_registry {
+            for route in &router.routes {
+                info!("{}: {}", key, route.binding.table.parts.to_string());
+            }
         }
     }
 }
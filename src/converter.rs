@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use protofish::{
+    context::{Context, MessageInfo},
+    prelude::{MessageValue, Value},
+};
+use tracing::*;
+
+use crate::{
+    cell::{uuid_text, Cell},
+    clickhouse_type::ColumnType,
+    config::Conversion,
+    error::Error,
+    result::Result,
+};
+
+/// Converts the messages of a type to a native value, in place of a nested
+/// message. Converters are registered by full message name in [`Converters`],
+/// and apply wherever a message of the type is decoded with them.
+pub trait Converter: Send + Sync {
+    /// Converts a decoded message. The message is empty when the field holding it
+    /// is not set, so the converter also provides the default value.
+    fn convert(
+        &self,
+        context: &Context,
+        info: &MessageInfo,
+        message: &MessageValue,
+    ) -> Result<Cell>;
+
+    /// The type of the column the converted values are written to, used when
+    /// generating tables. They are written to `String` columns when it is not known.
+    fn column_type(&self) -> Option<ColumnType> {
+        None
+    }
+}
+
+/// The converters of a config by full message name, passed down to wherever
+/// messages are decoded or bound to columns.
+#[derive(Default)]
+pub struct Converters(HashMap<String, Box<dyn Converter>>);
+
+impl Converters {
+    /// The conversions of the config, failing when a message or one of the fields
+    /// a conversion reads is not defined.
+    pub fn from_config(
+        context: &Context,
+        conversions: &HashMap<String, Conversion>,
+    ) -> Result<Converters> {
+        let mut converters = Converters::default();
+
+        for (message, conversion) in conversions {
+            let info = match context.get_message(message) {
+                Some(info) => info,
+                None => {
+                    return Err(Error::ParseError(format!(
+                        "Could not find message definition: {}",
+                        message
+                    )))
+                }
+            };
+
+            for field in conversion.fields() {
+                if !info.iter_fields().any(|f| f.name == field) {
+                    return Err(Error::ParseError(format!(
+                        "Message {} has no field {} to convert",
+                        message, field
+                    )));
+                }
+            }
+
+            debug!("Converting {} with {:?}", message, conversion);
+            converters.register(message, conversion.clone());
+        }
+
+        Ok(converters)
+    }
+
+    /// Registers the converter of the messages of type `message`, replacing the
+    /// previous converter of the type.
+    pub fn register(&mut self, message: &str, converter: impl Converter + 'static) {
+        self.0.insert(message.to_string(), Box::new(converter));
+    }
+
+    /// The converter of the messages of type `message`, if one is registered.
+    pub fn get(&self, message: &str) -> Option<&dyn Converter> {
+        self.0.get(message).map(|converter| converter.as_ref())
+    }
+}
+
+impl Conversion {
+    /// Names of the fields read by the conversion.
+    pub fn fields(&self) -> Vec<&str> {
+        match self {
+            Conversion::DateTime { seconds, nanos } => vec![seconds, nanos],
+            Conversion::Decimal { units, nanos } => vec![units, nanos],
+            Conversion::Uuid { field } => vec![field],
+        }
+    }
+}
+
+impl Converter for Conversion {
+    fn convert(
+        &self,
+        _context: &Context,
+        info: &MessageInfo,
+        message: &MessageValue,
+    ) -> Result<Cell> {
+        Ok(match self {
+            Conversion::DateTime { seconds, nanos } => Cell::DateTime {
+                seconds: integer_field(info, message, seconds)?,
+                nanos: u32::try_from(integer_field(info, message, nanos)?)
+                    .map_err(|_e| out_of_range(info, nanos))?,
+            },
+            Conversion::Decimal { units, nanos } => Cell::Decimal {
+                value: integer_field(info, message, units)? as i128 * 1_000_000_000
+                    + integer_field(info, message, nanos)? as i128,
+                scale: 9,
+            },
+            Conversion::Uuid { field } => match field_value(info, message, field)? {
+                Some(Value::Bytes(bytes)) if bytes.len() == 16 => {
+                    let mut uuid = [0; 16];
+                    uuid.copy_from_slice(bytes);
+                    Cell::String(uuid_text(&uuid))
+                }
+                Some(Value::String(text)) => Cell::String(text.clone()),
+                None => Cell::String(uuid_text(&[0; 16])),
+                Some(value) => {
+                    return Err(Error::ParseError(format!(
+                        "Invalid UUID in {}.{}: {:?}",
+                        info.full_name, field, value
+                    )))
+                }
+            },
+        })
+    }
+
+    fn column_type(&self) -> Option<ColumnType> {
+        Some(match self {
+            Conversion::DateTime { .. } => ColumnType::DateTime64 {
+                precision: 9,
+                timezone: None,
+            },
+            // Whole units of an int64 with 9 digits of nanos
+            Conversion::Decimal { .. } => ColumnType::Decimal {
+                precision: 38,
+                scale: 9,
+            },
+            Conversion::Uuid { .. } => ColumnType::Uuid,
+        })
+    }
+}
+
+/// The last value of the field named `name` in the message.
+fn field_value<'m>(
+    info: &MessageInfo,
+    message: &'m MessageValue,
+    name: &str,
+) -> Result<Option<&'m Value>> {
+    let number = match info.iter_fields().find(|field| field.name == name) {
+        Some(field) => field.number,
+        None => {
+            return Err(Error::ParseError(format!(
+                "Message {} has no field {} to convert",
+                info.full_name, name
+            )))
+        }
+    };
+
+    Ok(message
+        .fields
+        .iter()
+        .rev()
+        .find(|field| field.number == number)
+        .map(|field| &field.value))
+}
+
+fn integer_field(info: &MessageInfo, message: &MessageValue, name: &str) -> Result<i64> {
+    Ok(match field_value(info, message, name)? {
+        None => 0,
+        Some(Value::Int32(v) | Value::SInt32(v) | Value::SFixed32(v)) => *v as i64,
+        Some(Value::Int64(v) | Value::SInt64(v) | Value::SFixed64(v)) => *v,
+        Some(Value::UInt32(v) | Value::Fixed32(v)) => *v as i64,
+        Some(Value::UInt64(v) | Value::Fixed64(v)) => {
+            i64::try_from(*v).map_err(|_e| out_of_range(info, name))?
+        }
+        Some(value) => {
+            return Err(Error::ParseError(format!(
+                "Invalid integer in {}.{}: {:?}",
+                info.full_name, name, value
+            )))
+        }
+    })
+}
+
+fn out_of_range(info: &MessageInfo, name: &str) -> Error {
+    Error::ParseError(format!(
+        "Value of {}.{} is out of range to convert",
+        info.full_name, name
+    ))
+}
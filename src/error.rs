@@ -8,16 +8,24 @@ pub enum Error {
     ProtofishParseError(protofish::context::ParseError),
     Fdb(FdbError),
     Elapsed(tokio::time::error::Elapsed),
+    Task(tokio::task::JoinError),
     UnableToReadConfig(std::io::Error),
     UnableToWriteConfig(std::io::Error),
+    UnableToWriteCheckpoint(std::io::Error),
     InvalidMappingConfig(String),
     Clickhouse(Arc<clickhouse::error::Error>),
+    ClickhouseInsert(String),
+    ClickhouseQuery(String),
     ParseError(String),
     StringDecodeError(std::string::FromUtf8Error),
     NoAvailableColumnBinding(String),
     NoProtoDefault(String),
     MissingConfig(String),
+    DirectoryNotFound(String),
+    ExportFailed(usize),
     UnknownValueType,
+    SkippedMessage(String),
+    StaleCheckpoint(String),
 }
 
 impl std::fmt::Display for Error {
@@ -27,16 +35,22 @@ impl std::fmt::Display for Error {
             Error::ProtofishParseError(ref e) => write!(f, "Protofish parse error: {}", e),
             Error::Fdb(ref e) => write!(f, "Fdb error: {}", e),
             Error::Elapsed(ref e) => write!(f, "Tokio timeout elapsed error: {}", e),
+            Error::Task(ref e) => write!(f, "Tokio task error: {}", e),
             Error::UnableToReadConfig(ref err) => {
                 write!(f, "Unable to read configuration: {}", err)
             }
             Error::UnableToWriteConfig(ref err) => {
                 write!(f, "Unable to write configuration: {}", err)
             }
+            Error::UnableToWriteCheckpoint(ref err) => {
+                write!(f, "Unable to write checkpoint: {}", err)
+            }
             Error::InvalidMappingConfig(ref err) => {
                 write!(f, "Invalid mapping configuration: {}", err)
             }
             Error::Clickhouse(ref e) => write!(f, "Clickhouse error: {:?}", e),
+            Error::ClickhouseInsert(ref e) => write!(f, "Clickhouse insert error: {}", e),
+            Error::ClickhouseQuery(ref e) => write!(f, "Clickhouse query error: {}", e),
             Error::ParseError(ref e) => write!(f, "Unable to parse: {:?}", e),
             Error::StringDecodeError(ref e) => write!(f, "String decode error: {}", e),
             Error::NoAvailableColumnBinding(ref e) => {
@@ -46,7 +60,41 @@ impl std::fmt::Display for Error {
                 write!(f, "Could not find field or produce default: {:?}", e)
             }
             Error::MissingConfig(ref e) => write!(f, "Could not find config: {:?}", e),
+            Error::DirectoryNotFound(ref path) => write!(f, "Directory not found: {}", path),
+            Error::ExportFailed(count) => write!(f, "{} mappings failed to export", count),
             Error::UnknownValueType => write!(f, "Unknown value type"),
+            Error::SkippedMessage(ref reason) => write!(f, "Skipped message: {}", reason),
+            Error::StaleCheckpoint(ref e) => write!(f, "Stale checkpoint: {}", e),
+        }
+    }
+}
+
+impl Error {
+    /// Name of the kind of error, without its details.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::UnableToReadProtobuf(_) => "UnableToReadProtobuf",
+            Error::ProtofishParseError(_) => "ProtofishParseError",
+            Error::Fdb(_) => "Fdb",
+            Error::Elapsed(_) => "Elapsed",
+            Error::Task(_) => "Task",
+            Error::UnableToReadConfig(_) => "UnableToReadConfig",
+            Error::UnableToWriteConfig(_) => "UnableToWriteConfig",
+            Error::UnableToWriteCheckpoint(_) => "UnableToWriteCheckpoint",
+            Error::InvalidMappingConfig(_) => "InvalidMappingConfig",
+            Error::Clickhouse(_) => "Clickhouse",
+            Error::ClickhouseInsert(_) => "ClickhouseInsert",
+            Error::ClickhouseQuery(_) => "ClickhouseQuery",
+            Error::ParseError(_) => "ParseError",
+            Error::StringDecodeError(_) => "StringDecodeError",
+            Error::NoAvailableColumnBinding(_) => "NoAvailableColumnBinding",
+            Error::NoProtoDefault(_) => "NoProtoDefault",
+            Error::MissingConfig(_) => "MissingConfig",
+            Error::DirectoryNotFound(_) => "DirectoryNotFound",
+            Error::ExportFailed(_) => "ExportFailed",
+            Error::UnknownValueType => "UnknownValueType",
+            Error::SkippedMessage(_) => "SkippedMessage",
+            Error::StaleCheckpoint(_) => "StaleCheckpoint",
         }
     }
 }
@@ -69,6 +117,12 @@ impl From<tokio::time::error::Elapsed> for Error {
     }
 }
 
+impl From<tokio::task::JoinError> for Error {
+    fn from(err: tokio::task::JoinError) -> Error {
+        Error::Task(err)
+    }
+}
+
 impl From<clickhouse::error::Error> for Error {
     fn from(err: clickhouse::error::Error) -> Error {
         Error::Clickhouse(Arc::new(err))
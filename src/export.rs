@@ -0,0 +1,949 @@
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use protofish::prelude::Context;
+use tokio::task::JoinHandle;
+use tracing::*;
+
+use crate::checkpoint::{key_after, Checkpoint, CheckpointStore, PartialBatch};
+use crate::cli::Export;
+use crate::clickhouse_format::sql_literal;
+use crate::clickhouse_table::Row;
+use crate::config::Mapping;
+use crate::context::AppContext;
+use crate::error::Error;
+use crate::fdb::{is_retryable, split_by_size, KeySource};
+use crate::result::Result;
+use crate::router::Router;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Delay before the first retry of a failed read, doubled on every retry
+/// without progress up to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Number of generated rows kept for each table in a dry run.
+const DRY_RUN_SAMPLES: usize = 3;
+
+/// Messages handled while exporting.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ExportStats {
+    pub read: usize,
+    pub written: usize,
+    pub skipped: usize,
+    // messages matched by no route of the mapping
+    pub unmatched: usize,
+}
+
+impl AddAssign for ExportStats {
+    fn add_assign(&mut self, other: ExportStats) {
+        self.read += other.read;
+        self.written += other.written;
+        self.skipped += other.skipped;
+        self.unmatched += other.unmatched;
+    }
+}
+
+/// What a dry run found decoding the messages of a mapping, in place of writing them.
+#[derive(Default, Debug, Clone)]
+pub struct DryRun {
+    /// Rows generated for each table.
+    pub rows: BTreeMap<String, usize>,
+    /// Messages that could not be turned into a row, by error kind.
+    pub failures: BTreeMap<&'static str, usize>,
+    /// Rows written with a default because the bound field was not set, by column.
+    pub missing: BTreeMap<String, usize>,
+    /// The first rows generated for each table, as column names and SQL literals.
+    pub samples: BTreeMap<String, Vec<Vec<(String, String)>>>,
+}
+
+impl AddAssign for DryRun {
+    fn add_assign(&mut self, other: DryRun) {
+        for (table, rows) in other.rows {
+            *self.rows.entry(table).or_default() += rows;
+        }
+        for (kind, count) in other.failures {
+            *self.failures.entry(kind).or_default() += count;
+        }
+        for (column, count) in other.missing {
+            *self.missing.entry(column).or_default() += count;
+        }
+        for (table, rows) in other.samples {
+            let samples = self.samples.entry(table).or_default();
+            let room = DRY_RUN_SAMPLES.saturating_sub(samples.len());
+            samples.extend(rows.into_iter().take(room));
+        }
+    }
+}
+
+pub struct MappingSummary {
+    pub mapping: String,
+    pub stats: ExportStats,
+    pub duration: Duration,
+    pub error: Option<Error>,
+    /// Findings of the mapping when exported with `--dry-run`.
+    pub dry_run: Option<DryRun>,
+}
+
+/// Exports the mappings concurrently, running at most `export.concurrency` at
+/// once. A failed mapping is reported in its summary without stopping the others,
+/// unless `export.fail_fast` is set. With `export.dry_run` the mappings are only
+/// decoded, see [`dry_run_mapping`].
+pub async fn export_mappings(
+    context: &'static AppContext<'static>,
+    proto_context: &'static Context,
+    mapping: &[Mapping],
+    export: &Export,
+) -> Result<Vec<MappingSummary>> {
+    let mut exports = futures::stream::iter(mapping)
+        .map(|map| async move {
+            let started = Instant::now();
+            let resume = export.resume && !export.dry_run;
+            let result = export_shards(
+                context,
+                proto_context,
+                map,
+                resume,
+                export.workers,
+                export.dry_run,
+            )
+            .await;
+
+            (map, result, started.elapsed())
+        })
+        .buffer_unordered(export.concurrency.max(1));
+
+    let mut summaries = vec![];
+
+    while let Some((map, result, duration)) = exports.next().await {
+        let error = match result.error {
+            // Dropping the other exports aborts their shards
+            Some(e) if export.fail_fast => return Err(e),
+            Some(e) => {
+                error!("Failed exporting {}: {}", map.id(), e);
+                Some(e)
+            }
+            None => None,
+        };
+
+        let dry_run = if export.dry_run && error.is_none() {
+            Some(result.report)
+        } else {
+            None
+        };
+
+        summaries.push(MappingSummary {
+            mapping: map.id(),
+            stats: result.stats,
+            duration,
+            error,
+            dry_run,
+        });
+    }
+
+    Ok(summaries)
+}
+
+/// Renders the summaries as a plain text table.
+pub fn summary_table(summaries: &[MappingSummary]) -> String {
+    let mut rows = vec![vec![
+        "mapping".to_string(),
+        "read".to_string(),
+        "written".to_string(),
+        "skipped".to_string(),
+        "unmatched".to_string(),
+        "duration".to_string(),
+        "status".to_string(),
+    ]];
+
+    for summary in summaries {
+        rows.push(vec![
+            summary.mapping.clone(),
+            summary.stats.read.to_string(),
+            summary.stats.written.to_string(),
+            summary.stats.skipped.to_string(),
+            summary.stats.unmatched.to_string(),
+            format!("{:.1}s", summary.duration.as_secs_f64()),
+            match &summary.error {
+                Some(e) => format!("failed: {}", e),
+                None => "ok".to_string(),
+            },
+        ]);
+    }
+
+    let mut widths = vec![0; rows[0].len()];
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Renders what the dry runs of the summaries found, one section per mapping.
+pub fn dry_run_report(summaries: &[MappingSummary]) -> String {
+    let mut lines = vec![];
+
+    for summary in summaries {
+        let report = match &summary.dry_run {
+            Some(report) => report,
+            None => continue,
+        };
+
+        lines.push(format!("{}:", summary.mapping));
+
+        lines.push("  rows:".to_string());
+        if report.rows.is_empty() {
+            lines.push("    none".to_string());
+        }
+        for (table, rows) in &report.rows {
+            lines.push(format!("    {}: {}", table, rows));
+        }
+
+        if !report.failures.is_empty() {
+            lines.push("  decode failures:".to_string());
+            for (kind, count) in &report.failures {
+                lines.push(format!("    {}: {}", kind, count));
+            }
+        }
+
+        if !report.missing.is_empty() {
+            lines.push("  missing fields:".to_string());
+            for (column, count) in &report.missing {
+                lines.push(format!("    {}: {} rows", column, count));
+            }
+        }
+
+        if !report.samples.is_empty() {
+            lines.push("  sample rows:".to_string());
+            for (table, rows) in &report.samples {
+                for row in rows {
+                    let values = row
+                        .iter()
+                        .map(|(column, value)| format!("{} = {}", column, value))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    lines.push(format!("    {}: {}", table, values));
+                }
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Exports every key in the mapping range to its bound table. The range is split
+/// into `workers` shards which are read and written concurrently, each recording
+/// its own checkpoint.
+pub async fn export_mapping(
+    context: &'static AppContext<'static>,
+    proto_context: &'static Context,
+    map: &Mapping,
+    resume: bool,
+    workers: usize,
+) -> Result<ExportStats> {
+    let export = export_shards(context, proto_context, map, resume, workers, false).await;
+
+    match export.error {
+        Some(e) => Err(e),
+        None => Ok(export.stats),
+    }
+}
+
+/// Reads and decodes every key in the mapping range like [`export_mapping`], but
+/// reports the rows that would be written instead of writing them. Checkpoints
+/// are neither read nor saved.
+pub async fn dry_run_mapping(
+    context: &'static AppContext<'static>,
+    proto_context: &'static Context,
+    map: &Mapping,
+    workers: usize,
+) -> Result<(ExportStats, DryRun)> {
+    let export = export_shards(context, proto_context, map, false, workers, true).await;
+
+    match export.error {
+        Some(e) => Err(e),
+        None => Ok((export.stats, export.report)),
+    }
+}
+
+/// What the shards of a mapping exported, including the shards that completed
+/// before one of them failed with `error`.
+#[derive(Default)]
+struct MappingExport {
+    stats: ExportStats,
+    report: DryRun,
+    error: Option<Error>,
+}
+
+/// Tasks exporting the shards of a mapping, aborted when dropped so a mapping
+/// stopped by `--fail-fast` doesn't leave its shards running.
+struct ShardTasks(Vec<JoinHandle<MappingExport>>);
+
+impl Drop for ShardTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+async fn export_shards(
+    context: &'static AppContext<'static>,
+    proto_context: &'static Context,
+    map: &Mapping,
+    resume: bool,
+    workers: usize,
+    dry_run: bool,
+) -> MappingExport {
+    let router = match context.proto_registry.get(&map.id()) {
+        Some(router) => router,
+        None => {
+            return MappingExport {
+                error: Some(Error::InvalidMappingConfig(format!(
+                    "{} has no columns to write, `fdb-ch schema generate` creates the table",
+                    map.id()
+                ))),
+                ..MappingExport::default()
+            }
+        }
+    };
+
+    let source = context.fdb_client.as_ref();
+
+    let shards = match context.fdb_client.mapping_range(map) {
+        Ok((from, to)) if dry_run => split_by_size(source, &from, &to, workers)
+            .await
+            .map(|ranges| shard_ids(&map.id(), ranges)),
+        Ok(range) => {
+            plan_shards(
+                &context.checkpoints,
+                source,
+                &map.id(),
+                range,
+                resume,
+                workers,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let shards = match shards {
+        Ok(shards) => shards,
+        Err(e) => {
+            return MappingExport {
+                error: Some(e),
+                ..MappingExport::default()
+            }
+        }
+    };
+
+    let mut tasks = ShardTasks(vec![]);
+    for (id, range) in shards {
+        tasks.0.push(tokio::spawn(async move {
+            let source = context.fdb_client.as_ref();
+
+            if dry_run {
+                let writer = DryRunWriter::new(proto_context, router);
+                let counted = CountingWriter::new(&writer);
+                let result = export_range(None, &id, range, false, source, &counted).await;
+
+                MappingExport {
+                    stats: counted.into_stats(),
+                    report: writer.into_report(),
+                    error: result.err(),
+                }
+            } else {
+                let writer = TableWriter {
+                    context,
+                    proto_context,
+                    router,
+                };
+                let counted = CountingWriter::new(&writer);
+                let checkpoints = Some(&context.checkpoints);
+                let result = export_range(checkpoints, &id, range, resume, source, &counted).await;
+
+                MappingExport {
+                    stats: counted.into_stats(),
+                    report: DryRun::default(),
+                    error: result.err(),
+                }
+            }
+        }));
+    }
+
+    let mut export = MappingExport::default();
+
+    // Wait for every shard so a failing shard doesn't leave the others running unobserved
+    for result in futures::future::join_all(tasks.0.iter_mut()).await {
+        let shard = result.unwrap_or_else(|e| MappingExport {
+            error: Some(Error::from(e)),
+            ..MappingExport::default()
+        });
+
+        export.stats += shard.stats;
+        export.report += shard.report;
+
+        if let Some(e) = shard.error {
+            error!("Failed exporting shard of {}: {}", map.id(), e);
+            export.error.get_or_insert(e);
+        }
+    }
+
+    if export.error.is_none() && dry_run {
+        info!(
+            "{} rows generated for {} without writing",
+            export.report.rows.values().sum::<usize>(),
+            map.id()
+        );
+    } else if export.error.is_none() {
+        info!("{} messages written for {}", export.stats.written, map.id());
+    }
+
+    export
+}
+
+/// Id of the checkpoint of a shard and its range.
+pub type Shard = (String, (Vec<u8>, Vec<u8>));
+
+/// Splits the range of the mapping `id` into the shards exported by `workers`,
+/// as ids of their checkpoints and ranges.
+///
+/// The ranges of the shards are checkpointed so `resume` reads the same shards,
+/// and fails when the checkpoints were saved with a different number of workers,
+/// as none of them would match.
+pub async fn plan_shards(
+    checkpoints: &CheckpointStore,
+    source: &dyn KeySource,
+    id: &str,
+    (from, to): (Vec<u8>, Vec<u8>),
+    resume: bool,
+    workers: usize,
+) -> Result<Vec<Shard>> {
+    let plan_id = format!("{}#shards", id);
+
+    let stale = |saved_workers: usize| {
+        Error::StaleCheckpoint(format!(
+            "{} was checkpointed with {} workers, resume with --workers {} or export without --resume",
+            id, saved_workers, saved_workers
+        ))
+    };
+
+    if workers <= 1 {
+        if resume {
+            if let Some(plan) = checkpoints.get(&plan_id).await? {
+                return Err(stale(plan.shards.len()));
+            }
+        } else {
+            checkpoints.clear(&plan_id).await?;
+        }
+
+        return Ok(vec![(id.to_string(), (from, to))]);
+    }
+
+    if resume {
+        match checkpoints.get(&plan_id).await? {
+            Some(plan) if plan.shards.len() == workers => {
+                return Ok(shard_ids(id, plan.shards));
+            }
+            Some(plan) => return Err(stale(plan.shards.len())),
+            None if checkpoints.get(id).await?.is_some() => return Err(stale(1)),
+            None => {}
+        }
+    } else {
+        checkpoints.clear(id).await?;
+    }
+
+    let ranges = split_by_size(source, &from, &to, workers).await?;
+
+    checkpoints
+        .save(
+            &plan_id,
+            &Checkpoint {
+                last_key: vec![],
+                messages_written: 0,
+                completed: false,
+                shards: ranges.clone(),
+                partial: None,
+            },
+        )
+        .await?;
+
+    Ok(shard_ids(id, ranges))
+}
+
+fn shard_ids(id: &str, ranges: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<Shard> {
+    if ranges.len() == 1 {
+        return ranges
+            .into_iter()
+            .map(|range| (id.to_string(), range))
+            .collect();
+    }
+
+    let shards = ranges.len();
+    ranges
+        .into_iter()
+        .enumerate()
+        .map(|(index, range)| (format!("{}#{}/{}", id, index, shards), range))
+        .collect()
+}
+
+/// Writes the messages of the batches read from a range.
+pub trait BatchWriter: Send + Sync {
+    /// Writes the messages to the tables of their routes, except the routes in
+    /// `written` that an earlier attempt at the batch wrote. Routes are added to
+    /// `written` once written, so a batch failing partway is resumed with the
+    /// routes left.
+    fn write<'a>(
+        &'a self,
+        messages: &'a [(Vec<u8>, Vec<u8>)],
+        written: &'a mut Vec<usize>,
+    ) -> BoxFuture<'a, Result<ExportStats>>;
+}
+
+/// Writes the messages to the tables of their routes.
+pub struct TableWriter<'a> {
+    pub context: &'a AppContext<'a>,
+    pub proto_context: &'a Context,
+    pub router: &'a Router<'a>,
+}
+
+impl BatchWriter for TableWriter<'_> {
+    fn write<'a>(
+        &'a self,
+        messages: &'a [(Vec<u8>, Vec<u8>)],
+        written: &'a mut Vec<usize>,
+    ) -> BoxFuture<'a, Result<ExportStats>> {
+        let messages = messages.iter().map(|(key, value)| (&key[..], &value[..]));
+
+        write_messages(
+            self.context,
+            self.proto_context,
+            self.router,
+            messages,
+            written,
+        )
+        .boxed()
+    }
+}
+
+/// Adds up the stats of the batches written by `writer`, so a shard failing
+/// partway still reports the batches it wrote.
+struct CountingWriter<'a> {
+    writer: &'a dyn BatchWriter,
+    stats: Mutex<ExportStats>,
+}
+
+impl<'a> CountingWriter<'a> {
+    fn new(writer: &'a dyn BatchWriter) -> CountingWriter<'a> {
+        CountingWriter {
+            writer,
+            stats: Mutex::new(ExportStats::default()),
+        }
+    }
+
+    fn into_stats(self) -> ExportStats {
+        self.stats.into_inner().unwrap()
+    }
+}
+
+impl BatchWriter for CountingWriter<'_> {
+    fn write<'a>(
+        &'a self,
+        messages: &'a [(Vec<u8>, Vec<u8>)],
+        written: &'a mut Vec<usize>,
+    ) -> BoxFuture<'a, Result<ExportStats>> {
+        async move {
+            let stats = self.writer.write(messages, written).await?;
+            *self.stats.lock().unwrap() += stats;
+
+            Ok(stats)
+        }
+        .boxed()
+    }
+}
+
+/// Prepares the messages without writing them, recording what a dry run finds.
+pub struct DryRunWriter<'a> {
+    proto_context: &'a Context,
+    router: &'a Router<'a>,
+    report: Mutex<DryRun>,
+}
+
+impl<'a> DryRunWriter<'a> {
+    pub fn new(proto_context: &'a Context, router: &'a Router<'a>) -> DryRunWriter<'a> {
+        DryRunWriter {
+            proto_context,
+            router,
+            report: Mutex::new(DryRun::default()),
+        }
+    }
+
+    pub fn into_report(self) -> DryRun {
+        self.report.into_inner().unwrap()
+    }
+}
+
+impl BatchWriter for DryRunWriter<'_> {
+    fn write<'a>(
+        &'a self,
+        messages: &'a [(Vec<u8>, Vec<u8>)],
+        _written: &'a mut Vec<usize>,
+    ) -> BoxFuture<'a, Result<ExportStats>> {
+        let messages = messages.iter().map(|(key, value)| (&key[..], &value[..]));
+        let mut report = self.report.lock().unwrap();
+
+        let stats = dry_run_messages(self.proto_context, self.router, messages, &mut report);
+
+        futures::future::ready(Ok(stats)).boxed()
+    }
+}
+
+/// Exports the keys in `from..to` of a shard, restarting the read after the last
+/// written key when it fails with a retryable error, with a delay growing until
+/// the next written batch.
+///
+/// With `checkpoints` the progress of the shard is saved under `id` after every
+/// written batch, and `resume` starts after the last saved batch. Without, as in a
+/// dry run, the whole range is read.
+pub async fn export_range(
+    checkpoints: Option<&CheckpointStore>,
+    id: &str,
+    (mut from, to): (Vec<u8>, Vec<u8>),
+    resume: bool,
+    source: &dyn KeySource,
+    writer: &dyn BatchWriter,
+) -> Result<ExportStats> {
+    let mut progress = RangeProgress {
+        checkpoints,
+        id,
+        last_key: vec![],
+        messages_written: 0,
+        stats: ExportStats::default(),
+        retry_delay: RETRY_DELAY,
+        last_progress: Instant::now(),
+    };
+    let mut partial = None;
+
+    match checkpoints {
+        Some(checkpoints) if resume => match checkpoints.get(id).await? {
+            Some(checkpoint) if checkpoint.completed => {
+                info!("Skipping range as it has already been exported: {}", id);
+                return Ok(progress.stats);
+            }
+            Some(checkpoint) => {
+                info!(
+                    "Resuming {} from checkpoint ({} messages already written)",
+                    id, checkpoint.messages_written
+                );
+                from = checkpoint.resume_key();
+                progress.last_key = checkpoint.last_key;
+                progress.messages_written = checkpoint.messages_written;
+                partial = checkpoint.partial;
+            }
+            None => info!(
+                "No checkpoint found for {}, starting from the beginning",
+                id
+            ),
+        },
+        Some(checkpoints) => checkpoints.clear(id).await?,
+        None => {}
+    }
+
+    'retry: loop {
+        // A batch that failed after being written to some of its routes is read
+        // again as a whole and written to the routes left
+        if let Some(PartialBatch { last_key, routes }) = &partial {
+            let mut batch = vec![];
+            let mut batches = source.read_range(from.clone(), key_after(last_key));
+
+            while let Some(read) = batches.next().await {
+                match read {
+                    Ok(read) => batch.extend(read),
+                    Err(e) if is_retryable(&e) => {
+                        progress.back_off(&e).await;
+                        continue 'retry;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            progress
+                .write(writer, &batch, last_key.clone(), routes.clone())
+                .await?;
+
+            from = key_after(last_key);
+            partial = None;
+        }
+
+        let mut batches = source.read_range(from.clone(), to.clone());
+
+        while let Some(batch) = batches.next().await {
+            let batch = match batch {
+                Ok(batch) => batch,
+                // We restart the read after the last written key, as for a
+                // transaction too old to perform reads (1007)
+                Err(e) if is_retryable(&e) => {
+                    progress.back_off(&e).await;
+                    continue 'retry;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let last_read_key = match batch.last() {
+                Some((key, _)) => key.clone(),
+                None => continue,
+            };
+
+            progress
+                .write(writer, &batch, last_read_key.clone(), vec![])
+                .await?;
+
+            from = key_after(&last_read_key);
+        }
+
+        // We have read all the keys in this range
+        break;
+    }
+
+    progress.save(true, None).await?;
+
+    Ok(progress.stats)
+}
+
+/// Progress of the export of a range, checkpointed after every written batch.
+struct RangeProgress<'a> {
+    checkpoints: Option<&'a CheckpointStore>,
+    id: &'a str,
+    last_key: Vec<u8>,
+    messages_written: usize,
+    stats: ExportStats,
+    retry_delay: Duration,
+    last_progress: Instant,
+}
+
+impl RangeProgress<'_> {
+    /// Writes the batch ending at `batch_last_key` to the routes not `written`
+    /// yet and checkpoints it. A batch failing after being written to some of
+    /// its routes is checkpointed with those routes, so they are not written twice.
+    async fn write(
+        &mut self,
+        writer: &dyn BatchWriter,
+        batch: &[(Vec<u8>, Vec<u8>)],
+        batch_last_key: Vec<u8>,
+        mut written: Vec<usize>,
+    ) -> Result<()> {
+        let batch_stats = match writer.write(batch, &mut written).await {
+            Ok(batch_stats) => batch_stats,
+            Err(e) if written.is_empty() => return Err(e),
+            Err(e) => {
+                let partial = PartialBatch {
+                    last_key: batch_last_key,
+                    routes: written,
+                };
+                self.save(false, Some(partial)).await?;
+
+                return Err(e);
+            }
+        };
+
+        self.messages_written += batch_stats.written;
+        self.stats += batch_stats;
+        self.last_key = batch_last_key;
+        self.retry_delay = RETRY_DELAY;
+
+        // Only checkpoint once the batch is written so a resume never skips unwritten keys
+        self.save(false, None).await?;
+
+        if self.last_progress.elapsed() >= PROGRESS_INTERVAL {
+            info!(
+                "{}: {} read, {} written, {} skipped, {} unmatched",
+                self.id,
+                self.stats.read,
+                self.stats.written,
+                self.stats.skipped,
+                self.stats.unmatched
+            );
+            self.last_progress = Instant::now();
+        }
+
+        Ok(())
+    }
+
+    async fn save(&self, completed: bool, partial: Option<PartialBatch>) -> Result<()> {
+        let checkpoints = match self.checkpoints {
+            Some(checkpoints) => checkpoints,
+            None => return Ok(()),
+        };
+
+        let checkpoint = Checkpoint {
+            last_key: self.last_key.clone(),
+            messages_written: self.messages_written,
+            completed,
+            shards: vec![],
+            partial,
+        };
+
+        checkpoints.save(self.id, &checkpoint).await
+    }
+
+    /// Waits before restarting a read that failed with `error`, twice as long as
+    /// the previous time unless a batch was written since.
+    async fn back_off(&mut self, error: &Error) {
+        debug!(
+            "Retrying {} in {:?} after fdb error: {}",
+            self.id, self.retry_delay, error
+        );
+
+        tokio::time::sleep(self.retry_delay).await;
+        self.retry_delay = (self.retry_delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+/// Prepares every message with the binding of its route and writes them to the
+/// table of each route as a single batch, skipping the routes already `written`
+/// and adding the index of each route to it once written.
+pub async fn write_messages<'m>(
+    context: &AppContext<'_>,
+    proto_context: &Context,
+    router: &Router<'_>,
+    messages: impl IntoIterator<Item = (&'m [u8], &'m [u8])>,
+    written: &mut Vec<usize>,
+) -> Result<ExportStats> {
+    let (mut stats, batches) = prepare_messages(proto_context, router, messages, None);
+
+    for (index, (route, batch)) in router.routes.iter().zip(batches).enumerate() {
+        if batch.is_empty() || written.contains(&index) {
+            continue;
+        }
+
+        let binding = &route.binding;
+        context
+            .ch_client
+            .insert_batch(&binding.table, &binding.columns(), &batch)
+            .await?;
+
+        stats.written += batch.len();
+        written.push(index);
+    }
+
+    Ok(stats)
+}
+
+/// Prepares every message with the binding of its route like [`write_messages`],
+/// recording the generated rows in the report instead of writing them.
+pub fn dry_run_messages<'m>(
+    proto_context: &Context,
+    router: &Router<'_>,
+    messages: impl IntoIterator<Item = (&'m [u8], &'m [u8])>,
+    report: &mut DryRun,
+) -> ExportStats {
+    let (stats, batches) = prepare_messages(proto_context, router, messages, Some(report));
+
+    for (route, batch) in router.routes.iter().zip(batches) {
+        if batch.is_empty() {
+            continue;
+        }
+
+        let table = &route.binding.table;
+        *report.rows.entry(table.parts.to_string()).or_default() += batch.len();
+
+        let samples = report.samples.entry(table.parts.to_string()).or_default();
+        for row in batch
+            .iter()
+            .take(DRY_RUN_SAMPLES.saturating_sub(samples.len()))
+        {
+            samples.push(
+                row.iter()
+                    .map(|(idx, value)| {
+                        let column = &table.columns[*idx];
+                        let literal = sql_literal(&column.column_type, value)
+                            .unwrap_or_else(|e| format!("<{}>", e));
+                        (column.name.clone(), literal)
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    stats
+}
+
+/// Routes the messages and prepares the rows of each route, in the order of the
+/// routes. Messages that fail to prepare are skipped, and counted by error kind
+/// in the report of a dry run along with the fields missing from the rows.
+fn prepare_messages<'m>(
+    proto_context: &Context,
+    router: &Router<'_>,
+    messages: impl IntoIterator<Item = (&'m [u8], &'m [u8])>,
+    mut report: Option<&mut DryRun>,
+) -> (ExportStats, Vec<Vec<Row>>) {
+    let mut stats = ExportStats::default();
+    let mut batches: Vec<Vec<Row>> = router.routes.iter().map(|_| vec![]).collect();
+
+    for (key, value) in messages {
+        stats.read += 1;
+
+        let (index, message) = match router.route(key, value) {
+            Some(routed) => routed,
+            None => {
+                stats.unmatched += 1;
+                continue;
+            }
+        };
+
+        let binding = &router.routes[index].binding;
+
+        // Only a dry run reports the fields missing from the rows
+        let prepared = if report.is_some() {
+            binding.prepare_with_missing(proto_context, key, message)
+        } else {
+            binding
+                .prepare(proto_context, key, message)
+                .map(|fields| (fields, vec![]))
+        };
+
+        match prepared {
+            Ok((fields, missing)) => {
+                if let Some(report) = report.as_deref_mut() {
+                    for column in missing {
+                        let column = format!("{}.{}", binding.table.parts, column);
+                        *report.missing.entry(column).or_default() += 1;
+                    }
+                }
+
+                batches[index].push(fields);
+            }
+            Err(e) => {
+                match &e {
+                    Error::SkippedMessage(reason) => debug!("Skipped message: {}", reason),
+                    e => error!("Failed transforming message: {:?}", e),
+                }
+
+                if let Some(report) = report.as_deref_mut() {
+                    *report.failures.entry(e.kind()).or_default() += 1;
+                }
+                stats.skipped += 1;
+            }
+        }
+    }
+
+    (stats, batches)
+}
@@ -0,0 +1,423 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use protofish::{
+    context::{MessageField, MessageInfo, Multiplicity, ValueType},
+    prelude::{Context, MessageValue, Value},
+};
+
+use crate::{
+    cell::Cell, converter::Converters, error::Error, protobuf::value_to_cell, result::Result,
+};
+
+/// A value computed for a column from the key and the message, e.g.
+/// `coalesce(lower(email), $key_part[1])`.
+#[derive(Debug)]
+pub enum Expression<'a> {
+    /// `$key`: the key of the message.
+    Key,
+    /// `$key_part[n]`: the nth part of the key split by the key separator.
+    KeyPart(usize),
+    /// `$now`: the time the message is prepared.
+    Now,
+    Literal(Cell),
+    /// A field by path, outermost message field first.
+    Field(Vec<&'a MessageField>),
+    Call(Function, Vec<Expression<'a>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Lower,
+    Upper,
+    Trim,
+    Concat,
+    Coalesce,
+    Hash,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        Some(match name {
+            "lower" => Function::Lower,
+            "upper" => Function::Upper,
+            "trim" => Function::Trim,
+            "concat" => Function::Concat,
+            "coalesce" => Function::Coalesce,
+            "hash" => Function::Hash,
+            _ => return None,
+        })
+    }
+
+    fn accepts(&self, arguments: usize) -> bool {
+        match self {
+            Function::Lower | Function::Upper | Function::Trim | Function::Hash => arguments == 1,
+            Function::Concat | Function::Coalesce => arguments >= 1,
+        }
+    }
+}
+
+/// Values an expression is evaluated with.
+pub struct Scope<'s> {
+    pub key: &'s [u8],
+    pub key_separator: &'s str,
+    pub message: &'s MessageValue,
+    pub converters: &'s Converters,
+    pub now: SystemTime,
+}
+
+impl<'a> Expression<'a> {
+    /// Parses an expression, resolving the fields it refers to in `message`.
+    pub fn parse(ctx: &'a Context, message: &'a MessageInfo, input: &str) -> Result<Self> {
+        let mut parser = Parser {
+            ctx,
+            message,
+            input,
+            position: 0,
+        };
+
+        let expression = parser.expression()?;
+
+        parser.skip_whitespace();
+        if parser.position < input.len() {
+            return Err(parser.error("unexpected input"));
+        }
+
+        Ok(expression)
+    }
+
+    pub fn evaluate(&self, ctx: &Context, scope: &Scope) -> Result<Cell> {
+        Ok(match self {
+            Expression::Key => Cell::Bytes(scope.key.to_vec()),
+            Expression::KeyPart(index) => String::from_utf8_lossy(scope.key)
+                .split(scope.key_separator)
+                .nth(*index)
+                .map(|part| Cell::String(part.to_string()))
+                .unwrap_or(Cell::Null),
+            Expression::Now => {
+                let elapsed = scope.now.duration_since(UNIX_EPOCH).unwrap_or_default();
+                Cell::DateTime {
+                    seconds: elapsed.as_secs() as i64,
+                    nanos: elapsed.subsec_nanos(),
+                }
+            }
+            Expression::Literal(value) => value.clone(),
+            Expression::Field(path) => field_value(ctx, scope, path)?,
+            Expression::Call(function, arguments) => {
+                let mut values = vec![];
+                for argument in arguments {
+                    values.push(argument.evaluate(ctx, scope)?);
+                }
+
+                call(*function, values)?
+            }
+        })
+    }
+}
+
+/// The value of a field of the message of the scope, or `Null` if the field or a
+/// message holding it is not set.
+fn field_value(ctx: &Context, scope: &Scope, path: &[&MessageField]) -> Result<Cell> {
+    let (field, parents) = match path.split_last() {
+        Some(split) => split,
+        None => return Ok(Cell::Null),
+    };
+
+    let mut fields = &scope.message.fields[..];
+    for parent in parents {
+        fields = match fields.iter().find(|f| f.number == parent.number) {
+            Some(field_value) => match &field_value.value {
+                Value::Message(message) => &message.fields,
+                _ => return Ok(Cell::Null),
+            },
+            None => return Ok(Cell::Null),
+        };
+    }
+
+    let mut occurrences = fields.iter().filter(|f| f.number == field.number);
+
+    if field.multiplicity == Multiplicity::Repeated
+        || field.multiplicity == Multiplicity::RepeatedPacked
+    {
+        let mut values = vec![];
+        for field_value in occurrences {
+            let value = value_to_cell(ctx, scope.converters, &field_value.value)?;
+            match (&field_value.value, value) {
+                (Value::Packed(_), Cell::Array(packed)) => values.extend(packed),
+                (_, value) => values.push(value),
+            }
+        }
+
+        return Ok(Cell::Array(values));
+    }
+
+    match occurrences.next() {
+        Some(field_value) => value_to_cell(ctx, scope.converters, &field_value.value),
+        None => Ok(Cell::Null),
+    }
+}
+
+fn call(function: Function, mut values: Vec<Cell>) -> Result<Cell> {
+    if function == Function::Coalesce {
+        return Ok(values
+            .into_iter()
+            .find(|value| !value.is_null())
+            .unwrap_or(Cell::Null));
+    }
+
+    // Every other function returns NULL for a NULL argument
+    if values.iter().any(Cell::is_null) {
+        return Ok(Cell::Null);
+    }
+
+    Ok(match function {
+        Function::Lower => Cell::String(text(function, &values[0])?.to_lowercase()),
+        Function::Upper => Cell::String(text(function, &values[0])?.to_uppercase()),
+        Function::Trim => Cell::String(text(function, &values[0])?.trim().to_string()),
+        Function::Concat => {
+            let mut concatenated = String::new();
+            for value in &values {
+                concatenated.push_str(&text(function, value)?);
+            }
+            Cell::String(concatenated)
+        }
+        Function::Hash => {
+            let value = values.remove(0);
+            let bytes = match value {
+                Cell::Bytes(bytes) => bytes,
+                value => text(function, &value)?.into_bytes(),
+            };
+            Cell::UInt(fnv1a(&bytes))
+        }
+        Function::Coalesce => unreachable!(),
+    })
+}
+
+/// Text of a scalar argument of a string function.
+fn text(function: Function, value: &Cell) -> Result<String> {
+    Ok(match value {
+        Cell::String(v) => v.clone(),
+        Cell::Bytes(v) => String::from_utf8_lossy(v).to_string(),
+        Cell::Bool(v) => v.to_string(),
+        Cell::Int(v) => v.to_string(),
+        Cell::UInt(v) => v.to_string(),
+        Cell::Float(v) => v.to_string(),
+        Cell::Enum {
+            name: Some(name), ..
+        } => name.clone(),
+        Cell::Enum { number, .. } => number.to_string(),
+        _ => {
+            return Err(Error::ParseError(format!(
+                "{:?} does not accept {:?}",
+                function, value
+            )))
+        }
+    })
+}
+
+/// 64 bit FNV-1a hash, stable across runs and platforms.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+struct Parser<'a, 'i> {
+    ctx: &'a Context,
+    message: &'a MessageInfo,
+    input: &'i str,
+    position: usize,
+}
+
+impl<'a, 'i> Parser<'a, 'i> {
+    fn expression(&mut self) -> Result<Expression<'a>> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('$') => self.variable(),
+            Some('\'') => Ok(Expression::Literal(Cell::String(self.string()?))),
+            Some(c) if c.is_ascii_digit() || c == '-' => self.number(),
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.identifier(&['.']);
+
+                self.skip_whitespace();
+                if self.peek() == Some('(') {
+                    return self.call(&name);
+                }
+
+                match name.as_str() {
+                    "null" => Ok(Expression::Literal(Cell::Null)),
+                    "true" => Ok(Expression::Literal(Cell::Bool(true))),
+                    "false" => Ok(Expression::Literal(Cell::Bool(false))),
+                    _ => self.field(&name),
+                }
+            }
+            _ => Err(self.error("expected an expression")),
+        }
+    }
+
+    fn variable(&mut self) -> Result<Expression<'a>> {
+        self.position += 1;
+
+        match self.identifier(&[]).as_str() {
+            "key" => Ok(Expression::Key),
+            "now" => Ok(Expression::Now),
+            "key_part" => {
+                self.expect('[')?;
+                let start = self.position;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.position += 1;
+                }
+                let index = self.input[start..self.position]
+                    .parse::<usize>()
+                    .map_err(|_e| self.error("expected a key part index"))?;
+                self.expect(']')?;
+
+                Ok(Expression::KeyPart(index))
+            }
+            _ => Err(self.error("unknown variable")),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<Expression<'a>> {
+        let function = match Function::from_name(name) {
+            Some(function) => function,
+            None => return Err(self.error(&format!("unknown function {}", name))),
+        };
+
+        self.expect('(')?;
+
+        let mut arguments = vec![self.expression()?];
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.position += 1;
+                    arguments.push(self.expression()?);
+                }
+                Some(')') => {
+                    self.position += 1;
+                    break;
+                }
+                _ => return Err(self.error("expected , or )")),
+            }
+        }
+
+        if !function.accepts(arguments.len()) {
+            return Err(self.error(&format!(
+                "{} does not take {} arguments",
+                name,
+                arguments.len()
+            )));
+        }
+
+        Ok(Expression::Call(function, arguments))
+    }
+
+    /// Resolves a field path such as `address.city`.
+    fn field(&self, path: &str) -> Result<Expression<'a>> {
+        let mut fields: Vec<&'a MessageField> = vec![];
+        let mut message = self.message;
+
+        for name in path.split('.') {
+            if let Some(parent) = fields.last() {
+                message = match parent.field_type {
+                    ValueType::Message(nested) => self.ctx.resolve_message(nested),
+                    _ => return Err(self.error(&format!("{} is not a message field", path))),
+                };
+            }
+
+            match message.get_field_by_name(name) {
+                Some(field) => fields.push(field),
+                None => {
+                    return Err(self.error(&format!("no field {} in {}", name, &message.full_name)))
+                }
+            }
+        }
+
+        Ok(Expression::Field(fields))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('\'')?;
+
+        let mut value = String::new();
+        let mut chars = self.input[self.position..].chars();
+
+        while let Some(c) = chars.next() {
+            self.position += c.len_utf8();
+            match c {
+                '\'' => return Ok(value),
+                '\\' => match chars.next() {
+                    Some(escaped) => {
+                        self.position += escaped.len_utf8();
+                        value.push(escaped);
+                    }
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+
+        Err(self.error("unterminated string"))
+    }
+
+    fn number(&mut self) -> Result<Expression<'a>> {
+        let start = self.position;
+        self.position += 1;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.position += 1;
+        }
+
+        let number = &self.input[start..self.position];
+        if let Ok(value) = number.parse::<i64>() {
+            return Ok(Expression::Literal(Cell::Int(value)));
+        }
+        if let Ok(value) = number.parse::<f64>() {
+            return Ok(Expression::Literal(Cell::Float(value)));
+        }
+
+        Err(self.error("invalid number"))
+    }
+
+    fn identifier(&mut self, extra: &[char]) -> String {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_' || extra.contains(&c))
+        {
+            self.position += self.peek().map_or(0, char::len_utf8);
+        }
+
+        self.input[start..self.position].to_string()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(expected) {
+            self.position += 1;
+            return Ok(());
+        }
+
+        Err(self.error(&format!("expected {}", expected)))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidMappingConfig(format!(
+            "Invalid expression {}: {} at {}",
+            self.input, message, self.position
+        ))
+    }
+}
@@ -1,12 +1,32 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
+use crate::checkpoint::key_after;
+use crate::config::Mapping;
+use crate::error::Error;
 use crate::result::Result;
 use foundationdb::api::{FdbApiBuilder, NetworkAutoStop};
-use foundationdb::{Database, Transaction};
+use foundationdb::tuple::{pack, Bytes};
+use foundationdb::{Database, RangeOption, Transaction};
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, StreamExt};
+use futures::FutureExt;
 use tokio::time::timeout;
+use tracing::*;
+
+/// Prefix of the node subspace of the directory layer.
+const DIRECTORY_NODES: &[u8] = b"\xfe";
+
+/// Key of the subdirectories of a directory node.
+const SUBDIRS: i64 = 0;
+
+/// Layer of directory partitions, which hold their own node subspace.
+const PARTITION_LAYER: &[u8] = b"partition";
 
 pub struct FdbClient {
     pub db: Database,
+    // prefixes of the directories of the mappings by path
+    directories: HashMap<Vec<String>, Vec<u8>>,
 }
 
 impl FdbClient {
@@ -20,7 +40,10 @@ impl FdbClient {
 
     pub fn new(path: &str) -> Result<Self> {
         let db = Database::new(Some(path))?;
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            directories: HashMap::new(),
+        })
     }
 
     pub async fn begin_tx(&self) -> Result<Transaction> {
@@ -31,4 +54,334 @@ impl FdbClient {
             Err(e) => Err(e.into()),
         }
     }
+
+    /// Resolves the prefixes of the directories of the mappings through the
+    /// directory layer, failing when a directory does not exist.
+    pub async fn open_directories(&mut self, mappings: &[Mapping]) -> Result<()> {
+        for path in mappings.iter().filter_map(|map| map.directory.as_ref()) {
+            if self.directories.contains_key(path) {
+                continue;
+            }
+
+            let prefix = resolve_directory(&*self, path).await?;
+
+            info!("Resolved directory {} to {:?}", path.join("/"), &prefix);
+            self.directories.insert(path.clone(), prefix);
+        }
+
+        Ok(())
+    }
+
+    /// Prefix of the directory of the mapping, empty when it has none.
+    pub fn directory_prefix(&self, map: &Mapping) -> Result<&[u8]> {
+        let path = match &map.directory {
+            Some(path) => path,
+            None => return Ok(&[]),
+        };
+
+        match self.directories.get(path) {
+            Some(prefix) => Ok(prefix),
+            None => Err(Error::DirectoryNotFound(path.join("/"))),
+        }
+    }
+
+    /// The prefix of the keys of the mapping, inside its directory.
+    pub fn key_prefix(&self, map: &Mapping) -> Result<Vec<u8>> {
+        map.key_prefix(self.directory_prefix(map)?)
+    }
+
+    /// The range of keys of the mapping, inside its directory.
+    pub fn mapping_range(&self, map: &Mapping) -> Result<(Vec<u8>, Vec<u8>)> {
+        map.range(self.directory_prefix(map)?)
+    }
+}
+
+/// Keys and values read from a range, in key order.
+pub type KeyValues = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Reads the keys exported from a cluster.
+pub trait KeySource: Send + Sync {
+    /// The keys in `from..to` in batches, in key order. The stream ends after the
+    /// first error, the read being restarted by the caller when it is retryable.
+    fn read_range(&self, from: Vec<u8>, to: Vec<u8>) -> BoxStream<'_, Result<KeyValues>>;
+
+    /// The values of the keys, `None` for the keys that are not set.
+    fn get_values<'a>(&'a self, keys: &'a [Vec<u8>])
+        -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>>>;
+
+    /// The last key in `from..to`, if any.
+    fn last_key<'a>(
+        &'a self,
+        from: &'a [u8],
+        to: &'a [u8],
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+    /// Estimated size in bytes of the keys and values in the range `from..to`.
+    fn estimate_size<'a>(&'a self, from: &'a [u8], to: &'a [u8]) -> BoxFuture<'a, Result<u64>>;
+}
+
+impl KeySource for FdbClient {
+    fn read_range(&self, from: Vec<u8>, to: Vec<u8>) -> BoxStream<'_, Result<KeyValues>> {
+        // Batches grow with the iteration, as with `get_ranges`, within one transaction
+        let start = Some((None, from, 1));
+
+        futures::stream::unfold(start, move |state| {
+            let to = to.clone();
+
+            async move {
+                let (tx, from, iteration): (Option<Transaction>, Vec<u8>, usize) = state?;
+
+                let tx = match tx {
+                    Some(tx) => tx,
+                    None => match self.begin_tx().await {
+                        Ok(tx) => tx,
+                        Err(e) => return Some((Err(e), None)),
+                    },
+                };
+
+                let range = RangeOption {
+                    reverse: false,
+                    limit: None,
+                    ..RangeOption::from((from.as_slice(), to.as_slice()))
+                };
+                let values = match tx.get_range(&range, iteration, false).await {
+                    Ok(values) => values,
+                    Err(e) => return Some((Err(Error::Fdb(e)), None)),
+                };
+
+                let batch: KeyValues = values
+                    .iter()
+                    .map(|kv| (kv.key().to_vec(), kv.value().to_vec()))
+                    .collect();
+
+                let next = match batch.last() {
+                    Some((last_key, _)) if values.more() => {
+                        Some((Some(tx), key_after(last_key), iteration + 1))
+                    }
+                    _ => None,
+                };
+
+                Some((Ok(batch), next))
+            }
+        })
+        .boxed()
+    }
+
+    fn get_values<'a>(
+        &'a self,
+        keys: &'a [Vec<u8>],
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>>> {
+        async move {
+            let tx = self.begin_tx().await?;
+
+            let values =
+                futures::future::try_join_all(keys.iter().map(|key| tx.get(key, false))).await?;
+
+            Ok(values
+                .into_iter()
+                .map(|value| value.map(|value| value.to_vec()))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn last_key<'a>(
+        &'a self,
+        from: &'a [u8],
+        to: &'a [u8],
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        async move {
+            let tx = self.begin_tx().await?;
+
+            let range = RangeOption {
+                reverse: true,
+                limit: Some(1),
+                ..RangeOption::from((from, to))
+            };
+            let entries = tx.get_range(&range, 1, false).await?;
+
+            Ok(entries.iter().next().map(|entry| entry.key().to_vec()))
+        }
+        .boxed()
+    }
+
+    fn estimate_size<'a>(&'a self, from: &'a [u8], to: &'a [u8]) -> BoxFuture<'a, Result<u64>> {
+        async move {
+            let tx = self.begin_tx().await?;
+
+            let size = tx.get_estimated_range_size_bytes(from, to).await?;
+
+            Ok(size.max(0) as u64)
+        }
+        .boxed()
+    }
+}
+
+/// Whether a read failing with the error can be restarted in a new transaction,
+/// e.g. when the transaction is too old to perform reads (1007).
+pub fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::Fdb(e) => e.code() == 1007 || e.is_retryable(),
+        _ => false,
+    }
+}
+
+/// Key of the node of the directory with the given prefix, in the node subspace
+/// `nodes`.
+pub fn directory_node(nodes: &[u8], prefix: &[u8]) -> Vec<u8> {
+    [nodes, &pack(&Bytes::from(prefix))].concat()
+}
+
+/// Key holding the prefix of the subdirectory `name` of a directory node.
+pub fn subdirectory_key(node: &[u8], name: &str) -> Vec<u8> {
+    [node, &pack(&(SUBDIRS, name))].concat()
+}
+
+/// Walks the directory layer from the root directory to the directory at
+/// `path`, returning its prefix.
+pub async fn resolve_directory(source: &dyn KeySource, path: &[String]) -> Result<Vec<u8>> {
+    let mut nodes = DIRECTORY_NODES.to_vec();
+    let mut node = directory_node(&nodes, &nodes);
+    let mut prefix = vec![];
+
+    for (depth, name) in path.iter().enumerate() {
+        prefix = match get_value(source, subdirectory_key(&node, name)).await? {
+            Some(value) => value,
+            None => return Err(Error::DirectoryNotFound(path[..=depth].join("/"))),
+        };
+
+        node = directory_node(&nodes, &prefix);
+
+        // Subdirectories of a partition are allocated in the partition's own node subspace
+        let layer = get_value(source, [&node[..], &pack(&"layer")].concat()).await?;
+        if layer.as_deref() == Some(PARTITION_LAYER) {
+            nodes = [&prefix[..], DIRECTORY_NODES].concat();
+            node = directory_node(&nodes, &nodes);
+        }
+    }
+
+    Ok(prefix)
+}
+
+async fn get_value(source: &dyn KeySource, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+    let mut values = source.get_values(&[key]).await?;
+    Ok(values.pop().flatten())
+}
+
+/// Returns the first key after every key starting with `prefix`.
+pub fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+
+    vec![0xff]
+}
+
+/// Number of halvings of the range searched for each shard boundary.
+const SPLIT_SEARCH_STEPS: usize = 24;
+
+/// Splits the range `from..to` into exactly `shards` contiguous ranges of
+/// similar estimated sizes, the last ones being empty when the range is too
+/// small to split.
+///
+/// Each boundary is searched by halving the range with `split_range` until the
+/// estimated size before it reaches its share. Ranges without an estimate, such
+/// as ones too small for the cluster to sample, are split evenly.
+pub async fn split_by_size(
+    source: &dyn KeySource,
+    from: &[u8],
+    to: &[u8],
+    shards: usize,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let total = if shards > 1 && from < to {
+        source.estimate_size(from, to).await?
+    } else {
+        0
+    };
+
+    let mut ranges = if total == 0 {
+        split_range(from, to, shards)
+    } else {
+        let mut boundaries = vec![from.to_vec()];
+
+        for shard in 1..shards {
+            let target = total * shard as u64 / shards as u64;
+
+            let mut low = boundaries[shard - 1].clone();
+            let mut high = to.to_vec();
+
+            for _ in 0..SPLIT_SEARCH_STEPS {
+                let middle = match split_range(&low, &high, 2).as_slice() {
+                    [(_, middle), _] => middle.clone(),
+                    _ => break,
+                };
+
+                if source.estimate_size(from, &middle).await? < target {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+
+            boundaries.push(high);
+        }
+        boundaries.push(to.to_vec());
+
+        boundaries
+            .windows(2)
+            .map(|range| (range[0].clone(), range[1].clone()))
+            .collect()
+    };
+
+    while ranges.len() < shards {
+        ranges.push((to.to_vec(), to.to_vec()));
+    }
+
+    Ok(ranges)
+}
+
+/// Splits the range `from..to` into at most `shards` contiguous ranges.
+///
+/// Boundaries are spread evenly over the 8 bytes following the common prefix
+/// of `from` and `to`, so shards only hold similar numbers of keys when the
+/// keys are evenly distributed over that space.
+pub fn split_range(from: &[u8], to: &[u8], shards: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let whole = vec![(from.to_vec(), to.to_vec())];
+    if shards <= 1 || from >= to {
+        return whole;
+    }
+
+    let prefix_len = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+
+    let suffix = |key: &[u8]| {
+        let mut bytes = [0u8; 8];
+        for (i, byte) in key[prefix_len..].iter().take(8).enumerate() {
+            bytes[i] = *byte;
+        }
+        u64::from_be_bytes(bytes)
+    };
+
+    let start = suffix(from);
+    let step = (suffix(to) - start) / shards as u64;
+    if step == 0 {
+        return whole;
+    }
+
+    let mut boundaries = vec![from.to_vec()];
+    for i in 1..shards as u64 {
+        let mut boundary = to[..prefix_len].to_vec();
+        boundary.extend_from_slice(&(start + i * step).to_be_bytes());
+        boundaries.push(boundary);
+    }
+    boundaries.push(to.to_vec());
+
+    boundaries
+        .windows(2)
+        .map(|range| (range[0].clone(), range[1].clone()))
+        .collect()
 }
@@ -0,0 +1,256 @@
+use std::borrow::Cow;
+
+use foundationdb::tuple::{pack, unpack, Bytes, Element};
+
+use crate::{
+    cell::Cell,
+    config::{ElementPath, KeySpec, KeyTuple, Mapping},
+    error::Error,
+    fdb::prefix_end,
+    result::Result,
+};
+
+/// Unpacks a key encoded with the tuple layer, after the given raw prefix.
+pub fn unpack_key<'k>(key: &'k [u8], prefix: &[u8]) -> Result<Vec<Element<'k>>> {
+    let tuple = match key.strip_prefix(prefix) {
+        Some(tuple) => tuple,
+        None => {
+            return Err(Error::ParseError(format!(
+                "Key {:?} does not start with {:?}",
+                key, prefix
+            )))
+        }
+    };
+
+    unpack::<Vec<Element>>(tuple)
+        .map_err(|e| Error::ParseError(format!("Unable to unpack key {:?}: {}", key, e)))
+}
+
+/// Returns the element at the index path, e.g. `[2, 0]` for the first element of a
+/// tuple nested at index 2.
+pub fn element_at<'e, 'k>(elements: &'e [Element<'k>], path: &[usize]) -> Option<&'e Element<'k>> {
+    let (index, rest) = path.split_first()?;
+    let element = elements.get(*index)?;
+
+    match (rest.is_empty(), element) {
+        (true, element) => Some(element),
+        (false, Element::Tuple(nested)) => element_at(nested, rest),
+        (false, _) => None,
+    }
+}
+
+/// Converts a tuple element to a cell. UUIDs are written in their hyphenated form
+/// and versionstamps as their 12 bytes.
+pub fn element_to_cell(element: &Element) -> Cell {
+    match element {
+        Element::Nil => Cell::Null,
+        Element::Bytes(bytes) => Cell::Bytes(bytes.0.to_vec()),
+        Element::String(value) => Cell::String(value.to_string()),
+        Element::Tuple(elements) => Cell::Tuple(elements.iter().map(element_to_cell).collect()),
+        Element::Int(value) => Cell::Int(*value),
+        Element::Float(value) => Cell::Float(*value as f64),
+        Element::Double(value) => Cell::Float(*value),
+        Element::Bool(value) => Cell::Bool(*value),
+        Element::Uuid(value) => Cell::String(value.to_string()),
+        Element::Versionstamp(value) => Cell::Bytes(value.as_bytes().to_vec()),
+        // Elements only available with optional features, e.g. big integers
+        #[allow(unreachable_patterns)]
+        _ => Cell::Null,
+    }
+}
+
+impl KeyTuple {
+    /// Resolves a path of element names or indexes to indexes.
+    pub fn resolve(&self, path: &ElementPath) -> Option<Vec<usize>> {
+        let path = match path {
+            ElementPath::Index(index) => return Some(vec![*index]),
+            ElementPath::Path(path) => path,
+        };
+
+        path.split('.')
+            .enumerate()
+            .map(|(i, segment)| match segment.parse::<usize>() {
+                Ok(index) => Some(index),
+                // Only top level elements have names
+                Err(_) if i == 0 => self.elements.iter().position(|name| name == segment),
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    /// Index paths of the elements bound to a column, either by name or explicitly.
+    pub fn column_path(&self, column: &str) -> Option<Result<Vec<usize>>> {
+        if let Some(path) = self.columns.get(column) {
+            return Some(self.resolve(path).ok_or_else(|| {
+                Error::InvalidMappingConfig(format!(
+                    "No key element {} for column {}",
+                    path, column
+                ))
+            }));
+        }
+
+        self.elements
+            .iter()
+            .position(|name| name == column)
+            .map(|index| Ok(vec![index]))
+    }
+}
+
+impl KeySpec {
+    /// The bytes of the key.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            KeySpec::Escaped(escaped) => unescape(escaped),
+            KeySpec::Hex { hex } => decode_hex(hex),
+            KeySpec::Tuple(values) => {
+                let mut elements = vec![];
+                for value in values {
+                    elements.push(json_element(value)?);
+                }
+
+                Ok(pack(&elements))
+            }
+        }
+    }
+}
+
+impl Mapping {
+    /// The prefix of every key of the mapping: the prefix of the directory followed
+    /// by the `prefix` of the mapping. Tuple keys are unpacked after it.
+    pub fn key_prefix(&self, directory: &[u8]) -> Result<Vec<u8>> {
+        let mut prefix = directory.to_vec();
+        if let Some(mapping_prefix) = &self.prefix {
+            prefix.extend(mapping_prefix.to_bytes()?);
+        }
+
+        Ok(prefix)
+    }
+
+    /// The prefix of the versionstamped keys of the changelog of the mapping, if
+    /// it has one. Unlike `from` and `to`, it is not relative to the range.
+    pub fn changelog_prefix(&self) -> Result<Option<Vec<u8>>> {
+        self.changelog.as_ref().map(KeySpec::to_bytes).transpose()
+    }
+
+    /// The range of keys exported, `from..to`. Boundaries are relative to the
+    /// prefix of the directory and the `prefix` of the mapping, which bound the
+    /// range when `from` or `to` are not set.
+    pub fn range(&self, directory: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        if self.directory.is_none() && self.prefix.is_none() && self.to.is_none() {
+            return Err(Error::InvalidMappingConfig(format!(
+                "{} needs a directory, a prefix or a to key",
+                self.id()
+            )));
+        }
+
+        let base = self.key_prefix(directory)?;
+
+        let from = match &self.from {
+            Some(from) => [&base[..], &from.to_bytes()?].concat(),
+            None => base.clone(),
+        };
+        let to = match &self.to {
+            Some(to) => [&base[..], &to.to_bytes()?].concat(),
+            None => prefix_end(&base),
+        };
+
+        if from >= to {
+            return Err(Error::InvalidMappingConfig(format!(
+                "{} has an empty range {:?}..{:?}",
+                self.id(),
+                from,
+                to
+            )));
+        }
+
+        Ok((from, to))
+    }
+}
+
+/// Decodes `\xNN` escaped bytes and `\\` escaped backslashes, the way fdbcli
+/// prints keys.
+fn unescape(escaped: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::InvalidMappingConfig(format!("Invalid escaped key {}", escaped));
+
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+
+    while let Some((byte, tail)) = rest.split_first() {
+        rest = tail;
+        if *byte != b'\\' {
+            bytes.push(*byte);
+            continue;
+        }
+
+        match rest {
+            [b'\\', tail @ ..] => {
+                bytes.push(b'\\');
+                rest = tail;
+            }
+            [b'x', tail @ ..] if tail.len() >= 2 => {
+                let digits = std::str::from_utf8(&tail[..2]).map_err(|_e| invalid())?;
+                bytes.push(u8::from_str_radix(digits, 16).map_err(|_e| invalid())?);
+                rest = &tail[2..];
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::InvalidMappingConfig(format!("Invalid hex key {}", hex));
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|digits| match std::str::from_utf8(digits) {
+            Ok(digits) if digits.len() == 2 => {
+                u8::from_str_radix(digits, 16).map_err(|_e| invalid())
+            }
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// A tuple element from its JSON value. Byte strings are written as
+/// `{"bytes": "\\x00"}` or `{"hex": "00"}`, and nested tuples as arrays.
+pub fn json_element(value: &serde_json::Value) -> Result<Element<'static>> {
+    use serde_json::Value;
+
+    Ok(match value {
+        Value::Null => Element::Nil,
+        Value::Bool(value) => Element::Bool(*value),
+        Value::String(value) => Element::String(Cow::Owned(value.clone())),
+        Value::Number(number) => match (number.as_i64(), number.as_f64()) {
+            (Some(value), _) => Element::Int(value),
+            (None, Some(value)) if !number.is_u64() => Element::Double(value),
+            _ => {
+                return Err(Error::InvalidMappingConfig(format!(
+                    "Key element {} is out of range",
+                    number
+                )))
+            }
+        },
+        Value::Array(values) => {
+            let mut elements = vec![];
+            for value in values {
+                elements.push(json_element(value)?);
+            }
+            Element::Tuple(elements)
+        }
+        Value::Object(object) => {
+            let bytes = match (object.len(), object.get("bytes"), object.get("hex")) {
+                (1, Some(Value::String(escaped)), None) => unescape(escaped)?,
+                (1, None, Some(Value::String(hex))) => decode_hex(hex)?,
+                _ => {
+                    return Err(Error::InvalidMappingConfig(format!(
+                        "Invalid key element {}",
+                        value
+                    )))
+                }
+            };
+            Element::Bytes(Bytes(Cow::Owned(bytes)))
+        }
+    })
+}
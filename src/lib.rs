@@ -1,11 +1,23 @@
+pub mod cell;
+pub mod checkpoint;
 pub mod cli;
 pub mod clickhouse;
+pub mod clickhouse_format;
 pub mod clickhouse_message_binding;
 pub mod clickhouse_table;
+pub mod clickhouse_type;
+pub mod coercion;
 pub mod config;
 pub mod context;
+pub mod converter;
 pub mod error;
+pub mod export;
+pub mod expression;
 pub mod fdb;
+pub mod key;
 pub mod protobuf;
 pub mod protobuf_registry;
 pub mod result;
+pub mod router;
+pub mod schema;
+pub mod sync;
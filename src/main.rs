@@ -1,15 +1,18 @@
-use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 
-use clickhouse::Client;
+use fdb_ch_proto_export::checkpoint::CheckpointStore;
 use fdb_ch_proto_export::cli;
 use fdb_ch_proto_export::context::AppContext;
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::export::{dry_run_report, export_mappings, summary_table};
+use fdb_ch_proto_export::schema::{diff_report, diff_schemas, generate_table};
+use fdb_ch_proto_export::sync::sync_mappings;
 use fdb_ch_proto_export::{
     clickhouse::Client as ClickhouseClient, config, error::Error, fdb::FdbClient,
     protobuf::load_protobufs, result::Result,
 };
-use foundationdb::RangeOption;
-use futures::StreamExt;
+use protofish::prelude::Context;
 use tracing::*;
 
 #[tokio::main]
@@ -39,148 +42,148 @@ async fn main() -> Result<()> {
                     changed = true;
                 }
 
+                if let Some(insert_format) = set.insert_format {
+                    config.insert_format = insert_format;
+                    changed = true;
+                }
+
                 if let Some(mapping_file) = set.mapping_file {
                     config.mapping_file = Some(mapping_file);
                     changed = true;
                 }
 
+                if let Some(checkpoint_file) = set.checkpoint_file {
+                    config.checkpoint_file = checkpoint_file;
+                    changed = true;
+                }
+
+                if let Some(checkpoint_subspace) = set.checkpoint_subspace {
+                    config.checkpoint_subspace = Some(checkpoint_subspace);
+                    changed = true;
+                }
+
                 if changed {
                     match config.write() {
                         Ok(()) => info!("config file has been changed"),
                         Err(e) => panic!("writing config file: {}", e),
                     }
                 } else {
-                    info!("Options are cluster-file, proto-file and mapping-file")
+                    info!("Options are cluster-file, proto-file, clickhouse-url, insert-format, mapping-file, checkpoint-file and checkpoint-subspace")
                 }
             }
             cli::Setup::View => {
                 info!("{:?}", config);
             }
         },
-        cli::Opts::Export => {
+        cli::Opts::Schema(schema) => {
             let proto_context = match &config.proto_file {
+                Some(path) => load_protobufs(path).await?,
+                None => return Err(Error::MissingConfig("Missing protofile definition".into())),
+            };
+
+            let converters = Converters::from_config(&proto_context, &config.converters)?;
+
+            let ch_client = ClickhouseClient::new(&config.clickhouse_url, config.insert_format);
+
+            // Tables are named like the mapping writing to them, when there is one
+            let mapping = match config.mapping_file {
+                Some(_) => config.load_mapping()?,
+                None => vec![],
+            };
+
+            match schema {
+                cli::Schema::Generate(generate) => {
+                    let query = generate_table(
+                        &ch_client,
+                        &proto_context,
+                        &converters,
+                        &mapping,
+                        &generate,
+                    )
+                    .await?;
+
+                    println!("{}", query);
+                }
+                cli::Schema::Diff(diff) => {
+                    let diffs =
+                        diff_schemas(&ch_client, &proto_context, &converters, &mapping, &diff)
+                            .await?;
+
+                    println!("{}", diff_report(&diffs));
+                }
+            }
+        }
+        opts @ (cli::Opts::Export(_) | cli::Opts::Sync(_)) => {
+            // The proto context lives for the whole run and is shared by every export task
+            let proto_context: &'static Context = Box::leak(Box::new(match &config.proto_file {
                 Some(path) => {
                     debug!("Using protofile path: {}", path);
                     load_protobufs(&path).await?
                 }
                 None => return Err(Error::MissingConfig("Missing protofile definition".into())),
-            };
+            }));
+
+            let converters: &'static Converters = Box::leak(Box::new(Converters::from_config(
+                proto_context,
+                &config.converters,
+            )?));
 
             #[allow(unused)]
             let guard = unsafe { FdbClient::start_network() }.expect("unable to start network");
 
             debug!("Using fdb cluster file path: {}", &config.cluster_file);
 
-            let client =
-                Arc::new(FdbClient::new(&config.cluster_file).expect("unable to start client"));
+            let mut client = FdbClient::new(&config.cluster_file).expect("unable to start client");
 
             debug!("Using clickhouse url: {}", &config.clickhouse_url);
 
-            let ch_client =
-                ClickhouseClient::new(Client::default().with_url(&config.clickhouse_url));
+            let ch_client = ClickhouseClient::new(&config.clickhouse_url, config.insert_format);
 
             let mapping = &config
                 .load_mapping()
                 .expect("unable to read mapping config");
 
-            let mut context = AppContext::new(client.clone(), ch_client);
+            client.open_directories(mapping).await?;
+            let client = Arc::new(client);
+
+            let checkpoints = CheckpointStore::from_config(&config, client.clone())?;
+
+            let mut context = AppContext::new(client.clone(), ch_client, checkpoints, converters);
 
             context
-                .bind_messages(mapping, &proto_context)
+                .bind_messages(mapping, proto_context)
                 .await
                 .expect("unable to create registry");
 
-            for map in mapping {
-                let binding = match context.proto_registry.get(&map.proto) {
-                    Some(binding) => binding,
-                    None => continue,
-                };
-
-                let mut messages_written = 0;
-
-                let mut from = map.from.as_bytes().to_vec();
-                let to = map.to.as_bytes();
-                let mut retry = false;
-
-                'retry: loop {
-                    let tx = client.begin_tx().await.expect("unable to begin tx");
-
-                    let range_from = from.clone();
-
-                    let mut kvs = tx.get_ranges(
-                        RangeOption {
-                            reverse: false,
-                            limit: None,
-                            ..RangeOption::from((range_from.as_ref(), to))
-                        },
-                        false,
-                    );
-
-                    while let Some(kv) = kvs.next().await {
-                        let kv = match kv {
-                            Ok(kv) => kv,
-                            Err(e) => {
-                                // 1007: Transaction is too old to perform reads or be committed
-                                // We restart the transaction from the last read key.
-                                if e.code() == 1007 {
-                                    retry = true;
-                                    continue 'retry;
-                                }
-
-                                return Err(Error::Fdb(e));
-                            }
-                        };
-
-                        // TODO: Extract batch writing out
-                        let mut batch: Vec<BTreeMap<usize, String>> = vec![];
-
-                        let mut last_read_key = None;
-
-                        for value in (*kv).into_iter() {
-                            if retry {
-                                // If there has been a retry (due to code 1007) skip the first key as the last read captured it
-                                retry = false;
-                                continue;
-                            }
-
-                            let k = value.key().to_vec();
-                            let v = value.value();
-
-                            let fields = match binding.prepare(&proto_context, v) {
-                                Ok(res) => res,
-                                Err(e) => {
-                                    error!("Failed transforming message: {:?}", e);
-                                    continue;
-                                }
-                            };
-
-                            last_read_key = Some(k);
-                            batch.push(fields);
-                        }
-
-                        let query = match binding.table.construct_batch(batch.clone()) {
-                            Ok(query) => query,
-                            Err(_e) => continue,
-                        };
-
-                        context.ch_client.write_batch(query).await?;
-
-                        if let Some(last_read_key) = last_read_key {
-                            from = last_read_key;
-                        }
-
-                        messages_written += batch.len()
+            let context: &'static AppContext = Box::leak(Box::new(context));
+
+            match opts {
+                cli::Opts::Export(export) => {
+                    let summaries =
+                        export_mappings(context, proto_context, mapping, &export).await?;
+
+                    println!("{}", summary_table(&summaries));
+
+                    if export.dry_run {
+                        println!("\n{}", dry_run_report(&summaries));
                     }
 
-                    // We have read all the keys in this range
-                    break;
+                    let failed = summaries.iter().filter(|s| s.error.is_some()).count();
+                    if failed > 0 {
+                        return Err(Error::ExportFailed(failed));
+                    }
                 }
-
-                info!(
-                    "{} messages written to {}",
-                    messages_written,
-                    binding.table.parts.to_string()
-                )
+                cli::Opts::Sync(sync) => {
+                    sync_mappings(
+                        context,
+                        proto_context,
+                        mapping,
+                        Duration::from_millis(sync.interval),
+                        sync.workers,
+                    )
+                    .await?;
+                }
+                cli::Opts::Setup(_) | cli::Opts::Schema(_) => unreachable!(),
             }
         }
     }
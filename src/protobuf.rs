@@ -3,11 +3,33 @@ use tracing::error;
 
 use std::collections::HashMap;
 
+use crate::cell::Cell;
+use crate::converter::Converters;
 use crate::error::Error;
 use crate::result::Result;
-use protofish::{context::Context, prelude::Value};
+use protofish::{
+    context::{Context, MessageInfo, ValueType},
+    prelude::{FieldValue, MessageValue, PackedArray, Value},
+};
 use std::path::Path;
 
+/// Full name of the message wrapping a message of any type with its type url.
+pub const ANY: &str = "google.protobuf.Any";
+
+/// Messages wrapping a single scalar `value` field, so it can be told apart from
+/// its default.
+const WRAPPERS: &[&str] = &[
+    "google.protobuf.DoubleValue",
+    "google.protobuf.FloatValue",
+    "google.protobuf.Int64Value",
+    "google.protobuf.UInt64Value",
+    "google.protobuf.Int32Value",
+    "google.protobuf.UInt32Value",
+    "google.protobuf.BoolValue",
+    "google.protobuf.StringValue",
+    "google.protobuf.BytesValue",
+];
+
 pub async fn load_protobufs(path: impl AsRef<Path>) -> Result<Context> {
     let common_types = get_common_types().await?;
 
@@ -59,95 +81,353 @@ struct Field {
     value: serde_json::Value,
 }
 
-pub fn value_to_string(
+/// Reads the `type_url` and `value` of an encoded google.protobuf.Any, without
+/// decoding the message it wraps.
+pub fn decode_any(message: &[u8]) -> Result<(String, &[u8])> {
+    let mut type_url = String::new();
+    let mut value: &[u8] = &[];
+    let mut rest = message;
+
+    while !rest.is_empty() {
+        let tag = read_varint(&mut rest)?;
+
+        let skip = match tag & 0x7 {
+            0 => {
+                read_varint(&mut rest)?;
+                0
+            }
+            1 => 8,
+            2 => {
+                let len = read_varint(&mut rest)? as usize;
+                if len > rest.len() {
+                    return Err(Error::ParseError("Truncated google.protobuf.Any".into()));
+                }
+
+                match tag >> 3 {
+                    1 => {
+                        type_url = String::from_utf8(rest[..len].to_vec())
+                            .map_err(Error::StringDecodeError)?
+                    }
+                    2 => value = &rest[..len],
+                    _ => {}
+                }
+                len
+            }
+            5 => 4,
+            wire_type => {
+                return Err(Error::ParseError(format!(
+                    "Unexpected wire type {} in google.protobuf.Any",
+                    wire_type
+                )))
+            }
+        };
+
+        if skip > rest.len() {
+            return Err(Error::ParseError("Truncated google.protobuf.Any".into()));
+        }
+        rest = &rest[skip..];
+    }
+
+    Ok((type_url, value))
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+
+    for (i, byte) in input.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *input = &input[i + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(Error::ParseError("Invalid varint".into()))
+}
+
+/// Decodes the message wrapped in a google.protobuf.Any, resolving the type named
+/// by its `type_url` in the context. Returns the url, with the type and the message
+/// unless the type is missing from the context.
+pub fn unwrap_any<'c>(
+    context: &'c Context,
+    any: &MessageValue,
+) -> (String, Option<(&'c MessageInfo, MessageValue)>) {
+    let (type_url, value) = any_fields(any);
+
+    let wrapped = any_type(context, any).map(|wrapped| (wrapped, wrapped.decode(value, context)));
+
+    (type_url.to_string(), wrapped)
+}
+
+/// The type named by the `type_url` of a google.protobuf.Any, unless it is
+/// missing from the context.
+pub fn any_type<'c>(context: &'c Context, any: &MessageValue) -> Option<&'c MessageInfo> {
+    let (type_url, _) = any_fields(any);
+
+    context.get_message(type_url.rsplit('/').next().unwrap_or_default())
+}
+
+/// The `type_url` and `value` fields of a decoded google.protobuf.Any.
+fn any_fields(any: &MessageValue) -> (&str, &[u8]) {
+    let mut type_url = "";
+    let mut value: &[u8] = &[];
+
+    for field in &any.fields {
+        match (field.number, &field.value) {
+            (1, Value::String(url)) => type_url = url,
+            (2, Value::Bytes(bytes)) => value = bytes,
+            _ => {}
+        }
+    }
+
+    (type_url, value)
+}
+
+/// Converts a decoded proto value to a cell.
+pub fn value_to_cell(
     context: &protofish::context::Context,
+    converters: &Converters,
     value: &protofish::prelude::Value,
-) -> Result<String> {
+) -> Result<Cell> {
     use protofish::prelude::Value::*;
 
     Ok(match value {
-        Double(v) => v.to_string(),
-        Float(v) => v.to_string(),
-        Int32(v) => v.to_string(),
-        Int64(v) => v.to_string(),
-        UInt32(v) => v.to_string(),
-        UInt64(v) => v.to_string(),
-        SInt32(v) => v.to_string(),
-        SInt64(v) => v.to_string(),
-        Fixed32(v) => v.to_string(),
-        Fixed64(v) => v.to_string(),
-        SFixed32(v) => v.to_string(),
-        SFixed64(v) => v.to_string(),
-        Bool(v) => v.to_string(),
-        String(v) => format!("'{}'", v.replace("'", "\\'")),
-
-        // Packed(v) => match v {
-        //     PackedArray::Double(v) => to_value(v)?,
-        //     PackedArray::Float(v) => to_value(v)?,
-        //     PackedArray::Int32(v) => to_value(v)?,
-        //     PackedArray::Int64(v) => to_value(v)?,
-        //     PackedArray::UInt32(v) => to_value(v)?,
-        //     PackedArray::UInt64(v) => to_value(v)?,
-        //     PackedArray::SInt32(v) => to_value(v)?,
-        //     PackedArray::SInt64(v) => to_value(v)?,
-        //     PackedArray::Fixed32(v) => to_value(v)?,
-        //     PackedArray::Fixed64(v) => to_value(v)?,
-        //     PackedArray::SFixed32(v) => to_value(v)?,
-        //     PackedArray::SFixed64(v) => to_value(v)?,
-        //     PackedArray::Bool(v) => to_value(v)?,
-        // },
+        Double(v) => Cell::Float(*v),
+        Float(v) => Cell::Float(*v as f64),
+        Int32(v) => Cell::Int(*v as i64),
+        Int64(v) => Cell::Int(*v),
+        UInt32(v) => Cell::UInt(*v as u64),
+        UInt64(v) => Cell::UInt(*v),
+        SInt32(v) => Cell::Int(*v as i64),
+        SInt64(v) => Cell::Int(*v),
+        Fixed32(v) => Cell::UInt(*v as u64),
+        Fixed64(v) => Cell::UInt(*v),
+        SFixed32(v) => Cell::Int(*v as i64),
+        SFixed64(v) => Cell::Int(*v),
+        Bool(v) => Cell::Bool(*v),
+        String(v) => Cell::String(v.clone()),
+        Bytes(v) => Cell::Bytes(v.to_vec()),
+
+        Packed(v) => Cell::Array(match v {
+            PackedArray::Double(v) => v.iter().map(|v| Cell::Float(*v)).collect(),
+            PackedArray::Float(v) => v.iter().map(|v| Cell::Float(*v as f64)).collect(),
+            PackedArray::Int32(v) => v.iter().map(|v| Cell::Int(*v as i64)).collect(),
+            PackedArray::Int64(v) => v.iter().map(|v| Cell::Int(*v)).collect(),
+            PackedArray::UInt32(v) => v.iter().map(|v| Cell::UInt(*v as u64)).collect(),
+            PackedArray::UInt64(v) => v.iter().map(|v| Cell::UInt(*v)).collect(),
+            PackedArray::SInt32(v) => v.iter().map(|v| Cell::Int(*v as i64)).collect(),
+            PackedArray::SInt64(v) => v.iter().map(|v| Cell::Int(*v)).collect(),
+            PackedArray::Fixed32(v) => v.iter().map(|v| Cell::UInt(*v as u64)).collect(),
+            PackedArray::Fixed64(v) => v.iter().map(|v| Cell::UInt(*v)).collect(),
+            PackedArray::SFixed32(v) => v.iter().map(|v| Cell::Int(*v as i64)).collect(),
+            PackedArray::SFixed64(v) => v.iter().map(|v| Cell::Int(*v)).collect(),
+            PackedArray::Bool(v) => v.iter().map(|v| Cell::Bool(*v)).collect(),
+        }),
 
         Enum(v) => {
             let resolved = context.resolve_enum(v.enum_ref);
 
-            format!("'{}'", resolved.get_field_by_value(v.value).unwrap().name.clone())
+            Cell::Enum {
+                name: resolved.get_field_by_value(v.value).map(|f| f.name.clone()),
+                number: v.value,
+            }
         }
 
         Message(v) => {
             let resolved = context.resolve_message(v.msg_ref);
 
-            let mut fields = v.fields.clone().into_iter();
+            if let Some(converter) = converters.get(&resolved.full_name) {
+                return converter.convert(context, resolved, v);
+            }
 
-            // Handle google protobuf timestamp
-            if let Some(seconds) = fields.find(|f| f.number == 1) {
-                // TODO: protos.Timestamp is specific to our project, we need a better way to handle this
-                if resolved.full_name == "google.protobuf.Timestamp" || resolved.full_name == "protos.Timestamp" {
-                    if let Value::Int64(v) = seconds.value {
-                        return Ok(v.to_string());
-                    }
-                }
+            if let Some(cell) = well_known_cell(context, converters, resolved, &v.fields)? {
+                return Ok(cell);
             }
-            
-            let values: HashMap<std::string::String, std::string::String> = v.fields
-                .clone()
-                .into_iter()
-                .map(|field| {
-                    let name = &resolved.get_field(field.number).unwrap().name;
 
-                    (name.clone(), value_to_string(context, &field.value).unwrap())
-                })
-                .collect();
+            // Rendered as the wrapped message with its type in `@type`, as in the proto3 JSON mapping
+            if resolved.full_name == ANY {
+                let (type_url, wrapped) = unwrap_any(context, v);
+
+                // Messages of a type missing from the proto files only have their type
+                let mut fields = vec![("@type".to_string(), Cell::String(type_url))];
+                if let Some((wrapped, message)) = wrapped {
+                    fields.extend(message_fields(context, converters, wrapped, &message)?);
+                }
 
-            let v = serde_json::to_string(&values)?;
+                return Ok(Cell::Message(fields));
+            }
 
-            // Sub object will serialize strings with ''
-            v.to_string().replace("'", "")
+            Cell::Message(message_fields(context, converters, resolved, v)?)
         }
 
         Unknown(_) => return Err(Error::UnknownValueType),
 
-        _ => {
-            error!("Unable to convert proto value to string: {:?}", value);
-            return Err(Error::ParseError("Unsupported prototype".into()));
+        Incomplete(..) => {
+            error!("Unable to convert incomplete proto value: {:?}", value);
+            return Err(Error::ParseError("Incomplete proto value".into()));
         }
+    })
+}
 
-        // Value which was incomplete due to missing bytes in the payload.
-        // Incomplete(_, v) => serde_json::Value::String(format!(
-        //     "INCOMPLETE: {}",
-        //     std::string::String::from_utf8_lossy(&v.to_vec()).to_string()
-        // )),
+/// Converts the fields of a message to named cells, leaving out unknown fields.
+fn message_fields(
+    context: &Context,
+    converters: &Converters,
+    info: &MessageInfo,
+    message: &MessageValue,
+) -> Result<Vec<(String, Cell)>> {
+    let mut fields = vec![];
+    for field in &message.fields {
+        let name = match info.get_field(field.number) {
+            Some(desc) => desc.name.clone(),
+            None => continue,
+        };
 
-        // Value which wasn't defined in the context.
-        // Unknown(v) => serde_json::Value::String(format!("UNKNOWN: {:?}", v)),
-    })
+        fields.push((name, value_to_cell(context, converters, &field.value)?));
+    }
+
+    Ok(fields)
+}
+
+/// Whether the message is one of the `google.protobuf.*Value` scalar wrappers.
+pub fn is_wrapper(info: &MessageInfo) -> bool {
+    WRAPPERS.contains(&info.full_name.as_str())
+}
+
+/// Converts the well-known types with a native representation, as in the proto3
+/// JSON mapping: timestamps and durations to time values, wrappers to the value
+/// they wrap, field masks to their paths and `Struct`, `Value` and `ListValue` to
+/// the JSON values they hold. Returns `None` for other messages.
+fn well_known_cell(
+    context: &Context,
+    converters: &Converters,
+    info: &MessageInfo,
+    fields: &[FieldValue],
+) -> Result<Option<Cell>> {
+    Ok(Some(match info.full_name.as_str() {
+        // protos.Timestamp predates the converters, and keeps being converted by default
+        "google.protobuf.Timestamp" | "protos.Timestamp" => {
+            let mut seconds = 0;
+            let mut nanos = 0;
+            for field in fields {
+                match (field.number, &field.value) {
+                    (1, Value::Int64(v)) => seconds = *v,
+                    (2, Value::Int32(v)) => nanos = *v as u32,
+                    _ => {}
+                }
+            }
+
+            Cell::DateTime { seconds, nanos }
+        }
+
+        "google.protobuf.Duration" => {
+            let mut seconds = 0;
+            let mut nanos = 0;
+            for field in fields {
+                match (field.number, &field.value) {
+                    (1, Value::Int64(v)) => seconds = *v,
+                    (2, Value::Int32(v)) => nanos = *v,
+                    _ => {}
+                }
+            }
+
+            Cell::Duration { seconds, nanos }
+        }
+
+        _ if is_wrapper(info) => match fields.iter().rev().find(|f| f.number == 1) {
+            Some(field) => value_to_cell(context, converters, &field.value)?,
+            None => match info.get_field(1) {
+                Some(field) => proto_default(context, converters, &field.field_type),
+                None => Cell::Null,
+            },
+        },
+
+        "google.protobuf.FieldMask" | "google.protobuf.ListValue" => Cell::Array(
+            fields
+                .iter()
+                .filter(|f| f.number == 1)
+                .map(|f| value_to_cell(context, converters, &f.value))
+                .collect::<Result<Vec<Cell>>>()?,
+        ),
+
+        "google.protobuf.Struct" => {
+            let mut entries = vec![];
+            for field in fields.iter().filter(|f| f.number == 1) {
+                let entry = match &field.value {
+                    Value::Message(entry) => entry,
+                    _ => continue,
+                };
+
+                let mut key = String::new();
+                let mut value = Cell::Null;
+                for entry_field in &entry.fields {
+                    match (entry_field.number, &entry_field.value) {
+                        (1, Value::String(v)) => key = v.clone(),
+                        (2, v) => value = value_to_cell(context, converters, v)?,
+                        _ => {}
+                    }
+                }
+
+                entries.push((key, value));
+            }
+
+            Cell::Message(entries)
+        }
+
+        // One of `null_value`, `number_value`, `string_value`, `bool_value`,
+        // `struct_value` and `list_value`
+        "google.protobuf.Value" => match fields.last() {
+            Some(field) if field.number != 1 => value_to_cell(context, converters, &field.value)?,
+            _ => Cell::Null,
+        },
+
+        _ => return Ok(None),
+    }))
+}
+
+/// The value of a field that is not set in a message. Well-known types default
+/// and converted messages to the value of their empty message.
+pub fn proto_default(context: &Context, converters: &Converters, kind: &ValueType) -> Cell {
+    match *kind {
+        ValueType::Bool => Cell::Bool(false),
+        ValueType::String => Cell::String(String::new()),
+        ValueType::Bytes => Cell::Bytes(vec![]),
+        ValueType::Message(msg_ref) => {
+            let info = context.resolve_message(msg_ref);
+
+            if let Some(converter) = converters.get(&info.full_name) {
+                let empty = MessageValue {
+                    msg_ref,
+                    garbage: None,
+                    fields: vec![],
+                };
+
+                return converter
+                    .convert(context, info, &empty)
+                    .unwrap_or(Cell::Message(vec![]));
+            }
+
+            match well_known_cell(context, converters, info, &[]) {
+                Ok(Some(cell)) => cell,
+                _ => Cell::Message(vec![]),
+            }
+        }
+        ValueType::Enum(enum_ref) => Cell::Enum {
+            name: context
+                .resolve_enum(enum_ref)
+                .get_field_by_value(0)
+                .map(|field| field.name.clone()),
+            number: 0,
+        },
+        ValueType::Double | ValueType::Float => Cell::Float(0.0),
+        ValueType::Int32
+        | ValueType::Int64
+        | ValueType::SInt32
+        | ValueType::SInt64
+        | ValueType::SFixed32
+        | ValueType::SFixed64 => Cell::Int(0),
+        ValueType::UInt32 | ValueType::UInt64 | ValueType::Fixed32 | ValueType::Fixed64 => {
+            Cell::UInt(0)
+        }
+    }
 }
@@ -1,13 +1,14 @@
-use protofish::{context::MessageInfo, prelude::Context};
+use protofish::prelude::Context;
 use tracing::info;
 
 use crate::{
-    clickhouse_message_binding::bind_proto_message,
+    clickhouse_message_binding::{bind_proto_message, MessageBinding},
     clickhouse_table::{ClickhouseTableParts, Table},
     config::Mapping,
     context::AppContext,
     error::Error,
     result::Result,
+    router::{BoundRoute, RouteMatcher, Router},
 };
 
 impl<'a> AppContext<'a> {
@@ -17,47 +18,109 @@ impl<'a> AppContext<'a> {
         proto_context: &'a Context,
     ) -> Result<()> {
         for mapping in mappings {
-            let message = match proto_context.get_message(&mapping.proto) {
-                Some(message) => message,
-                None => {
-                    return Err(Error::ParseError(
-                        "Could not find message definition".into(),
-                    ))
-                }
-            };
-
-            if let Some(binding) = self.proto_registry.get(&message.full_name) {
-                let curr_binding = &binding.table.parts;
+            let id = mapping.id();
+            let unbound = mapping.proto.is_none() && mapping.routes.is_empty();
+            if mapping.proto.is_some() != mapping.table.is_some() || unbound || id.is_empty() {
+                return Err(Error::InvalidMappingConfig(format!(
+                    "Mapping {} needs both a proto and a table, or routes",
+                    id
+                )));
+            }
 
+            if self.proto_registry.contains_key(&id) {
                 return Err(Error::InvalidMappingConfig(format!(
-                    "Message {} is already binded to {}",
-                    &message.full_name,
-                    curr_binding.to_string()
+                    "Mapping {} is defined twice",
+                    id
                 )));
             }
 
-            let table = self.construct_table(&mapping.table).await?;
-            if table.columns.len() == 0 {
-                info!(
-                    "Skipping mapping for table as found no columns: table={}",
-                    &table.parts.table
-                );
-                continue;
+            let key_prefix = self.fdb_client.key_prefix(mapping)?;
+
+            let mut routes = vec![];
+            for route in &mapping.routes {
+                let matcher = RouteMatcher::new(route, mapping.key.as_ref())?;
+                let route_mapping = mapping.route_mapping(route);
+
+                // Dropping the route would bind its messages with the next route
+                let binding = match self
+                    .bind_message(proto_context, &route_mapping, &key_prefix)
+                    .await?
+                {
+                    Some(binding) => binding,
+                    None => {
+                        return Err(Error::InvalidMappingConfig(format!(
+                            "Route of {} to {} has no columns to write, `fdb-ch schema generate` creates the table",
+                            id, route.table
+                        )))
+                    }
+                };
+                routes.push(BoundRoute { matcher, binding });
+            }
+
+            // The proto of the mapping binds the messages matched by no route. A mapping
+            // whose table has no columns is left out of the registry, failing its export
+            if mapping.proto.is_some() {
+                match self
+                    .bind_message(proto_context, mapping, &key_prefix)
+                    .await?
+                {
+                    Some(binding) => routes.push(BoundRoute {
+                        matcher: RouteMatcher::default(),
+                        binding,
+                    }),
+                    None => continue,
+                }
             }
 
-            self.bind_message(message, table).await?;
+            self.proto_registry.insert(
+                id,
+                Router {
+                    routes,
+                    key_prefix,
+                    log_unmatched: mapping.log_unmatched,
+                },
+            );
         }
 
         Ok(())
     }
 
-    async fn bind_message(&mut self, message: &'a MessageInfo, table: Table) -> Result<()> {
-        let binding = bind_proto_message(message, table)?;
+    /// Binds the proto of the mapping to its table, unless the table has no columns.
+    async fn bind_message(
+        &self,
+        proto_context: &'a Context,
+        mapping: &Mapping,
+        key_prefix: &[u8],
+    ) -> Result<Option<MessageBinding<'a>>> {
+        let (proto, table) = match (&mapping.proto, &mapping.table) {
+            (Some(proto), Some(table)) => (proto, table),
+            _ => return Ok(None),
+        };
+
+        let message = match proto_context.get_message(proto) {
+            Some(message) => message,
+            None => {
+                return Err(Error::ParseError(format!(
+                    "Could not find message definition: {}",
+                    proto
+                )))
+            }
+        };
+
+        let table = self.construct_table(table).await?;
+        if table.columns.len() == 0 {
+            info!(
+                "Skipping mapping for table as found no columns, `fdb-ch schema generate` creates it: table={}",
+                &table.parts.table
+            );
+            return Ok(None);
+        }
 
-        self.proto_registry
-            .insert(message.full_name.clone(), binding);
+        let mut binding =
+            bind_proto_message(proto_context, self.converters, message, table, mapping)?;
+        binding.key_prefix = key_prefix.to_vec();
 
-        Ok(())
+        Ok(Some(binding))
     }
 
     async fn construct_table(&self, table_name: &String) -> Result<Table> {
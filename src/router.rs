@@ -0,0 +1,136 @@
+use foundationdb::tuple::{pack, Element};
+use regex::bytes::Regex;
+use tracing::*;
+
+use crate::{
+    clickhouse_message_binding::MessageBinding,
+    config::{KeyTuple, Route},
+    error::Error,
+    key::{element_at, json_element, unpack_key},
+    protobuf::decode_any,
+    result::Result,
+};
+
+/// Routes the messages of a mapping to the binding of their type.
+pub struct Router<'a> {
+    pub routes: Vec<BoundRoute<'a>>,
+    /// Prefix of the directory holding the keys, before their tuple elements.
+    pub key_prefix: Vec<u8>,
+    pub log_unmatched: bool,
+}
+
+pub struct BoundRoute<'a> {
+    pub matcher: RouteMatcher,
+    pub binding: MessageBinding<'a>,
+}
+
+/// Conditions on the key and type of the messages of a route. A matcher without
+/// conditions matches every message.
+#[derive(Default)]
+pub struct RouteMatcher {
+    key_pattern: Option<Regex>,
+    // index path of the element and its packed value
+    key_element: Option<(Vec<usize>, Vec<u8>)>,
+    type_url: Option<String>,
+}
+
+impl RouteMatcher {
+    pub fn new(route: &Route, key: Option<&KeyTuple>) -> Result<RouteMatcher> {
+        let invalid = |e: String| {
+            Error::InvalidMappingConfig(format!("Invalid route to {}: {}", route.table, e))
+        };
+
+        let key_pattern = match &route.key_pattern {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| invalid(e.to_string()))?),
+            None => None,
+        };
+
+        let key_element = match &route.key_element {
+            Some(element_match) => {
+                let path = key
+                    .cloned()
+                    .unwrap_or_default()
+                    .resolve(&element_match.element)
+                    .ok_or_else(|| invalid(format!("no key element {}", element_match.element)))?;
+
+                Some((path, pack(&json_element(&element_match.value)?)))
+            }
+            None => None,
+        };
+
+        Ok(RouteMatcher {
+            key_pattern,
+            key_element,
+            type_url: route.type_url.clone(),
+        })
+    }
+
+    fn matches(
+        &self,
+        key: &[u8],
+        elements: Option<&[Element]>,
+        any: Option<&(String, &[u8])>,
+    ) -> bool {
+        if let Some(pattern) = &self.key_pattern {
+            if !pattern.is_match(key) {
+                return false;
+            }
+        }
+
+        if let Some((path, value)) = &self.key_element {
+            match elements.and_then(|elements| element_at(elements, path)) {
+                Some(element) if &pack(element) == value => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(type_url) = &self.type_url {
+            match any {
+                // Matches the whole url or the type name after its last `/`
+                Some((url, _)) if url == type_url || url.rsplit('/').next() == Some(type_url) => {}
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+impl<'a> Router<'a> {
+    /// Returns the index of the first route matching the message, along with the
+    /// message to bind, unwrapped from its google.protobuf.Any when the route
+    /// matches its type. Keys and values that can't be decoded only match the
+    /// routes without conditions on them.
+    pub fn route<'v>(&self, key: &[u8], value: &'v [u8]) -> Option<(usize, &'v [u8])> {
+        let elements = if self.routes.iter().any(|r| r.matcher.key_element.is_some()) {
+            unpack_key(key, &self.key_prefix).ok()
+        } else {
+            None
+        };
+        let any = if self.routes.iter().any(|r| r.matcher.type_url.is_some()) {
+            decode_any(value).ok()
+        } else {
+            None
+        };
+
+        for (index, route) in self.routes.iter().enumerate() {
+            if !route
+                .matcher
+                .matches(key, elements.as_deref(), any.as_ref())
+            {
+                continue;
+            }
+
+            return match (&route.matcher.type_url, &any) {
+                (Some(_), Some((_, message))) => Some((index, *message)),
+                _ => Some((index, value)),
+            };
+        }
+
+        if self.log_unmatched {
+            warn!("No route matches the message of key {:?}", key);
+        }
+
+        None
+    }
+}
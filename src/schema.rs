@@ -0,0 +1,676 @@
+use protofish::{
+    context::{EnumInfo, MessageField, MessageInfo, Multiplicity, ValueType},
+    prelude::Context,
+};
+
+use crate::{
+    cli::{Diff, Generate},
+    clickhouse::Client,
+    clickhouse_message_binding::{bound_field, map_entry, nested_message},
+    clickhouse_table::{quote_name, ClickhouseTableParts, Table},
+    clickhouse_type::ColumnType,
+    coercion::{check_enum, check_field},
+    config::Mapping,
+    converter::Converters,
+    error::Error,
+    protobuf::{is_wrapper, ANY},
+    result::Result,
+};
+use tracing::*;
+
+const DEFAULT_ENGINE: &str = "MergeTree";
+
+/// Options of the tables generated from a message.
+#[derive(Debug, Clone, Default)]
+pub struct TableOptions {
+    /// Table engine, `MergeTree` by default.
+    pub engine: Option<String>,
+    /// Sorting key of a `MergeTree` table, `tuple()` by default.
+    pub order_by: Option<String>,
+    pub partition_by: Option<String>,
+    /// Writes wrappers and the fields of nested messages to `Nullable` columns,
+    /// which are `NULL` when the message is not set.
+    pub nullable: bool,
+    /// Depth of the nested messages whose fields get their own columns. Deeper
+    /// messages are written to `String` columns as JSON.
+    pub flatten: usize,
+}
+
+impl From<&Generate> for TableOptions {
+    fn from(generate: &Generate) -> TableOptions {
+        TableOptions {
+            engine: generate.engine.clone(),
+            order_by: generate.order_by.clone(),
+            partition_by: generate.partition_by.clone(),
+            nullable: generate.nullable,
+            flatten: generate.flatten,
+        }
+    }
+}
+
+/// Generates the `CREATE TABLE` query of the table of a message, creating the
+/// table when asked to. Fields get the columns the mapping or route writing the
+/// message to the table binds them to, if there is one, as in [`message_columns`].
+pub async fn generate_table(
+    ch_client: &Client,
+    proto_context: &Context,
+    converters: &Converters,
+    mappings: &[Mapping],
+    generate: &Generate,
+) -> Result<String> {
+    let message = match proto_context.get_message(&generate.proto) {
+        Some(message) => message,
+        None => {
+            return Err(Error::ParseError(format!(
+                "Could not find message definition: {}",
+                generate.proto
+            )))
+        }
+    };
+    let table = ClickhouseTableParts::from_string(&generate.table)?;
+
+    let mapping = table_mapping(mappings, &generate.proto, &generate.table).unwrap_or_default();
+    let options = TableOptions::from(generate);
+
+    let columns: Vec<(String, ColumnType)> =
+        message_columns(proto_context, converters, message, &mapping, &options)
+            .into_iter()
+            .map(|column| (column.name, column.r#type))
+            .collect();
+    let query = create_table(&table, &columns, &options);
+
+    if generate.apply {
+        ch_client.execute(&query).await?;
+        info!("Created table {}", table);
+    }
+
+    Ok(query)
+}
+
+/// A column whose type can't hold the field bound to it.
+pub struct MismatchedColumn {
+    pub name: String,
+    pub r#type: ColumnType,
+    /// The type of the column generated for the field.
+    pub expected: ColumnType,
+    pub reason: String,
+    /// Whether modifying the column to the expected type may lose its values.
+    pub lossy: bool,
+}
+
+/// How the table of a mapping differs from the fields of its message.
+pub struct SchemaDiff {
+    pub proto: String,
+    pub table: ClickhouseTableParts,
+    /// Whether the table has columns. Its columns are all missing otherwise.
+    pub exists: bool,
+    /// Columns of the fields bound to no column.
+    pub missing: Vec<(String, ColumnType)>,
+    pub mismatched: Vec<MismatchedColumn>,
+    /// Columns bound to no field, expression or key element.
+    pub orphaned: Vec<String>,
+    /// Options the missing columns are generated with, and a missing table.
+    pub options: TableOptions,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.exists && self.missing.is_empty() && self.mismatched.is_empty()
+    }
+
+    /// Statements adding the missing columns and modifying the mismatched ones, the
+    /// modifications that may lose values only when `lossy`. Orphaned columns are
+    /// left to be dropped by hand, and missing tables to `schema generate`.
+    pub fn statements(&self, lossy: bool) -> Vec<String> {
+        if !self.exists {
+            return vec![];
+        }
+
+        let added = self.missing.iter().map(|(name, r#type)| {
+            format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                self.table.quoted(),
+                quote_name(name),
+                r#type
+            )
+        });
+        let modified = self
+            .mismatched
+            .iter()
+            .filter(|column| lossy || !column.lossy)
+            .map(|column| {
+                format!(
+                    "ALTER TABLE {} MODIFY COLUMN {} {}",
+                    self.table.quoted(),
+                    quote_name(&column.name),
+                    column.expected
+                )
+            });
+
+        added.chain(modified).collect()
+    }
+}
+
+/// Compares the table of every mapping and route with the fields of its message,
+/// running the statements fixing the tables when asked to.
+pub async fn diff_schemas(
+    ch_client: &Client,
+    proto_context: &Context,
+    converters: &Converters,
+    mappings: &[Mapping],
+    diff: &Diff,
+) -> Result<Vec<SchemaDiff>> {
+    let options = TableOptions {
+        nullable: diff.nullable,
+        flatten: diff.flatten,
+        ..TableOptions::default()
+    };
+
+    let mut diffs = vec![];
+    for mapping in mappings {
+        let mut bound: Vec<Mapping> = mapping
+            .routes
+            .iter()
+            .map(|route| mapping.route_mapping(route))
+            .collect();
+        bound.push(mapping.clone());
+
+        for mapping in bound {
+            let (proto, table) = match (&mapping.proto, &mapping.table) {
+                (Some(proto), Some(table)) => (proto, table),
+                _ => continue,
+            };
+
+            let message = match proto_context.get_message(proto) {
+                Some(message) => message,
+                None => {
+                    return Err(Error::ParseError(format!(
+                        "Could not find message definition: {}",
+                        proto
+                    )))
+                }
+            };
+
+            let parts = ClickhouseTableParts::from_string(table)?;
+            let mut columns = vec![];
+            for column in ch_client.table_columns(parts.clone()).await? {
+                columns.push(column.try_into()?);
+            }
+            let table = Table::new(parts, columns);
+
+            let diff_table = diff_table(
+                proto_context,
+                converters,
+                message,
+                &table,
+                &mapping,
+                &options,
+            );
+            if diff.apply {
+                for statement in diff_table.statements(diff.lossy) {
+                    ch_client.execute(&statement).await?;
+                    info!("Applied: {}", statement);
+                }
+            }
+
+            diffs.push(diff_table);
+        }
+    }
+
+    Ok(diffs)
+}
+
+/// Compares a table with the columns generated for the fields of a message. Fields
+/// are matched with the columns the mapping binds them to, so a column of a nested
+/// message covers the columns of its fields and the other way around.
+pub fn diff_table(
+    ctx: &Context,
+    converters: &Converters,
+    message: &MessageInfo,
+    table: &Table,
+    mapping: &Mapping,
+    options: &TableOptions,
+) -> SchemaDiff {
+    let mut bound: Vec<Vec<&MessageField>> = vec![];
+    let mut mismatched = vec![];
+    let mut orphaned = vec![];
+
+    for column in &table.columns {
+        if mapping.expressions.contains_key(&column.name) {
+            continue;
+        }
+
+        let key_element = mapping
+            .key
+            .as_ref()
+            .and_then(|key| key.column_path(&column.name));
+        if !mapping.columns.contains_key(&column.name) && key_element.is_some() {
+            continue;
+        }
+
+        let (parents, field) = match bound_field(ctx, message, &column.name, mapping) {
+            Some(bound) => bound,
+            None => {
+                orphaned.push(column.name.clone());
+                continue;
+            }
+        };
+        bound.push([&parents[..], &[field]].concat());
+
+        let bytes = mapping.bytes.get(&column.name).cloned();
+        let reason = match check_field(ctx, converters, field, &column.column_type, bytes) {
+            Ok(()) => match check_enum(ctx, field, &column.column_type, mapping.unknown_enums) {
+                Ok(missing) if missing.is_empty() => continue,
+                Ok(missing) => format!("missing enum values {}", missing.join(", ")),
+                Err(reason) => reason,
+            },
+            Err(reason) => reason,
+        };
+
+        let mut expected = field_type(ctx, converters, field, options.nullable);
+        if column.nullable {
+            expected = nullable(expected);
+        }
+
+        mismatched.push(MismatchedColumn {
+            name: column.name.clone(),
+            r#type: column.column_type.clone(),
+            lossy: lossy_modification(&column.column_type, &expected),
+            expected,
+            reason,
+        });
+    }
+
+    let missing = message_columns(ctx, converters, message, mapping, options)
+        .into_iter()
+        .filter(|column| {
+            !bound.iter().any(|path| {
+                path.iter()
+                    .zip(&column.path)
+                    .all(|(bound, field)| std::ptr::eq(*bound, *field))
+            })
+        })
+        .map(|column| (column.name, column.r#type))
+        .collect();
+
+    SchemaDiff {
+        proto: message.full_name.clone(),
+        table: table.parts.clone(),
+        exists: !table.columns.is_empty(),
+        missing,
+        mismatched,
+        orphaned,
+        options: options.clone(),
+    }
+}
+
+/// Whether modifying a column of type `from` to `to` may lose values, which is the
+/// case of every modification but widening integers and adding enum values.
+fn lossy_modification(from: &ColumnType, to: &ColumnType) -> bool {
+    match (from, to) {
+        (ColumnType::Nullable(from), ColumnType::Nullable(to))
+        | (ColumnType::Array(from), ColumnType::Array(to)) => lossy_modification(from, to),
+        (from, ColumnType::Nullable(to)) => lossy_modification(from, to),
+        (ColumnType::Map(from_key, from_value), ColumnType::Map(to_key, to_value)) => {
+            lossy_modification(from_key, to_key) || lossy_modification(from_value, to_value)
+        }
+        _ => match (from.enum_values(), to.enum_values()) {
+            (Some(from), Some(to)) => !from.iter().all(|value| to.contains(value)),
+            _ => match (from.integer_range(), to.integer_range()) {
+                (Some((from_min, from_max)), Some((to_min, to_max))) => {
+                    from_min < to_min || from_max > to_max
+                }
+                _ => from != to,
+            },
+        },
+    }
+}
+
+/// Renders the differences of every table, along with the statements fixing them.
+pub fn diff_report(diffs: &[SchemaDiff]) -> String {
+    let mut lines = vec![];
+
+    for diff in diffs {
+        let status = if !diff.exists {
+            ": missing table"
+        } else if diff.is_empty() && diff.orphaned.is_empty() {
+            ": up to date"
+        } else {
+            ""
+        };
+        lines.push(format!("{} ({}){}", diff.table, diff.proto, status));
+
+        if diff.exists {
+            for (name, r#type) in &diff.missing {
+                lines.push(format!("  missing column {} {}", name, r#type));
+            }
+        }
+        for column in &diff.mismatched {
+            lines.push(format!(
+                "  mismatched column {} {}: {}",
+                column.name, column.r#type, column.reason
+            ));
+        }
+        for name in &diff.orphaned {
+            lines.push(format!("  orphaned column {}", name));
+        }
+
+        if !diff.exists {
+            let query = create_table(&diff.table, &diff.missing, &diff.options);
+            lines.push(format!("  {};", query.replace('\n', "\n  ")));
+        }
+
+        let safe = diff.statements(false);
+        for statement in diff.statements(true) {
+            if safe.contains(&statement) {
+                lines.push(format!("  {};", statement));
+            } else {
+                lines.push(format!("  {}; -- may lose values", statement));
+            }
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// The mapping writing messages of type `proto` to `table`, or the mapping of the
+/// route doing so.
+pub fn table_mapping(mappings: &[Mapping], proto: &str, table: &str) -> Option<Mapping> {
+    for mapping in mappings {
+        if mapping.proto.as_deref() == Some(proto) && mapping.table.as_deref() == Some(table) {
+            return Some(mapping.clone());
+        }
+
+        if let Some(route) = mapping
+            .routes
+            .iter()
+            .find(|route| route.proto == proto && route.table == table)
+        {
+            return Some(mapping.route_mapping(route));
+        }
+    }
+
+    None
+}
+
+/// A column of the table of a message.
+pub struct MessageColumn<'a> {
+    pub name: String,
+    pub r#type: ColumnType,
+    /// Fields holding the field written to the column, outermost first, and the field.
+    pub path: Vec<&'a MessageField>,
+}
+
+/// The columns a message is written to. Fields bound to columns by the `columns`
+/// of the mapping are written to those, the other fields to the column named like
+/// them unless an expression or a key element of the mapping takes it. Columns of
+/// expressions and key elements are left out, their type being unknown.
+pub fn message_columns<'a>(
+    ctx: &'a Context,
+    converters: &Converters,
+    message: &'a MessageInfo,
+    mapping: &Mapping,
+    options: &TableOptions,
+) -> Vec<MessageColumn<'a>> {
+    let mut names: Vec<&String> = mapping.columns.keys().collect();
+    names.sort();
+    let renamed: Vec<(String, Vec<&MessageField>)> = names
+        .into_iter()
+        .filter_map(|name| {
+            let (parents, field) = bound_field(ctx, message, name, mapping)?;
+            Some((name.clone(), [&parents[..], &[field]].concat()))
+        })
+        .collect();
+
+    field_columns(ctx, converters, message, &[], mapping, &renamed, options)
+}
+
+fn field_columns<'a>(
+    ctx: &'a Context,
+    converters: &Converters,
+    message: &'a MessageInfo,
+    parents: &[&'a MessageField],
+    mapping: &Mapping,
+    renamed: &[(String, Vec<&'a MessageField>)],
+    options: &TableOptions,
+) -> Vec<MessageColumn<'a>> {
+    let mut columns = vec![];
+    let prefix: String = parents
+        .iter()
+        .map(|parent| {
+            format!(
+                "{}{}",
+                mapping.column_name(&parent.name),
+                mapping.separator()
+            )
+        })
+        .collect();
+
+    for field in message.iter_fields() {
+        let path = [parents, &[field]].concat();
+        let r#type = || {
+            let r#type = field_type(ctx, converters, field, options.nullable);
+            if options.nullable && !parents.is_empty() {
+                nullable(r#type)
+            } else {
+                r#type
+            }
+        };
+
+        let names: Vec<&String> = renamed
+            .iter()
+            .filter(|(_, bound)| {
+                bound.len() == path.len()
+                    && bound.iter().zip(&path).all(|(a, b)| std::ptr::eq(*a, *b))
+            })
+            .map(|(name, _)| name)
+            .collect();
+        if !names.is_empty() {
+            for name in names {
+                columns.push(MessageColumn {
+                    name: name.clone(),
+                    r#type: r#type(),
+                    path: path.clone(),
+                });
+            }
+            continue;
+        }
+
+        if parents.len() < options.flatten && !native_message(ctx, converters, field) {
+            if let Some(nested) = nested_message(ctx, &path, mapping) {
+                columns.extend(field_columns(
+                    ctx, converters, nested, &path, mapping, renamed, options,
+                ));
+                continue;
+            }
+        }
+
+        let name = format!("{}{}", prefix, mapping.column_name(&field.name));
+        let key_element = mapping.key.as_ref().and_then(|key| key.column_path(&name));
+        if mapping.columns.contains_key(&name)
+            || mapping.expressions.contains_key(&name)
+            || key_element.is_some()
+        {
+            continue;
+        }
+
+        columns.push(MessageColumn {
+            name,
+            r#type: r#type(),
+            path,
+        });
+    }
+
+    columns
+}
+
+/// The type of the column a field is written to.
+pub fn field_type(
+    ctx: &Context,
+    converters: &Converters,
+    field: &MessageField,
+    nullable: bool,
+) -> ColumnType {
+    if let Some((key_kind, value_kind)) = map_entry(ctx, field) {
+        return ColumnType::Map(
+            Box::new(kind_type(ctx, converters, &key_kind, false)),
+            Box::new(kind_type(ctx, converters, &value_kind, false)),
+        );
+    }
+
+    match field.multiplicity {
+        Multiplicity::Repeated | Multiplicity::RepeatedPacked => {
+            match field.field_type {
+                // Repeated messages are written as a JSON array
+                ValueType::Message(_) if !native_message(ctx, converters, field) => {
+                    ColumnType::String
+                }
+                _ => ColumnType::Array(Box::new(kind_type(
+                    ctx,
+                    converters,
+                    &field.field_type,
+                    false,
+                ))),
+            }
+        }
+        _ => kind_type(ctx, converters, &field.field_type, nullable),
+    }
+}
+
+/// The type of the column single values of a kind are written to.
+fn kind_type(
+    ctx: &Context,
+    converters: &Converters,
+    kind: &ValueType,
+    nullable: bool,
+) -> ColumnType {
+    match kind {
+        ValueType::Double => ColumnType::Float64,
+        ValueType::Float => ColumnType::Float32,
+        ValueType::Int32 | ValueType::SInt32 | ValueType::SFixed32 => ColumnType::Int32,
+        ValueType::Int64 | ValueType::SInt64 | ValueType::SFixed64 => ColumnType::Int64,
+        ValueType::UInt32 | ValueType::Fixed32 => ColumnType::UInt32,
+        ValueType::UInt64 | ValueType::Fixed64 => ColumnType::UInt64,
+        ValueType::Bool => ColumnType::Bool,
+        ValueType::String | ValueType::Bytes => ColumnType::String,
+        ValueType::Enum(enum_ref) => enum_type(ctx.resolve_enum(*enum_ref)),
+        ValueType::Message(msg_ref) => {
+            let info = ctx.resolve_message(*msg_ref);
+            match message_type(ctx, converters, info, nullable) {
+                Some(r#type) => r#type,
+                None => ColumnType::String,
+            }
+        }
+    }
+}
+
+/// The type of the column of the messages converted to a native value, `None`
+/// for the messages written as JSON.
+fn message_type(
+    ctx: &Context,
+    converters: &Converters,
+    info: &MessageInfo,
+    nullable: bool,
+) -> Option<ColumnType> {
+    if let Some(converter) = converters.get(&info.full_name) {
+        return converter.column_type();
+    }
+
+    Some(match info.full_name.as_str() {
+        "google.protobuf.Timestamp" | "protos.Timestamp" => ColumnType::DateTime64 {
+            precision: 9,
+            timezone: None,
+        },
+        "google.protobuf.Duration" => ColumnType::Int64,
+        "google.protobuf.FieldMask" => ColumnType::Array(Box::new(ColumnType::String)),
+        _ if is_wrapper(info) => {
+            let inner = match info.get_field(1) {
+                Some(field) => kind_type(ctx, converters, &field.field_type, false),
+                None => ColumnType::String,
+            };
+
+            if nullable {
+                ColumnType::Nullable(Box::new(inner))
+            } else {
+                inner
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// Whether a field holds messages converted to a native value or well-known types,
+/// which are not flattened. Messages wrapped in an Any are flattened like others.
+fn native_message(ctx: &Context, converters: &Converters, field: &MessageField) -> bool {
+    let info = match field.field_type {
+        ValueType::Message(msg_ref) => ctx.resolve_message(msg_ref),
+        _ => return false,
+    };
+
+    converters.get(&info.full_name).is_some()
+        || (info.full_name.starts_with("google.protobuf.") && info.full_name != ANY)
+}
+
+/// An `Enum8` or `Enum16` of the values of an enum, or `Int32` when they don't fit.
+/// Aliases of a value are left out.
+fn enum_type(info: &EnumInfo) -> ColumnType {
+    let mut values: Vec<(String, i64)> = vec![];
+    for field in info.iter_fields() {
+        if !values.iter().any(|(_, number)| *number == field.value) {
+            values.push((field.name.clone(), field.value));
+        }
+    }
+
+    let fits = |min: i64, max: i64| values.iter().all(|(_, n)| *n >= min && *n <= max);
+    if values.is_empty() {
+        ColumnType::Int32
+    } else if fits(i8::MIN as i64, i8::MAX as i64) {
+        ColumnType::Enum8(values)
+    } else if fits(i16::MIN as i64, i16::MAX as i64) {
+        ColumnType::Enum16(values)
+    } else {
+        ColumnType::Int32
+    }
+}
+
+/// Wraps a type in `Nullable`, unless it can't be.
+pub fn nullable(r#type: ColumnType) -> ColumnType {
+    match r#type {
+        ColumnType::Nullable(_)
+        | ColumnType::Array(_)
+        | ColumnType::Map(_, _)
+        | ColumnType::Tuple(_)
+        | ColumnType::Nested(_) => r#type,
+        _ => ColumnType::Nullable(Box::new(r#type)),
+    }
+}
+
+/// A `CREATE TABLE` query creating a table of the columns.
+pub fn create_table(
+    table: &ClickhouseTableParts,
+    columns: &[(String, ColumnType)],
+    options: &TableOptions,
+) -> String {
+    let engine = options.engine.as_deref().unwrap_or(DEFAULT_ENGINE);
+
+    let mut query = format!("CREATE TABLE {}\n(\n", table.quoted());
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|(name, r#type)| format!("    {} {}", quote_name(name), r#type))
+        .collect();
+    query.push_str(&columns.join(",\n"));
+    query.push_str(&format!("\n)\nENGINE = {}", engine));
+
+    if let Some(partition_by) = &options.partition_by {
+        query.push_str(&format!("\nPARTITION BY {}", partition_by));
+    }
+
+    // Only the MergeTree family has a sorting key, which it requires
+    match &options.order_by {
+        Some(order_by) => query.push_str(&format!("\nORDER BY {}", order_by)),
+        None if engine.contains("MergeTree") => query.push_str("\nORDER BY tuple()"),
+        None => {}
+    }
+
+    query
+}
@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use futures::StreamExt;
+use protofish::prelude::Context;
+use tracing::*;
+
+use crate::checkpoint::{key_after, Checkpoint, CheckpointStore};
+use crate::config::Mapping;
+use crate::context::AppContext;
+use crate::error::Error;
+use crate::export::{export_mapping, BatchWriter, TableWriter};
+use crate::fdb::{is_retryable, prefix_end, KeySource};
+use crate::result::Result;
+
+/// Position in the changelog of a single mapping.
+pub struct ChangelogTail {
+    pub id: String,
+    /// Range of the keys of the mapping, changes to other keys are ignored.
+    pub range: (Vec<u8>, Vec<u8>),
+    pub prefix: Vec<u8>,
+    pub cursor: Vec<u8>,
+    pub messages_written: usize,
+}
+
+/// Exports every mapping that has a changelog and then keeps writing the keys
+/// recorded in the changelogs until interrupted.
+///
+/// Applications record a change by setting a versionstamped key under the
+/// changelog prefix with the changed key as its value. The current value of
+/// every changed key is written again, so target tables are expected to
+/// deduplicate rows (e.g. with a ReplacingMergeTree engine).
+///
+/// Deleted keys are not propagated, as rows can't be told apart by key in
+/// clickhouse, and changelog entries are never cleared, as other mappings or
+/// readers may share the changelog.
+pub async fn sync_mappings(
+    context: &'static AppContext<'static>,
+    proto_context: &'static Context,
+    mapping: &[Mapping],
+    interval: Duration,
+    workers: usize,
+) -> Result<()> {
+    let checkpoints = &context.checkpoints;
+    let source = context.fdb_client.as_ref();
+
+    let mut tails = vec![];
+
+    for map in mapping {
+        let prefix = match map.changelog_prefix()? {
+            Some(prefix) => prefix,
+            None => {
+                info!("Skipping mapping without a changelog: {}", map.id());
+                continue;
+            }
+        };
+
+        let id = format!("{}:changelog", map.id());
+        let range = context.fdb_client.mapping_range(map)?;
+
+        // The tail is checkpointed before exporting, so no change made while
+        // exporting is missed when restarting
+        let tail = ChangelogTail::open(checkpoints, source, id, prefix, range).await?;
+
+        export_mapping(context, proto_context, map, true, workers).await?;
+
+        tails.push((map, tail));
+    }
+
+    if tails.is_empty() {
+        return Err(Error::MissingConfig(
+            "No mapping has a changelog to sync from".into(),
+        ));
+    }
+
+    info!("Initial export complete, syncing changes");
+
+    loop {
+        for (map, tail) in tails.iter_mut() {
+            let router = match context.proto_registry.get(&map.id()) {
+                Some(router) => router,
+                None => continue,
+            };
+            let writer = TableWriter {
+                context,
+                proto_context,
+                router,
+            };
+
+            let written = tail.poll(checkpoints, source, &writer).await?;
+            if written > 0 {
+                info!("{} changed messages written for {}", written, map.id());
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!("Stopping sync");
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl ChangelogTail {
+    /// Opens the tail of the changelog under `prefix` from its checkpoint `id`.
+    /// Without a checkpoint, the tail starts after the last entry of the changelog
+    /// and is checkpointed there right away.
+    pub async fn open(
+        checkpoints: &CheckpointStore,
+        source: &dyn KeySource,
+        id: String,
+        prefix: Vec<u8>,
+        range: (Vec<u8>, Vec<u8>),
+    ) -> Result<ChangelogTail> {
+        if let Some(checkpoint) = checkpoints.get(&id).await? {
+            return Ok(ChangelogTail {
+                id,
+                range,
+                prefix,
+                cursor: checkpoint.resume_key(),
+                messages_written: checkpoint.messages_written,
+            });
+        }
+
+        // An empty changelog is read from the key after its prefix, which sorts
+        // before every versionstamped entry under it
+        let last_key = match source.last_key(&prefix, &prefix_end(&prefix)).await? {
+            Some(last_key) => last_key,
+            None => prefix.clone(),
+        };
+
+        let checkpoint = Checkpoint {
+            last_key,
+            messages_written: 0,
+            completed: false,
+            shards: vec![],
+            partial: None,
+        };
+        checkpoints.save(&id, &checkpoint).await?;
+
+        Ok(ChangelogTail {
+            id,
+            range,
+            prefix,
+            cursor: checkpoint.resume_key(),
+            messages_written: 0,
+        })
+    }
+
+    /// Writes the current value of every key recorded in the changelog since the
+    /// last read, in the order of the changelog, and checkpoints the entries read.
+    /// Returns the number of messages written.
+    pub async fn poll(
+        &mut self,
+        checkpoints: &CheckpointStore,
+        source: &dyn KeySource,
+        writer: &dyn BatchWriter,
+    ) -> Result<usize> {
+        let mut entries = source.read_range(self.cursor.clone(), prefix_end(&self.prefix));
+
+        let mut messages_written = 0;
+
+        while let Some(batch) = entries.next().await {
+            let batch = match batch {
+                Ok(batch) => batch,
+                // The remaining entries are read on the next poll
+                Err(e) if is_retryable(&e) => break,
+                Err(e) => return Err(e),
+            };
+
+            let last_entry_key = match batch.last() {
+                Some((key, _)) => key.clone(),
+                None => continue,
+            };
+
+            let (from, to) = &self.range;
+            let mut changed_keys: Vec<Vec<u8>> = vec![];
+            for (_, key) in batch {
+                if key >= *from && key < *to && !changed_keys.contains(&key) {
+                    changed_keys.push(key);
+                }
+            }
+
+            let values = source.get_values(&changed_keys).await?;
+
+            let mut messages = vec![];
+            for (key, value) in changed_keys.into_iter().zip(values) {
+                match value {
+                    Some(value) => messages.push((key, value)),
+                    None => debug!("Skipping changed key as it has been deleted: {:?}", key),
+                }
+            }
+
+            // A batch failing partway is written again to every route, which the
+            // deduplicating tables absorb
+            let written = writer.write(&messages, &mut vec![]).await?.written;
+
+            messages_written += written;
+            self.messages_written += written;
+
+            checkpoints
+                .save(
+                    &self.id,
+                    &Checkpoint {
+                        last_key: last_entry_key.clone(),
+                        messages_written: self.messages_written,
+                        completed: false,
+                        shards: vec![],
+                        partial: None,
+                    },
+                )
+                .await?;
+
+            self.cursor = key_after(&last_entry_key);
+        }
+
+        Ok(messages_written)
+    }
+}
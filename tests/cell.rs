@@ -0,0 +1,38 @@
+use fdb_ch_proto_export::cell::{base64, hex, Cell};
+use fdb_ch_proto_export::config::BytesEncoding;
+
+#[test]
+fn hex_bytes() {
+    assert_eq!(hex(&[]), "");
+    assert_eq!(hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+}
+
+#[test]
+fn base64_bytes() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+}
+
+#[test]
+fn encode_bytes() {
+    let bytes = Cell::Bytes(vec![0xde, 0xad]);
+
+    assert_eq!(
+        bytes.clone().encode_bytes(BytesEncoding::Hex),
+        Cell::String("dead".into())
+    );
+    assert_eq!(
+        bytes.clone().encode_bytes(BytesEncoding::Base64),
+        Cell::String("3q0=".into())
+    );
+    assert_eq!(bytes.clone().encode_bytes(BytesEncoding::Raw), bytes);
+    assert_eq!(
+        Cell::Array(vec![bytes, Cell::Bytes(vec![])]).encode_bytes(BytesEncoding::Hex),
+        Cell::Array(vec!["dead".into(), "".into()])
+    );
+    assert_eq!(Cell::Int(1).encode_bytes(BytesEncoding::Hex), Cell::Int(1));
+}
@@ -0,0 +1,160 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use fdb_ch_proto_export::checkpoint::{
+    key_after, Checkpoint, FdbCheckpointStore, FileCheckpointStore,
+};
+use fdb_ch_proto_export::fdb::FdbClient;
+
+fn state_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "fdb-ch-proto-export-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn batch_checkpoint(last_key: &[u8], messages_written: usize) -> Checkpoint {
+    Checkpoint {
+        last_key: last_key.to_vec(),
+        messages_written,
+        completed: false,
+        shards: vec![],
+        partial: None,
+    }
+}
+
+#[test]
+fn resumes_after_last_written_batch() {
+    let path = state_file("resume");
+    let keys: Vec<&[u8]> = vec![b"users/001", b"users/002", b"users/003", b"users/004"];
+
+    {
+        let store = FileCheckpointStore::open(&path).unwrap();
+
+        // First batch is written and checkpointed
+        store
+            .save("protos.User:default.users", &batch_checkpoint(keys[1], 2))
+            .unwrap();
+
+        // Second batch fails to write, so the process dies before checkpointing it
+    }
+
+    let store = FileCheckpointStore::open(&path).unwrap();
+    let checkpoint = store.get("protos.User:default.users").unwrap();
+
+    assert_eq!(checkpoint.messages_written, 2);
+    assert!(!checkpoint.completed);
+
+    let resume_key = checkpoint.resume_key();
+    let remaining: Vec<&[u8]> = keys
+        .into_iter()
+        .filter(|key| *key >= resume_key.as_slice())
+        .collect();
+
+    assert_eq!(remaining, vec![b"users/003" as &[u8], b"users/004"]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn keeps_checkpoints_per_mapping() {
+    let path = state_file("per-mapping");
+
+    let store = FileCheckpointStore::open(&path).unwrap();
+    store
+        .save(
+            "protos.User:default.users",
+            &batch_checkpoint(b"users/001", 1),
+        )
+        .unwrap();
+    store
+        .save(
+            "protos.Order:default.orders",
+            &batch_checkpoint(b"orders/009", 9),
+        )
+        .unwrap();
+    store.clear("protos.User:default.users").unwrap();
+
+    let store = FileCheckpointStore::open(&path).unwrap();
+    assert_eq!(store.get("protos.User:default.users"), None);
+    assert_eq!(
+        store.get("protos.Order:default.orders"),
+        Some(batch_checkpoint(b"orders/009", 9))
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn missing_state_file_has_no_checkpoints() {
+    let path = state_file("missing");
+
+    let store = FileCheckpointStore::open(&path).unwrap();
+    assert_eq!(store.get("protos.User:default.users"), None);
+    assert!(!path.exists());
+}
+
+#[test]
+fn key_after_sorts_between_adjacent_keys() {
+    let next = key_after(b"users/001");
+
+    assert!(next.as_slice() > b"users/001".as_ref());
+    assert!(next.as_slice() < b"users/0010".as_ref());
+}
+
+/// Needs a running cluster, whose cluster file is given by `FDB_CLUSTER_FILE`.
+#[tokio::test]
+async fn fdb_store_keeps_checkpoints_per_mapping() {
+    let cluster_file = match std::env::var("FDB_CLUSTER_FILE") {
+        Ok(cluster_file) => cluster_file,
+        Err(_) => {
+            eprintln!("FDB_CLUSTER_FILE is not set, skipping");
+            return;
+        }
+    };
+
+    let _network = unsafe { FdbClient::start_network() }.unwrap();
+    let client = Arc::new(FdbClient::new(&cluster_file).unwrap());
+
+    let prefix = format!("fdb-ch-proto-export-test-{}", std::process::id());
+    let store = FdbCheckpointStore::new(client, prefix.as_bytes());
+
+    store
+        .save(
+            "protos.User:default.users",
+            &batch_checkpoint(b"users/001", 1),
+        )
+        .await
+        .unwrap();
+    store
+        .save(
+            "protos.Order:default.orders",
+            &batch_checkpoint(b"orders/009", 9),
+        )
+        .await
+        .unwrap();
+    store
+        .save(
+            "protos.User:default.users",
+            &batch_checkpoint(b"users/002", 2),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        store.get("protos.User:default.users").await.unwrap(),
+        Some(batch_checkpoint(b"users/002", 2))
+    );
+
+    store.clear("protos.User:default.users").await.unwrap();
+    store.clear("protos.Order:default.orders").await.unwrap();
+
+    assert_eq!(store.get("protos.User:default.users").await.unwrap(), None);
+    assert_eq!(
+        store.get("protos.Order:default.orders").await.unwrap(),
+        None
+    );
+}
@@ -0,0 +1,510 @@
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::clickhouse_format::{encode_value, json_value, sql_literal};
+
+fn encode(r#type: &str, value: Cell) -> Vec<u8> {
+    let mut buf = vec![];
+    encode_value(&mut buf, &r#type.parse().unwrap(), &value).unwrap();
+    buf
+}
+
+fn encode_err(r#type: &str, value: Cell) -> bool {
+    encode_value(&mut vec![], &r#type.parse().unwrap(), &value).is_err()
+}
+
+fn literal(r#type: &str, value: Cell) -> String {
+    sql_literal(&r#type.parse().unwrap(), &value).unwrap()
+}
+
+#[test]
+fn string() {
+    assert_eq!(encode("String", "abc".into()), b"\x03abc");
+    assert_eq!(
+        encode("String", Cell::Bytes(vec![0, 0xff])),
+        b"\x02\x00\xff"
+    );
+    assert_eq!(encode("String", Cell::Int(-12)), b"\x03-12");
+    assert_eq!(encode("String", "x".repeat(200).into())[..2], [0xc8, 0x01]);
+
+    assert_eq!(literal("String", "O'Brien \\".into()), "'O\\'Brien \\\\'");
+    assert_eq!(literal("String", Cell::Bytes(vec![b'a', 0xff])), "'a\\xFF'");
+    assert!(encode_err("String", Cell::Null));
+}
+
+#[test]
+fn nested_message_as_json() {
+    let value = Cell::Message(vec![
+        ("name".to_string(), "O'Brien".into()),
+        ("age".to_string(), Cell::UInt(42)),
+        (
+            "tags".to_string(),
+            Cell::Array(vec!["a".into(), "b".into()]),
+        ),
+    ]);
+
+    assert_eq!(
+        json_value(&value).to_string(),
+        r#"{"age":42,"name":"O'Brien","tags":["a","b"]}"#
+    );
+    assert_eq!(
+        literal("String", value),
+        r#"'{"age":42,"name":"O\'Brien","tags":["a","b"]}'"#
+    );
+}
+
+#[test]
+fn unsigned_integers() {
+    assert_eq!(encode("UInt8", Cell::UInt(255)), [0xff]);
+    assert_eq!(encode("UInt16", Cell::UInt(0x0102)), [0x02, 0x01]);
+    assert_eq!(encode("UInt32", Cell::Int(1)), [1, 0, 0, 0]);
+    assert_eq!(encode("UInt64", Cell::UInt(u64::MAX)), [0xff; 8]);
+    assert_eq!(encode("UInt32", "7".into()), [7, 0, 0, 0]);
+
+    assert!(encode_err("UInt8", Cell::UInt(256)));
+    assert!(encode_err("UInt64", Cell::Int(-1)));
+    assert!(encode_err("UInt32", Cell::Float(1.5)));
+    assert_eq!(
+        literal("UInt64", Cell::UInt(u64::MAX)),
+        u64::MAX.to_string()
+    );
+}
+
+#[test]
+fn signed_integers() {
+    assert_eq!(encode("Int8", Cell::Int(-1)), [0xff]);
+    assert_eq!(encode("Int16", Cell::Int(-2)), [0xfe, 0xff]);
+    assert_eq!(encode("Int32", Cell::Int(i32::MIN as i64)), [0, 0, 0, 0x80]);
+    assert_eq!(encode("Int64", Cell::Float(3.0)), 3i64.to_le_bytes());
+
+    assert!(encode_err("Int8", Cell::Int(128)));
+    assert!(encode_err("Int64", Cell::UInt(u64::MAX)));
+    assert_eq!(literal("Int32", Cell::Int(-5)), "-5");
+}
+
+#[test]
+fn bool() {
+    assert_eq!(encode("Bool", Cell::Bool(true)), [1]);
+    assert_eq!(encode("UInt8", Cell::Bool(false)), [0]);
+    assert_eq!(encode("Bool", "true".into()), [1]);
+    assert!(encode_err("Bool", Cell::Int(2)));
+    assert_eq!(literal("Bool", Cell::Bool(true)), "1");
+}
+
+#[test]
+fn floats() {
+    assert_eq!(encode("Float32", Cell::Float(1.5)), 1.5f32.to_le_bytes());
+    assert_eq!(
+        encode("Float64", Cell::Float(-0.25)),
+        (-0.25f64).to_le_bytes()
+    );
+    assert_eq!(encode("Float64", Cell::Int(2)), 2f64.to_le_bytes());
+    assert!(encode_err("Float64", Cell::Bytes(vec![])));
+    assert_eq!(literal("Float64", Cell::Float(0.5)), "0.5");
+}
+
+#[test]
+fn uuid() {
+    let value: Cell = "00112233-4455-6677-8899-aabbccddeeff".into();
+    assert_eq!(
+        encode("UUID", value.clone()),
+        [
+            0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, 0x00, 0xff, 0xee, 0xdd, 0xcc, 0xbb, 0xaa,
+            0x99, 0x88
+        ]
+    );
+    assert!(encode_err("UUID", "not-a-uuid".into()));
+    assert_eq!(
+        literal("UUID", value.clone()),
+        "'00112233-4455-6677-8899-aabbccddeeff'"
+    );
+
+    // 16 bytes are read in network order
+    let bytes = Cell::Bytes((0..16).map(|i| i * 0x11).collect());
+    assert_eq!(encode("UUID", bytes.clone()), encode("UUID", value));
+    assert_eq!(
+        literal("UUID", bytes),
+        "'00112233-4455-6677-8899-aabbccddeeff'"
+    );
+    assert!(encode_err("UUID", Cell::Bytes(vec![0; 15])));
+}
+
+#[test]
+fn datetime() {
+    let value = Cell::DateTime {
+        seconds: 1_600_000_000,
+        nanos: 123_456_789,
+    };
+
+    assert_eq!(
+        encode("DateTime", value.clone()),
+        1_600_000_000u32.to_le_bytes()
+    );
+    assert_eq!(encode("DateTime('UTC')", Cell::Int(1)), 1u32.to_le_bytes());
+    assert!(encode_err("DateTime", Cell::Int(-1)));
+    assert_eq!(literal("DateTime", value), "'1600000000'");
+}
+
+#[test]
+fn date() {
+    let day = Cell::DateTime {
+        seconds: 19_000 * 86_400 + 3_600,
+        nanos: 0,
+    };
+
+    assert_eq!(encode("Date", day.clone()), 19_000u16.to_le_bytes());
+    assert_eq!(encode("Date", Cell::UInt(1)), 1u16.to_le_bytes());
+    assert_eq!(encode("Date32", day.clone()), 19_000i32.to_le_bytes());
+    assert_eq!(encode("Date32", Cell::Int(-1)), (-1i32).to_le_bytes());
+    // Times before 1970 belong to the day they fall in
+    let before = Cell::DateTime {
+        seconds: -1,
+        nanos: 0,
+    };
+    assert_eq!(encode("Date32", before.clone()), (-1i32).to_le_bytes());
+
+    assert_eq!(literal("Date", day), "'2022-01-08'");
+    assert_eq!(literal("Date", Cell::UInt(0)), "'1970-01-01'");
+    assert_eq!(literal("Date32", before), "'1969-12-31'");
+    assert_eq!(literal("Date32", Cell::Int(-25_567)), "'1900-01-01'");
+    assert_eq!(literal("Date32", Cell::Int(120_529)), "'2299-12-31'");
+    assert_eq!(literal("Date32", Cell::Int(11_016)), "'2000-02-29'");
+
+    assert!(encode_err("Date", Cell::Int(-1)));
+    assert!(encode_err("Date", Cell::UInt(65_536)));
+    assert!(encode_err("Date32", Cell::Int(-25_568)));
+    assert!(encode_err("Date32", Cell::Int(120_530)));
+}
+
+#[test]
+fn json() {
+    let value = Cell::Message(vec![("name".to_string(), "O'Brien".into())]);
+
+    // JSON columns have no RowBinary encoding and are inserted as text
+    assert!(encode_err("JSON", value.clone()));
+    assert!(encode_err("Object('json')", value.clone()));
+    assert_eq!(literal("JSON", value.clone()), r#"'{"name":"O\'Brien"}'"#);
+    assert_eq!(literal("Object('json')", value), r#"'{"name":"O\'Brien"}'"#);
+}
+
+#[test]
+fn datetime64() {
+    let value = Cell::DateTime {
+        seconds: 1_600_000_000,
+        nanos: 123_456_789,
+    };
+
+    assert_eq!(
+        encode("DateTime64(3)", value.clone()),
+        1_600_000_000_123i64.to_le_bytes()
+    );
+    assert_eq!(
+        encode("DateTime64(9, 'UTC')", value.clone()),
+        1_600_000_000_123_456_789i64.to_le_bytes()
+    );
+    assert_eq!(
+        encode("DateTime64(6)", Cell::Int(2)),
+        2_000_000i64.to_le_bytes()
+    );
+    assert_eq!(literal("DateTime64(3)", value), "'1600000000.123'");
+
+    // -1.5 s, normalized to a negative second and a positive fraction
+    let negative = Cell::DateTime {
+        seconds: -2,
+        nanos: 500_000_000,
+    };
+    assert_eq!(
+        encode("DateTime64(9)", negative.clone()),
+        (-1_500_000_000i64).to_le_bytes()
+    );
+    assert_eq!(literal("DateTime64(9)", negative.clone()), "'-1.500000000'");
+    // Ticks of a lower precision round towards the past, like the cell
+    assert_eq!(literal("DateTime64(0)", negative), "'-2'");
+    assert_eq!(
+        literal(
+            "DateTime64(3)",
+            Cell::DateTime {
+                seconds: -1,
+                nanos: 750_000_000
+            }
+        ),
+        "'-0.250'"
+    );
+
+    // The last second of a Timestamp, 9999-12-31T23:59:59Z, only fits lower precisions
+    let max = Cell::DateTime {
+        seconds: 253_402_300_799,
+        nanos: 999_999_999,
+    };
+    assert_eq!(
+        encode("DateTime64(3)", max.clone()),
+        253_402_300_799_999i64.to_le_bytes()
+    );
+    assert!(encode_err("DateTime64(9)", max.clone()));
+    assert!(sql_literal(&"DateTime64(9)".parse().unwrap(), &max).is_err());
+    assert!(encode_err(
+        "DateTime64(9)",
+        Cell::DateTime {
+            seconds: -62_135_596_800,
+            nanos: 0
+        }
+    ));
+
+    // Nanoseconds since the epoch overflow in 2262
+    assert!(encode_err("DateTime64(9)", Cell::Int(9_300_000_000)));
+    assert_eq!(
+        encode("DateTime64(9)", Cell::Int(9_200_000_000)),
+        9_200_000_000_000_000_000i64.to_le_bytes()
+    );
+}
+
+#[test]
+fn duration() {
+    let value = Cell::Duration {
+        seconds: 1,
+        nanos: 500_000_000,
+    };
+
+    assert_eq!(
+        encode("Int64", value.clone()),
+        1_500_000_000i64.to_le_bytes()
+    );
+    assert_eq!(encode("Float64", value.clone()), 1.5f64.to_le_bytes());
+    assert_eq!(encode("String", value.clone()), b"\x061.500s");
+    assert_eq!(json_value(&value).to_string(), r#""1.500s""#);
+
+    let negative = Cell::Duration {
+        seconds: -2,
+        nanos: -1_000,
+    };
+    assert_eq!(literal("Int64", negative.clone()), "-2000001000");
+    assert_eq!(literal("String", negative), "'-2.000001s'");
+    assert!(encode_err("Int8", value));
+}
+
+#[test]
+fn decimal() {
+    let value = Cell::Decimal {
+        value: 12_500_000_000,
+        scale: 9,
+    };
+
+    assert_eq!(
+        encode("Decimal(18, 9)", value.clone()),
+        12_500_000_000i64.to_le_bytes()
+    );
+    assert_eq!(
+        encode("Decimal(9, 2)", value.clone()),
+        1250i32.to_le_bytes()
+    );
+    assert_eq!(
+        encode("Decimal32(2)", Cell::Int(-3)),
+        (-300i32).to_le_bytes()
+    );
+    assert_eq!(
+        encode("Decimal128(1)", "-1.25".into()),
+        (-12i128).to_le_bytes()
+    );
+    assert_eq!(
+        encode("Decimal64(3)", Cell::Float(0.5)),
+        500i64.to_le_bytes()
+    );
+
+    assert_eq!(literal("Decimal(18, 9)", value.clone()), "12.500000000");
+    assert_eq!(literal("String", value.clone()), "'12.500000000'");
+    assert_eq!(json_value(&value).to_string(), r#""12.500000000""#);
+    assert_eq!(encode("Float64", value.clone()), 12.5f64.to_le_bytes());
+    assert!(encode_err("Int64", value.clone()));
+
+    assert!(encode_err("Decimal(3, 2)", value.clone()));
+    assert!(encode_err("Decimal(40, 2)", value));
+    assert!(encode_err("Decimal(38, 38)", Cell::Int(i64::MAX)));
+    assert!(encode_err(
+        "Decimal(38, 38)",
+        "1".repeat(30).as_str().into()
+    ));
+    assert_eq!(
+        encode(
+            "Decimal(9, 0)",
+            Cell::Decimal {
+                value: 7,
+                scale: 38
+            }
+        ),
+        0i32.to_le_bytes()
+    );
+}
+
+#[test]
+fn enums() {
+    let r#type = "Enum8('ACTIVE' = 1, 'DISABLED' = -2)";
+    let active = Cell::Enum {
+        name: Some("ACTIVE".to_string()),
+        number: 5,
+    };
+
+    assert_eq!(encode(r#type, active.clone()), [1]);
+    assert_eq!(encode(r#type, "DISABLED".into()), [0xfe]);
+    assert_eq!(
+        encode(
+            r#type,
+            Cell::Enum {
+                name: None,
+                number: 3
+            }
+        ),
+        [3]
+    );
+    assert_eq!(
+        encode("Enum16('A' = 1000)", "A".into()),
+        1000i16.to_le_bytes()
+    );
+    assert!(encode_err(r#type, "UNKNOWN".into()));
+
+    assert_eq!(literal(r#type, active), "'ACTIVE'");
+    assert_eq!(literal(r#type, Cell::Int(-2)), "'DISABLED'");
+}
+
+#[test]
+fn fixed_string() {
+    assert_eq!(encode("FixedString(4)", "ab".into()), b"ab\0\0");
+    assert_eq!(encode("FixedString(2)", Cell::Bytes(vec![1, 2])), [1, 2]);
+    assert!(encode_err("FixedString(2)", "abc".into()));
+    assert_eq!(literal("FixedString(4)", "ab".into()), "'ab'");
+}
+
+#[test]
+fn nullable() {
+    assert_eq!(encode("Nullable(String)", Cell::Null), [1]);
+    assert_eq!(encode("Nullable(String)", "a".into()), b"\x00\x01a");
+    assert_eq!(encode("Nullable(Int32)", Cell::Int(1)), [0, 1, 0, 0, 0]);
+    assert_eq!(literal("Nullable(Int32)", Cell::Null), "NULL");
+    assert_eq!(literal("Nullable(Int32)", Cell::Int(1)), "1");
+}
+
+#[test]
+fn low_cardinality() {
+    assert_eq!(encode("LowCardinality(String)", "a".into()), b"\x01a");
+    assert_eq!(encode("LowCardinality(Nullable(String))", Cell::Null), [1]);
+    assert_eq!(literal("LowCardinality(String)", "a".into()), "'a'");
+}
+
+#[test]
+fn array() {
+    let tags = Cell::Array(vec!["a".into(), "bc".into()]);
+
+    assert_eq!(encode("Array(String)", tags.clone()), b"\x02\x01a\x02bc");
+    assert_eq!(
+        encode("Array(Int64)", Cell::Array(vec![Cell::Int(-1)])),
+        [&[1u8][..], &(-1i64).to_le_bytes()].concat()
+    );
+    assert_eq!(encode("Array(UInt32)", Cell::Array(vec![])), [0]);
+    assert_eq!(
+        encode(
+            "Array(Nullable(String))",
+            Cell::Array(vec![Cell::Null, "a".into()])
+        ),
+        b"\x02\x01\x00\x01a"
+    );
+    assert_eq!(
+        encode(
+            "Array(Array(UInt8))",
+            Cell::Array(vec![Cell::Array(vec![Cell::UInt(1), Cell::UInt(2)])])
+        ),
+        [1, 2, 1, 2]
+    );
+
+    assert!(encode_err("Array(UInt8)", Cell::Array(vec![Cell::Int(-1)])));
+    assert!(encode_err("Array(String)", "a".into()));
+
+    assert_eq!(literal("Array(String)", tags.clone()), "['a','bc']");
+    assert_eq!(
+        literal(
+            "Array(Int32)",
+            Cell::Array(vec![Cell::Int(1), Cell::Int(2)])
+        ),
+        "[1,2]"
+    );
+    assert_eq!(literal("String", tags), r#"'["a","bc"]'"#);
+}
+
+#[test]
+fn map() {
+    let counts = Cell::Map(vec![("a".into(), Cell::Int(1)), ("b".into(), Cell::Int(2))]);
+
+    assert_eq!(
+        encode("Map(String, UInt8)", counts.clone()),
+        b"\x02\x01a\x01\x01b\x02"
+    );
+    assert_eq!(
+        encode(
+            "Map(UInt16, String)",
+            Cell::Map(vec![(Cell::UInt(1), "x".into())])
+        ),
+        b"\x01\x01\x00\x01x"
+    );
+    assert_eq!(encode("Map(String, String)", Cell::Map(vec![])), [0]);
+    assert!(encode_err("Map(String, UInt8)", Cell::Array(vec![])));
+
+    assert_eq!(
+        literal("Map(String, Int64)", counts.clone()),
+        "{'a':1,'b':2}"
+    );
+    assert_eq!(literal("String", counts), r#"'{"a":1,"b":2}'"#);
+}
+
+#[test]
+fn tuple() {
+    let entry = Cell::Tuple(vec!["a".into(), Cell::Int(-1)]);
+
+    assert_eq!(encode("Tuple(String, Int8)", entry.clone()), b"\x01a\xff");
+    assert_eq!(
+        encode(
+            "Array(Tuple(String, Int8))",
+            Cell::Array(vec![entry.clone()])
+        ),
+        b"\x01\x01a\xff"
+    );
+    assert!(encode_err("Tuple(String)", entry.clone()));
+
+    assert_eq!(literal("Tuple(String, Int8)", entry.clone()), "('a',-1)");
+    assert_eq!(
+        literal("Array(Tuple(String, Int8))", Cell::Array(vec![entry])),
+        "[('a',-1)]"
+    );
+}
+
+#[test]
+fn unsupported_types() {
+    assert!(encode_err("Int256", Cell::Int(1)));
+    // Dates are not parsed from text
+    assert!(encode_err("Date", "2021-01-01".into()));
+}
+
+#[test]
+fn ip_addresses() {
+    assert_eq!(encode("IPv4", "1.2.3.4".into()), [4, 3, 2, 1]);
+    assert_eq!(encode("IPv4", Cell::UInt(0x01020304)), [4, 3, 2, 1]);
+    assert_eq!(encode("IPv4", Cell::Bytes(vec![1, 2, 3, 4])), [4, 3, 2, 1]);
+    assert!(encode_err("IPv4", "::1".into()));
+
+    let mut loopback = vec![0; 15];
+    loopback.push(1);
+    assert_eq!(encode("IPv6", "::1".into()), loopback);
+    assert_eq!(encode("IPv6", Cell::Bytes(loopback.clone())), loopback);
+
+    assert_eq!(literal("IPv4", Cell::UInt(0x01020304)), "'1.2.3.4'");
+    assert_eq!(literal("IPv6", Cell::Bytes(loopback)), "'::1'");
+}
+
+#[test]
+fn nested() {
+    let rows = Cell::Array(vec![Cell::Tuple(vec!["a".into(), Cell::Int(1)])]);
+
+    assert_eq!(
+        encode("Nested(name String, value Int8)", rows.clone()),
+        b"\x01\x01a\x01"
+    );
+    assert_eq!(
+        literal("Nested(name String, value Int8)", rows),
+        "[('a',1)]"
+    );
+}
@@ -0,0 +1,295 @@
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_message_binding::bind_proto_message;
+use fdb_ch_proto_export::clickhouse_table::{ClickhouseTableParts, Table};
+use fdb_ch_proto_export::config::Mapping;
+use fdb_ch_proto_export::converter::Converters;
+use protofish::prelude::Context;
+
+const PROTO: &str = r#"
+syntax = "proto3";
+package shop;
+
+message Point {
+  double x = 1;
+  double y = 2;
+}
+
+message Address {
+  string city = 1;
+  string zip_code = 2;
+  Point location = 3;
+}
+
+message User {
+  uint64 id = 1;
+  string first_name = 2;
+  Address address = 3;
+  Address home_address = 4;
+  string address_note = 5;
+}
+"#;
+
+fn mapping(settings: serde_json::Value) -> Mapping {
+    let mut spec = serde_json::json!({
+        "from": "users/",
+        "to": "users0",
+        "proto": "shop.User",
+        "table": "shop.users",
+    });
+    for (name, value) in settings.as_object().unwrap() {
+        spec[name] = value.clone();
+    }
+
+    serde_json::from_value(spec).unwrap()
+}
+
+/// Names of the columns bound to a field of `shop.User`, out of `columns`.
+fn bound(columns: &[&str], mapping: &Mapping) -> Vec<String> {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+    let user = ctx.get_message("shop.User").unwrap();
+
+    let columns = columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            ClickhouseTableColumnRow {
+                name: name.to_string(),
+                position: i as u64 + 1,
+                r#type: "String".to_string(),
+                default_expression: String::new(),
+            }
+            .try_into()
+            .unwrap()
+        })
+        .collect();
+    let table = Table::new(
+        ClickhouseTableParts::from_string("shop.users").unwrap(),
+        columns,
+    );
+
+    let converters = Converters::default();
+    let binding = bind_proto_message(&ctx, &converters, user, table, mapping).unwrap();
+    binding
+        .columns()
+        .into_iter()
+        .map(|i| binding.table.columns[i].name.clone())
+        .collect()
+}
+
+#[test]
+fn fields_by_column_name() {
+    let columns = [
+        "id",
+        "address_city",
+        "address_location_x",
+        "address_country",
+        "id_city",
+    ];
+
+    assert_eq!(
+        bound(&columns, &mapping(serde_json::json!({}))),
+        ["id", "address_city", "address_location_x"]
+    );
+}
+
+#[test]
+fn field_names_holding_the_separator() {
+    let columns = [
+        "first_name",
+        "address_zip_code",
+        "home_address_location_y",
+        "address_note",
+        "home_address.zip_code",
+    ];
+
+    assert_eq!(
+        bound(&columns, &mapping(serde_json::json!({}))),
+        [
+            "first_name",
+            "address_zip_code",
+            "home_address_location_y",
+            "address_note",
+        ]
+    );
+
+    let dotted = mapping(serde_json::json!({ "separator": "." }));
+    assert_eq!(
+        bound(&["home_address.zip_code", "home_address_zip_code"], &dotted),
+        ["home_address.zip_code"]
+    );
+}
+
+#[test]
+fn fields_from_mapped_columns() {
+    let mapping = mapping(serde_json::json!({
+        "columns": {
+            "uid": 1,
+            "town": "address.city",
+            "x": "3.3.1",
+            "home_y": "home_address.3.y",
+            "country": "address.country",
+            "id_city": "id.city",
+            "note": 6,
+        }
+    }));
+    let columns = [
+        "uid",
+        "town",
+        "x",
+        "home_y",
+        "country",
+        "id_city",
+        "note",
+        "first_name",
+    ];
+
+    // Columns that are not mapped are bound by name
+    assert_eq!(
+        bound(&columns, &mapping),
+        ["uid", "town", "x", "home_y", "first_name"]
+    );
+}
+
+const ORDER: &str = r#"
+syntax = "proto3";
+package shop;
+
+import "google/protobuf/wrappers.proto";
+
+message Address {
+  string city = 1;
+}
+
+message Order {
+  uint64 id = 1;
+  string note = 2;
+  optional string comment = 3;
+  google.protobuf.StringValue label = 4;
+  Address address = 5;
+}
+"#;
+
+#[test]
+fn unset_fields_in_nullable_columns() {
+    let wrappers = std::fs::read_to_string("google_protobuf/wrappers.proto").unwrap();
+    let ctx = Context::parse(&[ORDER, &wrappers]).unwrap();
+    let order = ctx.get_message("shop.Order").unwrap();
+    let columns = [
+        ("id", "Nullable(UInt64)"),
+        ("note", "Nullable(String)"),
+        ("comment", "Nullable(String)"),
+        ("label", "Nullable(String)"),
+        ("address_city", "Nullable(String)"),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, (name, r#type))| {
+        ClickhouseTableColumnRow {
+            name: name.to_string(),
+            position: i as u64 + 1,
+            r#type: r#type.to_string(),
+            default_expression: String::new(),
+        }
+        .try_into()
+        .unwrap()
+    })
+    .collect();
+    let table = Table::new(
+        ClickhouseTableParts::from_string("shop.orders").unwrap(),
+        columns,
+    );
+    let converters = Converters::default();
+    let binding = bind_proto_message(&ctx, &converters, order, table, &Mapping::default()).unwrap();
+    let row = |message: &[u8]| {
+        binding
+            .prepare(&ctx, b"key", message)
+            .unwrap()
+            .into_values()
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        row(&[]),
+        [
+            Cell::UInt(0),
+            Cell::String(String::new()),
+            Cell::Null,
+            Cell::Null,
+            Cell::Null
+        ]
+    );
+
+    // address = {}
+    assert_eq!(row(&[0x2a, 0])[4], Cell::String(String::new()));
+}
+
+const EVENT: &str = r#"
+syntax = "proto3";
+package shop;
+
+message Point {
+  double x = 1;
+  double y = 2;
+}
+
+message Event {
+  uint32 day = 1;
+  sint32 day32 = 2;
+  Point payload = 3;
+  Point detail = 4;
+}
+"#;
+
+#[test]
+fn date_and_json_columns() {
+    let ctx = Context::parse(&[EVENT]).unwrap();
+    let event = ctx.get_message("shop.Event").unwrap();
+    let columns = [
+        ("day", "Date"),
+        ("day32", "Date32"),
+        ("payload", "JSON"),
+        ("detail", "Object('json')"),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, (name, r#type))| {
+        ClickhouseTableColumnRow {
+            name: name.to_string(),
+            position: i as u64 + 1,
+            r#type: r#type.to_string(),
+            default_expression: String::new(),
+        }
+        .try_into()
+        .unwrap()
+    })
+    .collect();
+    let table = Table::new(
+        ClickhouseTableParts::from_string("shop.events").unwrap(),
+        columns,
+    );
+    let converters = Converters::default();
+    let binding = bind_proto_message(&ctx, &converters, event, table, &Mapping::default()).unwrap();
+
+    // day = 19000, day32 = -1, payload = {x: 1}
+    let mut message = vec![0x08, 0xb8, 0x94, 0x01, 0x10, 0x01, 0x1a, 0x09, 0x09];
+    message.extend_from_slice(&1f64.to_le_bytes());
+    let row = binding.prepare(&ctx, b"key", &message).unwrap();
+
+    assert_eq!(
+        binding.table.encode_batch(&[0, 1], [&row]).unwrap(),
+        [
+            19_000u16.to_le_bytes().to_vec(),
+            (-1i32).to_le_bytes().to_vec()
+        ]
+        .concat()
+    );
+
+    // The JSON columns are inserted as literals
+    assert!(!binding.table.has_binary_encoding(&[0, 1, 2, 3]));
+    let query = binding
+        .table
+        .construct_batch(&[0, 1, 2, 3], [&row])
+        .unwrap();
+    assert!(query.contains("VALUES ('2022-01-08','1969-12-31','{"));
+}
@@ -0,0 +1,174 @@
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_table::{ClickhouseTableParts, Row, Table};
+
+fn table(name: &str, columns: &[(&str, &str)]) -> Table {
+    let columns = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (name, r#type))| {
+            ClickhouseTableColumnRow {
+                name: name.to_string(),
+                position: i as u64 + 1,
+                r#type: r#type.to_string(),
+                default_expression: String::new(),
+            }
+            .try_into()
+            .unwrap()
+        })
+        .collect();
+
+    Table::new(ClickhouseTableParts::from_string(name).unwrap(), columns)
+}
+
+fn row(cells: Vec<Cell>) -> Row {
+    cells.into_iter().enumerate().collect()
+}
+
+#[test]
+fn insert_statements() {
+    let users = table(
+        "shop.users",
+        &[("id", "UInt64"), ("name", "String"), ("note", "String")],
+    );
+
+    assert_eq!(
+        users.insert_statement(&[0, 1], "RowBinary"),
+        "INSERT INTO `shop`.`users` (`id`,`name`) FORMAT RowBinary"
+    );
+
+    // Names are quoted, so they may be keywords or hold any character
+    let orders = table("order.select", &[("from", "String"), ("a`b", "String")]);
+    assert_eq!(
+        orders.insert_statement(&[0, 1], "RowBinary"),
+        "INSERT INTO `order`.`select` (`from`,`a\\`b`) FORMAT RowBinary"
+    );
+    assert_eq!(
+        orders
+            .construct_batch(
+                &[0, 1],
+                &[row(vec![
+                    Cell::String("x".to_string()),
+                    Cell::String("it's".to_string())
+                ])]
+            )
+            .unwrap(),
+        "INSERT INTO `order`.`select` (`from`,`a\\`b`) VALUES ('x','it\\'s')"
+    );
+}
+
+#[test]
+fn encodes_batches() {
+    let users = table(
+        "shop.users",
+        &[
+            ("id", "UInt64"),
+            ("name", "String"),
+            ("score", "Nullable(Int16)"),
+        ],
+    );
+    let rows = [
+        row(vec![
+            Cell::UInt(1),
+            Cell::String("ann".to_string()),
+            Cell::Int(-2),
+        ]),
+        row(vec![
+            Cell::UInt(258),
+            Cell::String(String::new()),
+            Cell::Null,
+        ]),
+    ];
+
+    assert_eq!(
+        users.encode_batch(&[0, 1, 2], &rows).unwrap(),
+        [
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 3, b'a', b'n', b'n', 0, 0xfe, 0xff],
+            vec![2, 1, 0, 0, 0, 0, 0, 0, 0, 1],
+        ]
+        .concat()
+    );
+
+    // Only the given columns are written, in their order
+    assert_eq!(
+        users.encode_batch(&[1], &rows[..1]).unwrap(),
+        vec![3, b'a', b'n', b'n']
+    );
+
+    // Rows missing a value of a written column fail the batch
+    assert!(users.encode_batch(&[0], &[row(vec![])]).is_err());
+}
+
+#[test]
+fn splits_rows_by_columns() {
+    let mut users = table(
+        "shop.users",
+        &[
+            ("id", "UInt64"),
+            ("name", "String"),
+            ("created", "DateTime"),
+        ],
+    );
+    users.columns[2].default_expression = "now()".to_string();
+
+    let rows = [
+        row(vec![Cell::UInt(1), Cell::String("ann".to_string())]),
+        row(vec![
+            Cell::UInt(2),
+            Cell::String("bob".to_string()),
+            Cell::UInt(1_600_000_000),
+        ]),
+        row(vec![Cell::UInt(3), Cell::String("cy".to_string())]),
+    ];
+
+    // Columns with a default expression are left out of the rows without them
+    let groups = users.split_by_columns(&[0, 1, 2], &rows);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].0, [0, 1]);
+    assert_eq!(groups[0].1, [&rows[0], &rows[2]]);
+    assert_eq!(groups[1].0, [0, 1, 2]);
+    assert_eq!(groups[1].1, [&rows[1]]);
+
+    assert_eq!(
+        users
+            .construct_batch(&groups[0].0, groups[0].1.clone())
+            .unwrap(),
+        "INSERT INTO `shop`.`users` (`id`,`name`) VALUES (1,'ann'),(3,'cy')"
+    );
+
+    // Other missing columns are still written, failing the batch
+    let rows = [row(vec![Cell::UInt(4)])];
+    let groups = users.split_by_columns(&[0, 1, 2], &rows);
+    assert_eq!(groups[0].0, [0, 1]);
+    assert!(users
+        .encode_batch(&groups[0].0, groups[0].1.clone())
+        .is_err());
+}
+
+#[test]
+fn json_columns_have_no_binary_encoding() {
+    let events = table(
+        "shop.events",
+        &[
+            ("day", "Date"),
+            ("payload", "JSON"),
+            ("history", "Array(Object('json'))"),
+        ],
+    );
+    let rows = [row(vec![
+        Cell::UInt(19_000),
+        Cell::Message(vec![("id".to_string(), Cell::UInt(1))]),
+        Cell::Array(vec![]),
+    ])];
+
+    assert!(events.has_binary_encoding(&[0]));
+    assert!(!events.has_binary_encoding(&[0, 1]));
+    assert!(!events.has_binary_encoding(&[2]));
+    assert!(events.encode_batch(&[0, 1], &rows).is_err());
+
+    // The client inserts them as literals instead
+    assert_eq!(
+        events.construct_batch(&[0, 1, 2], &rows).unwrap(),
+        r#"INSERT INTO `shop`.`events` (`day`,`payload`,`history`) VALUES ('2022-01-08','{"id":1}',[])"#
+    );
+}
@@ -0,0 +1,177 @@
+use fdb_ch_proto_export::clickhouse_type::ColumnType;
+use fdb_ch_proto_export::error::Error;
+
+fn parse(r#type: &str) -> ColumnType {
+    r#type.parse().unwrap()
+}
+
+#[test]
+fn simple_types() {
+    assert_eq!(parse("UInt64"), ColumnType::UInt64);
+    assert_eq!(parse("Boolean"), ColumnType::Bool);
+    assert_eq!(parse("UUID"), ColumnType::Uuid);
+    assert_eq!(parse("IPv6"), ColumnType::Ipv6);
+    assert_eq!(parse("FixedString(16)"), ColumnType::FixedString(16));
+    assert_eq!(parse("DateTime"), ColumnType::DateTime(None));
+    assert_eq!(
+        parse("DateTime('Europe/Paris')"),
+        ColumnType::DateTime(Some("Europe/Paris".to_string()))
+    );
+    assert_eq!(
+        parse("Object('json')"),
+        ColumnType::Object("json".to_string())
+    );
+}
+
+#[test]
+fn decimals() {
+    assert_eq!(
+        parse("Decimal(18, 4)"),
+        ColumnType::Decimal {
+            precision: 18,
+            scale: 4
+        }
+    );
+    assert_eq!(
+        parse("Decimal64(4)"),
+        ColumnType::Decimal {
+            precision: 18,
+            scale: 4
+        }
+    );
+    assert!("Decimal(4, 6)".parse::<ColumnType>().is_err());
+    assert!("Decimal(77, 2)".parse::<ColumnType>().is_err());
+}
+
+#[test]
+fn datetime64() {
+    assert_eq!(
+        parse("DateTime64(3)"),
+        ColumnType::DateTime64 {
+            precision: 3,
+            timezone: None
+        }
+    );
+    assert_eq!(
+        parse("DateTime64(9, 'UTC')"),
+        ColumnType::DateTime64 {
+            precision: 9,
+            timezone: Some("UTC".to_string())
+        }
+    );
+    assert!("DateTime64(10)".parse::<ColumnType>().is_err());
+}
+
+#[test]
+fn enums() {
+    let r#type = parse("Enum8('a, (b' = 1, 'c\\'' = -2)");
+
+    assert_eq!(
+        r#type,
+        ColumnType::Enum8(vec![("a, (b".to_string(), 1), ("c'".to_string(), -2)])
+    );
+    assert_eq!(r#type.integer_range(), Some((-128, 127)));
+    assert_eq!(r#type.to_string(), "Enum8('a, (b' = 1, 'c\\'' = -2)");
+    assert_eq!(
+        parse("Enum16('x' = 1000)").enum_values(),
+        Some(&[("x".to_string(), 1000)][..])
+    );
+}
+
+#[test]
+fn wrappers() {
+    let r#type = parse("LowCardinality(Nullable(String))");
+
+    assert_eq!(r#type.inner(), &ColumnType::String);
+    assert!(r#type.is_nullable());
+    assert!(!parse("Array(Nullable(String))").is_nullable());
+}
+
+#[test]
+fn compound_types() {
+    assert_eq!(
+        parse("Map(String, Array(Tuple(UInt8, String)))"),
+        ColumnType::Map(
+            Box::new(ColumnType::String),
+            Box::new(ColumnType::Array(Box::new(ColumnType::Tuple(vec![
+                (None, ColumnType::UInt8),
+                (None, ColumnType::String)
+            ]))))
+        )
+    );
+    assert_eq!(
+        parse("Tuple(lat Float64, `long name` Nullable(Float64))"),
+        ColumnType::Tuple(vec![
+            (Some("lat".to_string()), ColumnType::Float64),
+            (
+                Some("long name".to_string()),
+                ColumnType::Nullable(Box::new(ColumnType::Float64))
+            )
+        ])
+    );
+
+    let nested = parse("Nested(name String, value Int8)");
+    assert_eq!(
+        nested.element(),
+        Some(ColumnType::Tuple(vec![
+            (Some("name".to_string()), ColumnType::String),
+            (Some("value".to_string()), ColumnType::Int8)
+        ]))
+    );
+    assert_eq!(parse("Array(UInt8)").element(), Some(ColumnType::UInt8));
+    assert_eq!(parse("String").element(), None);
+}
+
+#[test]
+fn other_types() {
+    assert_eq!(
+        parse("AggregateFunction(uniq, UInt64)"),
+        ColumnType::Other("AggregateFunction(uniq, UInt64)".to_string())
+    );
+    assert_eq!(
+        parse("Array(Int256)"),
+        ColumnType::Array(Box::new(ColumnType::Other("Int256".to_string())))
+    );
+}
+
+#[test]
+fn invalid_types() {
+    assert!("".parse::<ColumnType>().is_err());
+    assert!("Array(String".parse::<ColumnType>().is_err());
+    assert!("String)".parse::<ColumnType>().is_err());
+    assert!("Enum8('a' = x)".parse::<ColumnType>().is_err());
+    assert!("Nested(String)".parse::<ColumnType>().is_err());
+    assert!("Decimal(4, 5)".parse::<ColumnType>().is_err());
+    assert!("Decimal32(10)".parse::<ColumnType>().is_err());
+}
+
+#[test]
+fn multibyte_whitespace() {
+    assert!(matches!(
+        "Array(\u{a0}Int32)".parse::<ColumnType>(),
+        Err(Error::ParseError(_))
+    ));
+    assert!(matches!(
+        "Int32\u{2003}".parse::<ColumnType>(),
+        Err(Error::ParseError(_))
+    ));
+}
+
+#[test]
+fn display() {
+    for r#type in [
+        "Nullable(Decimal(18, 4))",
+        "LowCardinality(Nullable(String))",
+        "Map(String, Array(Tuple(UInt8, String)))",
+        "Tuple(lat Float64, `long name` Float64)",
+        "Nested(name String, value Int8)",
+        "DateTime64(3, 'UTC')",
+        "Enum16('a' = 1, 'b' = 2)",
+        "AggregateFunction(uniq, UInt64)",
+    ] {
+        assert_eq!(parse(r#type).to_string(), r#type);
+    }
+
+    assert_eq!(parse("Decimal32(2)").to_string(), "Decimal(9, 2)");
+    assert_eq!(parse("Array( UInt8 )").to_string(), "Array(UInt8)");
+}
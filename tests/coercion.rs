@@ -0,0 +1,323 @@
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_table::TableColumn;
+use fdb_ch_proto_export::clickhouse_type::ColumnType;
+use fdb_ch_proto_export::coercion::{check_enum, check_field, enum_cell, message_tuple};
+use fdb_ch_proto_export::config::{BytesEncoding, UnknownEnum};
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::error::Error;
+use protofish::prelude::Context;
+
+const PROTO: &str = r#"
+syntax = "proto3";
+package shop;
+
+enum Status {
+  UNKNOWN = 0;
+  PAID = 1;
+  SHIPPED = 300;
+}
+
+message Point {
+  double x = 1;
+  double y = 2;
+}
+
+message Order {
+  int32 quantity = 1;
+  uint64 total = 2;
+  sint64 delta = 3;
+  double price = 4;
+  string name = 5;
+  bytes id = 6;
+  Status status = 7;
+  Point location = 8;
+  repeated string tags = 9;
+  map<string, int32> counts = 10;
+  bool paid = 11;
+  repeated Point path = 12;
+}
+"#;
+
+fn check(ctx: &Context, field: &str, r#type: &str) -> Result<(), String> {
+    let order = ctx.get_message("shop.Order").unwrap();
+    let field = order.iter_fields().find(|f| f.name == field).unwrap();
+
+    check_field(
+        ctx,
+        &Converters::default(),
+        field,
+        &r#type.parse().unwrap(),
+        None,
+    )
+}
+
+#[test]
+fn nullable_from_type() {
+    let column = |name: &str, r#type: &str| -> TableColumn {
+        ClickhouseTableColumnRow {
+            name: name.to_string(),
+            position: 1,
+            r#type: r#type.to_string(),
+            default_expression: String::new(),
+        }
+        .try_into()
+        .unwrap()
+    };
+
+    assert!(column("a", "Nullable(Int32)").nullable);
+    assert_eq!(column("a", "Nullable(Int32)").default(), Some(Cell::Null));
+    assert!(!column("Nullable(x)", "String").nullable);
+    assert_eq!(
+        column("Nullable(x)", "String").default(),
+        Some(Cell::String(String::new()))
+    );
+}
+
+#[test]
+fn integers() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    assert!(check(&ctx, "quantity", "Int32").is_ok());
+    assert!(check(&ctx, "quantity", "Int64").is_ok());
+    assert!(check(&ctx, "quantity", "Nullable(Int32)").is_ok());
+    assert!(check(&ctx, "quantity", "Int16").is_err());
+    assert!(check(&ctx, "quantity", "UInt32").is_err());
+    assert!(check(&ctx, "total", "UInt64").is_ok());
+    assert!(check(&ctx, "total", "Int32").is_err());
+    assert!(check(&ctx, "total", "Int64").is_err());
+    assert!(check(&ctx, "delta", "Int64").is_ok());
+    assert!(check(&ctx, "paid", "UInt8").is_ok());
+    assert!(check(&ctx, "paid", "Bool").is_ok());
+    assert!(check(&ctx, "quantity", "Bool").is_err());
+
+    assert!(check(&ctx, "quantity", "Float64").is_ok());
+    assert!(check(&ctx, "quantity", "Decimal(18, 2)").is_ok());
+    assert!(check(&ctx, "total", "DateTime").is_ok());
+    assert!(check(&ctx, "total", "Date").is_ok());
+    assert!(check(&ctx, "delta", "Date32").is_ok());
+    assert!(check(&ctx, "quantity", "String").is_ok());
+    assert!(check(&ctx, "quantity", "UUID").is_err());
+}
+
+#[test]
+fn floats_and_strings() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    assert!(check(&ctx, "price", "Float32").is_ok());
+    assert!(check(&ctx, "price", "Decimal(18, 9)").is_ok());
+    assert!(check(&ctx, "price", "Int64").is_err());
+
+    assert!(check(&ctx, "name", "LowCardinality(String)").is_ok());
+    assert!(check(&ctx, "name", "FixedString(8)").is_ok());
+    assert!(check(&ctx, "name", "UUID").is_ok());
+    assert!(check(&ctx, "name", "Int32").is_err());
+    assert!(check(&ctx, "name", "JSON").is_ok());
+    assert!(check(&ctx, "name", "DateTime").is_err());
+    assert!(check(&ctx, "name", "DateTime64(3)").is_err());
+    assert!(check(&ctx, "name", "Date").is_err());
+
+    assert!(check(&ctx, "id", "UUID").is_ok());
+    assert!(check(&ctx, "id", "Int32").is_err());
+
+    let order = ctx.get_message("shop.Order").unwrap();
+    let id = order.iter_fields().find(|f| f.name == "id").unwrap();
+    assert!(check_field(
+        &ctx,
+        &Converters::default(),
+        id,
+        &ColumnType::String,
+        Some(BytesEncoding::Hex)
+    )
+    .is_ok());
+    assert!(check_field(
+        &ctx,
+        &Converters::default(),
+        id,
+        &ColumnType::Uuid,
+        Some(BytesEncoding::Hex)
+    )
+    .is_err());
+}
+
+#[test]
+fn enums() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    assert!(check(&ctx, "status", "Enum8('UNKNOWN' = 0, 'PAID' = 1)").is_ok());
+    assert!(check(&ctx, "status", "LowCardinality(String)").is_ok());
+    assert!(check(&ctx, "status", "Int16").is_ok());
+    assert!(check(&ctx, "status", "Int8").is_err());
+    assert!(check(&ctx, "status", "Float64").is_err());
+}
+
+#[test]
+fn enum_values() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+    let order = ctx.get_message("shop.Order").unwrap();
+    let status = order.iter_fields().find(|f| f.name == "status").unwrap();
+    let check = |r#type: &str, unknown| check_enum(&ctx, status, &r#type.parse().unwrap(), unknown);
+
+    assert_eq!(
+        check("Enum8('UNKNOWN' = 0, 'PAID' = 1)", UnknownEnum::Number),
+        Ok(vec!["SHIPPED".to_string()])
+    );
+    assert!(check("Enum8('PAID' = 1)", UnknownEnum::Unknown).is_err());
+    assert!(check("Enum8('UNKNOWN' = 0)", UnknownEnum::Unknown).is_ok());
+    assert_eq!(check("String", UnknownEnum::Unknown), Ok(vec![]));
+}
+
+#[test]
+fn unknown_enums() {
+    let paid = || Cell::Enum {
+        name: Some("PAID".to_string()),
+        number: 1,
+    };
+    let unknown = || Cell::Enum {
+        name: None,
+        number: 7,
+    };
+    let r#enum = |name: &str, number| Cell::Enum {
+        name: Some(name.to_string()),
+        number,
+    };
+    let convert =
+        |value, r#type: &str, unknown| enum_cell(value, &r#type.parse().unwrap(), unknown);
+
+    // By name to String and Enum columns, by number to others
+    let column = "Enum8('UNKNOWN' = -1, 'PAID' = 5)";
+    assert_eq!(
+        convert(paid(), "String", UnknownEnum::Number).unwrap(),
+        "PAID".into()
+    );
+    assert_eq!(
+        convert(paid(), column, UnknownEnum::Number).unwrap(),
+        r#enum("PAID", 5)
+    );
+    assert_eq!(convert(paid(), "Int8", UnknownEnum::Skip).unwrap(), paid());
+    assert_eq!(
+        convert(unknown(), "Int8", UnknownEnum::Skip).unwrap(),
+        unknown()
+    );
+
+    assert_eq!(
+        convert(unknown(), "String", UnknownEnum::Number).unwrap(),
+        unknown()
+    );
+    assert!(convert(unknown(), column, UnknownEnum::Number).is_err());
+    assert_eq!(
+        convert(unknown(), "Enum8('a' = 7)", UnknownEnum::Number).unwrap(),
+        r#enum("a", 7)
+    );
+
+    assert_eq!(
+        convert(unknown(), "String", UnknownEnum::Unknown).unwrap(),
+        "UNKNOWN".into()
+    );
+    assert_eq!(
+        convert(unknown(), column, UnknownEnum::Unknown).unwrap(),
+        r#enum("UNKNOWN", -1)
+    );
+    assert_eq!(
+        convert(r#enum("SHIPPED", 300), column, UnknownEnum::Unknown).unwrap(),
+        r#enum("UNKNOWN", -1)
+    );
+
+    assert_eq!(
+        convert(unknown(), column, UnknownEnum::Default).unwrap(),
+        r#enum("UNKNOWN", -1)
+    );
+    assert_eq!(
+        convert(unknown(), "Nullable(String)", UnknownEnum::Default).unwrap(),
+        Cell::Null
+    );
+    assert_eq!(
+        convert(unknown(), "String", UnknownEnum::Default).unwrap(),
+        "".into()
+    );
+
+    assert!(matches!(
+        convert(unknown(), column, UnknownEnum::Skip),
+        Err(Error::SkippedMessage(_))
+    ));
+
+    // Nested in arrays and maps
+    assert_eq!(
+        convert(
+            Cell::Array(vec![paid(), unknown()]),
+            "Array(String)",
+            UnknownEnum::Unknown
+        )
+        .unwrap(),
+        Cell::Array(vec!["PAID".into(), "UNKNOWN".into()])
+    );
+    assert!(matches!(
+        convert(
+            Cell::Map(vec![("a".into(), unknown())]),
+            "Map(String, String)",
+            UnknownEnum::Skip
+        ),
+        Err(Error::SkippedMessage(_))
+    ));
+}
+
+#[test]
+fn messages() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    assert!(check(&ctx, "location", "String").is_ok());
+    assert!(check(&ctx, "location", "JSON").is_ok());
+    assert!(check(&ctx, "location", "Object('json')").is_ok());
+    assert!(check(&ctx, "location", "Tuple(Float64, Float64)").is_ok());
+    assert!(check(&ctx, "location", "Tuple(Float64)").is_err());
+    assert!(check(&ctx, "location", "Tuple(Float64, UUID)").is_err());
+    assert!(check(&ctx, "location", "Int64").is_err());
+}
+
+#[test]
+fn repeated_and_maps() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    assert!(check(&ctx, "tags", "Array(String)").is_ok());
+    assert!(check(&ctx, "tags", "String").is_ok());
+    assert!(check(&ctx, "tags", "LowCardinality(String)").is_ok());
+    assert!(check(&ctx, "tags", "UUID").is_err());
+    assert!(check(&ctx, "tags", "Array(Int32)").is_err());
+    assert!(check(&ctx, "quantity", "Array(Int64)").is_ok());
+    assert!(check(&ctx, "path", "Array(Tuple(Float64, Float64))").is_ok());
+
+    assert!(check(&ctx, "counts", "Map(String, Int64)").is_ok());
+    assert!(check(&ctx, "counts", "Array(Tuple(String, Int32))").is_ok());
+    assert!(check(&ctx, "counts", "Map(String, Int8)").is_err());
+    assert!(check(&ctx, "counts", "Array(String)").is_err());
+}
+
+#[test]
+fn message_tuples() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+    let point = ctx.get_message("shop.Point").unwrap();
+
+    assert_eq!(
+        message_tuple(
+            &ctx,
+            &Converters::default(),
+            point,
+            &"Tuple(Float64, Float64)".parse().unwrap(),
+            Cell::Message(vec![("y".to_string(), Cell::Float(2.0))])
+        ),
+        Cell::Tuple(vec![Cell::Float(0.0), Cell::Float(2.0)])
+    );
+    assert_eq!(
+        message_tuple(
+            &ctx,
+            &Converters::default(),
+            point,
+            &"Array(Tuple(Float64, Float64))".parse().unwrap(),
+            Cell::Array(vec![Cell::Message(vec![
+                ("y".to_string(), Cell::Float(2.0)),
+                ("x".to_string(), Cell::Float(1.0)),
+            ])])
+        ),
+        Cell::Array(vec![Cell::Tuple(vec![Cell::Float(1.0), Cell::Float(2.0)])])
+    );
+}
@@ -0,0 +1,157 @@
+//! In-memory stand-ins for the cluster and the tables exported to.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+
+use fdb_ch_proto_export::error::Error;
+use fdb_ch_proto_export::export::{BatchWriter, ExportStats};
+use fdb_ch_proto_export::fdb::{KeySource, KeyValues};
+use fdb_ch_proto_export::result::Result;
+
+/// Keys of a cluster, read in batches of two.
+#[derive(Default)]
+pub struct Cluster(pub Mutex<BTreeMap<Vec<u8>, Vec<u8>>>);
+
+impl Cluster {
+    /// A cluster holding the keys `users/001` to `users/<count>`.
+    pub fn users(count: usize) -> Cluster {
+        let cluster = Cluster::default();
+        for i in 1..=count {
+            cluster.set(&format!("users/{:03}", i), b"");
+        }
+        cluster
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(key.as_bytes().to_vec(), value.to_vec());
+    }
+
+    pub fn clear(&self, key: &str) {
+        self.0.lock().unwrap().remove(key.as_bytes());
+    }
+}
+
+impl KeySource for Cluster {
+    fn read_range(&self, from: Vec<u8>, to: Vec<u8>) -> BoxStream<'_, Result<KeyValues>> {
+        let keys: KeyValues = self
+            .0
+            .lock()
+            .unwrap()
+            .range(from..to)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let batches: Vec<Result<KeyValues>> = keys.chunks(2).map(|c| Ok(c.to_vec())).collect();
+
+        futures::stream::iter(batches).boxed()
+    }
+
+    fn get_values<'a>(
+        &'a self,
+        keys: &'a [Vec<u8>],
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>>> {
+        let cluster = self.0.lock().unwrap();
+        let values = keys.iter().map(|key| cluster.get(key).cloned());
+
+        futures::future::ready(Ok(values.collect())).boxed()
+    }
+
+    fn last_key<'a>(
+        &'a self,
+        from: &'a [u8],
+        to: &'a [u8],
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        let last_key = self
+            .0
+            .lock()
+            .unwrap()
+            .range(from.to_vec()..to.to_vec())
+            .next_back()
+            .map(|(key, _)| key.clone());
+
+        futures::future::ready(Ok(last_key)).boxed()
+    }
+
+    /// The length of the keys and values in the range.
+    fn estimate_size<'a>(&'a self, from: &'a [u8], to: &'a [u8]) -> BoxFuture<'a, Result<u64>> {
+        let size = self
+            .0
+            .lock()
+            .unwrap()
+            .range(from.to_vec()..to.to_vec())
+            .map(|(key, value)| (key.len() + value.len()) as u64)
+            .sum();
+
+        futures::future::ready(Ok(size)).boxed()
+    }
+}
+
+/// Records the keys of the batches written to a single route, failing to write
+/// the batch at `fail_at`.
+#[derive(Default)]
+pub struct Writer {
+    pub batches: Mutex<Vec<Vec<String>>>,
+    pub fail_at: Option<usize>,
+}
+
+impl Writer {
+    pub fn failing_at(batch: usize) -> Writer {
+        Writer {
+            fail_at: Some(batch),
+            ..Writer::default()
+        }
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.batches.lock().unwrap().concat()
+    }
+}
+
+impl BatchWriter for Writer {
+    fn write<'a>(
+        &'a self,
+        messages: &'a [(Vec<u8>, Vec<u8>)],
+        written: &'a mut Vec<usize>,
+    ) -> BoxFuture<'a, Result<ExportStats>> {
+        let mut batches = self.batches.lock().unwrap();
+
+        let result = if self.fail_at == Some(batches.len()) {
+            Err(Error::ParseError("connection reset".to_string()))
+        } else {
+            batches.push(
+                messages
+                    .iter()
+                    .map(|(key, _)| String::from_utf8(key.clone()).unwrap())
+                    .collect(),
+            );
+            written.push(0);
+
+            Ok(ExportStats {
+                read: messages.len(),
+                written: messages.len(),
+                ..ExportStats::default()
+            })
+        };
+
+        futures::future::ready(result).boxed()
+    }
+}
+
+/// Path of a state file that does not exist yet.
+pub fn state_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "fdb-ch-proto-export-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
@@ -0,0 +1,37 @@
+use fdb_ch_proto_export::config::Naming;
+
+#[test]
+fn snake_case_names() {
+    let cases = [
+        ("userName", "user_name"),
+        ("UserName", "user_name"),
+        ("user_name", "user_name"),
+        ("id", "id"),
+        ("HTTPServer", "http_server"),
+        ("userID", "user_id"),
+        ("parseHTTPResponse", "parse_http_response"),
+        ("address2City", "address2_city"),
+        ("HTTP2Server", "http2_server"),
+        ("URL", "url"),
+        ("_private", "_private"),
+    ];
+
+    for (name, expected) in cases {
+        assert_eq!(Naming::SnakeCase.apply(name), expected, "{}", name);
+    }
+}
+
+#[test]
+fn camel_case_names() {
+    let cases = [
+        ("user_name", "userName"),
+        ("userName", "userName"),
+        ("http_server_url", "httpServerUrl"),
+        ("address_2_city", "address2City"),
+        ("_private", "_private"),
+    ];
+
+    for (name, expected) in cases {
+        assert_eq!(Naming::CamelCase.apply(name), expected, "{}", name);
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+use fdb_ch_proto_export::cell::{uuid_text, Cell};
+use fdb_ch_proto_export::config::Conversion;
+use fdb_ch_proto_export::converter::{Converter, Converters};
+use fdb_ch_proto_export::protobuf::value_to_cell;
+use fdb_ch_proto_export::result::Result;
+use protofish::context::MessageInfo;
+use protofish::prelude::{Context, MessageValue, Value};
+
+const PROTO: &str = r#"
+syntax = "proto3";
+package custom;
+
+message Money {
+  string currency_code = 1;
+  int64 units = 2;
+  int32 nanos = 3;
+}
+
+message Time {
+  int64 secs = 1;
+  int32 nanos = 2;
+}
+
+message Id {
+  bytes value = 1;
+}
+
+message Token {
+  bytes value = 1;
+}
+
+message Amount {
+  uint64 units = 1;
+  int32 nanos = 2;
+}
+
+message LatLng {
+  double latitude = 1;
+  double longitude = 2;
+}
+"#;
+
+fn convert(ctx: &Context, converters: &Converters, name: &str, bytes: &[u8]) -> Cell {
+    let message = ctx.get_message(name).unwrap().decode(bytes, ctx);
+
+    value_to_cell(ctx, converters, &Value::Message(Box::new(message))).unwrap()
+}
+
+fn conversions(json: &str) -> HashMap<String, Conversion> {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn conversion_config() {
+    let parsed = conversions(
+        r#"{
+            "custom.Money": { "conversion": "decimal" },
+            "custom.Time": { "conversion": "datetime", "seconds": "secs" },
+            "custom.Id": { "conversion": "uuid" }
+        }"#,
+    );
+
+    assert_eq!(parsed["custom.Money"].fields(), ["units", "nanos"]);
+    assert_eq!(parsed["custom.Time"].fields(), ["secs", "nanos"]);
+    assert_eq!(parsed["custom.Id"].fields(), ["value"]);
+
+    assert!(serde_json::from_str::<Conversion>(r#"{ "conversion": "latlng" }"#).is_err());
+}
+
+#[test]
+fn uuid() {
+    let bytes = [
+        0x67, 0xe5, 0x50, 0x44, 0x10, 0xb1, 0x42, 0x6f, 0x92, 0x47, 0xbb, 0x68, 0x0e, 0x5f, 0xe0,
+        0xc8,
+    ];
+
+    assert_eq!(uuid_text(&bytes), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+}
+
+#[test]
+fn configured_conversions() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    let converters = Converters::from_config(
+        &ctx,
+        &conversions(
+            r#"{
+                "custom.Money": { "conversion": "decimal" },
+                "custom.Time": { "conversion": "datetime", "seconds": "secs" },
+                "custom.Id": { "conversion": "uuid" },
+                "custom.Amount": { "conversion": "decimal" }
+            }"#,
+        ),
+    )
+    .unwrap();
+
+    // currency_code = "EUR", units = 12, nanos = 500000000
+    let money = [
+        0x0a, 3, b'E', b'U', b'R', 0x10, 12, 0x18, 0x80, 0xca, 0xb5, 0xee, 0x01,
+    ];
+    assert_eq!(
+        convert(&ctx, &converters, "custom.Money", &money),
+        Cell::Decimal {
+            value: 12_500_000_000,
+            scale: 9
+        }
+    );
+
+    assert_eq!(
+        convert(&ctx, &converters, "custom.Time", &[0x08, 10, 0x10, 5]),
+        Cell::DateTime {
+            seconds: 10,
+            nanos: 5
+        }
+    );
+
+    let mut id = vec![0x0a, 16];
+    id.extend_from_slice(&[0xab; 16]);
+    assert_eq!(
+        convert(&ctx, &converters, "custom.Id", &id),
+        Cell::String("abababab-abab-abab-abab-abababababab".into())
+    );
+    assert!(value_to_cell(
+        &ctx,
+        &converters,
+        &Value::Message(Box::new(
+            ctx.get_message("custom.Id")
+                .unwrap()
+                .decode(&[0x0a, 1, 0], &ctx)
+        ))
+    )
+    .is_err());
+
+    // Values the converted types can't hold
+    let invalid = |name: &str, bytes: &[u8]| {
+        let message = ctx.get_message(name).unwrap().decode(bytes, &ctx);
+        value_to_cell(&ctx, &converters, &Value::Message(Box::new(message))).is_err()
+    };
+    let mut negative_nanos = vec![0x08, 10, 0x10];
+    negative_nanos.extend_from_slice(&[0xff; 9]);
+    negative_nanos.push(0x01);
+    assert!(invalid("custom.Time", &negative_nanos));
+
+    let mut large_units = vec![0x08];
+    large_units.extend_from_slice(&[0xff; 9]);
+    large_units.push(0x01);
+    assert!(invalid("custom.Amount", &large_units));
+}
+
+#[test]
+fn invalid_conversions() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+
+    assert!(Converters::from_config(
+        &ctx,
+        &conversions(r#"{ "custom.Missing": { "conversion": "uuid" } }"#)
+    )
+    .is_err());
+    assert!(Converters::from_config(
+        &ctx,
+        &conversions(r#"{ "custom.Time": { "conversion": "datetime" } }"#)
+    )
+    .is_err());
+}
+
+#[test]
+fn converters_are_per_config() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+    let converters = Converters::from_config(
+        &ctx,
+        &conversions(r#"{ "custom.Token": { "conversion": "uuid" } }"#),
+    )
+    .unwrap();
+
+    let mut token = vec![0x0a, 16];
+    token.extend_from_slice(&[0xab; 16]);
+    assert_eq!(
+        convert(&ctx, &converters, "custom.Token", &token),
+        Cell::String("abababab-abab-abab-abab-abababababab".into())
+    );
+
+    // Messages are only converted with the converters they are decoded with
+    assert!(converters.get("custom.Token").is_some());
+    assert!(Converters::default().get("custom.Token").is_none());
+    assert_eq!(
+        convert(&ctx, &Converters::default(), "custom.Token", &token),
+        Cell::Message(vec![("value".to_string(), Cell::Bytes(vec![0xab; 16]))])
+    );
+}
+
+#[test]
+fn project_timestamps_by_default() {
+    let proto = r#"
+        syntax = "proto3";
+        package protos;
+
+        message Timestamp {
+          int64 seconds = 1;
+          int32 nanos = 2;
+        }
+    "#;
+    let ctx = Context::parse(&[proto]).unwrap();
+
+    // seconds = 90, nanos = 5
+    assert_eq!(
+        convert(
+            &ctx,
+            &Converters::default(),
+            "protos.Timestamp",
+            &[0x08, 90, 0x10, 5]
+        ),
+        Cell::DateTime {
+            seconds: 90,
+            nanos: 5
+        }
+    );
+}
+
+struct LatLng;
+
+impl Converter for LatLng {
+    fn convert(
+        &self,
+        _context: &Context,
+        _info: &MessageInfo,
+        message: &MessageValue,
+    ) -> Result<Cell> {
+        let mut point = vec![Cell::Float(0.0), Cell::Float(0.0)];
+        for field in &message.fields {
+            if let (1..=2, Value::Double(v)) = (field.number, &field.value) {
+                point[field.number as usize - 1] = Cell::Float(*v);
+            }
+        }
+
+        Ok(Cell::Tuple(point))
+    }
+}
+
+#[test]
+fn custom_converter() {
+    let ctx = Context::parse(&[PROTO]).unwrap();
+    let mut converters = Converters::default();
+    converters.register("custom.LatLng", LatLng);
+
+    let point = [
+        &[0x09][..],
+        &52.5f64.to_le_bytes(),
+        &[0x11],
+        &13.4f64.to_le_bytes(),
+    ]
+    .concat();
+    assert_eq!(
+        convert(&ctx, &converters, "custom.LatLng", &point),
+        Cell::Tuple(vec![Cell::Float(52.5), Cell::Float(13.4)])
+    );
+}
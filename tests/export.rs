@@ -0,0 +1,473 @@
+mod common;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use common::{state_file, Cluster, Writer};
+use fdb_ch_proto_export::checkpoint::{CheckpointStore, FileCheckpointStore, PartialBatch};
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_message_binding::bind_proto_message;
+use fdb_ch_proto_export::clickhouse_table::{ClickhouseTableParts, Table};
+use fdb_ch_proto_export::config::{Mapping, Route};
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::error::Error;
+use fdb_ch_proto_export::export::{
+    dry_run_report, export_range, plan_shards, summary_table, BatchWriter, DryRun, DryRunWriter,
+    ExportStats, MappingSummary,
+};
+use fdb_ch_proto_export::result::Result;
+use fdb_ch_proto_export::router::{BoundRoute, RouteMatcher, Router};
+use protofish::prelude::Context;
+
+fn users_range() -> (Vec<u8>, Vec<u8>) {
+    (b"users/".to_vec(), b"users0".to_vec())
+}
+
+#[tokio::test]
+async fn resumes_after_failed_batch() {
+    let path = state_file("export-resume");
+    let source = Cluster::users(5);
+
+    {
+        let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+
+        // The second batch fails to write, after the first one has been checkpointed
+        let writer = Writer::failing_at(1);
+        let result = export_range(
+            Some(&checkpoints),
+            "users",
+            users_range(),
+            false,
+            &source,
+            &writer,
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::ParseError(_))));
+        assert_eq!(writer.keys(), vec!["users/001", "users/002"]);
+    }
+
+    // Restarted with the state file left by the failed export
+    let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+
+    let writer = Writer::default();
+    let stats = export_range(
+        Some(&checkpoints),
+        "users",
+        users_range(),
+        true,
+        &source,
+        &writer,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(writer.keys(), vec!["users/003", "users/004", "users/005"]);
+    assert_eq!(stats.written, 3);
+
+    let checkpoint = checkpoints.get("users").await.unwrap().unwrap();
+    assert_eq!(checkpoint.messages_written, 5);
+    assert!(checkpoint.completed);
+
+    // A completed range is skipped by later resumes, and exported again without
+    let writer = Writer::default();
+    export_range(
+        Some(&checkpoints),
+        "users",
+        users_range(),
+        true,
+        &source,
+        &writer,
+    )
+    .await
+    .unwrap();
+    assert!(writer.keys().is_empty());
+
+    export_range(
+        Some(&checkpoints),
+        "users",
+        users_range(),
+        false,
+        &source,
+        &writer,
+    )
+    .await
+    .unwrap();
+    assert_eq!(writer.keys().len(), 5);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn exports_without_checkpoints() {
+    let source = Cluster::users(3);
+
+    let writer = Writer::default();
+    let stats = export_range(None, "users", users_range(), true, &source, &writer)
+        .await
+        .unwrap();
+
+    assert_eq!(stats.written, 3);
+    assert_eq!(writer.batches.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn resumes_the_same_shards() {
+    let path = state_file("export-shards");
+    let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+    let source = Cluster::users(8);
+
+    let shards = plan_shards(&checkpoints, &source, "users", users_range(), false, 4)
+        .await
+        .unwrap();
+    assert_eq!(shards.len(), 4);
+    assert_eq!(shards[1].0, "users#1/4");
+
+    // The data moved since, but the shards checkpointed are resumed
+    source.set("users/001", &[0; 1000]);
+    let resumed = plan_shards(&checkpoints, &source, "users", users_range(), true, 4)
+        .await
+        .unwrap();
+    assert_eq!(resumed, shards);
+
+    // Shards of another number of workers would match no checkpoint
+    for workers in [1, 2] {
+        let result =
+            plan_shards(&checkpoints, &source, "users", users_range(), true, workers).await;
+        assert!(matches!(result, Err(Error::StaleCheckpoint(_))));
+    }
+
+    // Exporting again without resuming splits the range again
+    let shards = plan_shards(&checkpoints, &source, "users", users_range(), false, 2)
+        .await
+        .unwrap();
+    assert_eq!(shards[0].0, "users#0/2");
+    assert_ne!(shards[0].1, resumed[0].1);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_to_shard_unsharded_checkpoints() {
+    let path = state_file("export-unsharded");
+    let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+    let source = Cluster::users(5);
+
+    let writer = Writer::failing_at(1);
+    let shards = plan_shards(&checkpoints, &source, "users", users_range(), false, 1)
+        .await
+        .unwrap();
+    assert_eq!(shards, vec![("users".to_string(), users_range())]);
+
+    let (id, range) = shards.into_iter().next().unwrap();
+    assert!(
+        export_range(Some(&checkpoints), &id, range, false, &source, &writer)
+            .await
+            .is_err()
+    );
+
+    let result = plan_shards(&checkpoints, &source, "users", users_range(), true, 4).await;
+    assert!(matches!(result, Err(Error::StaleCheckpoint(_))));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Writes the odd keys to the first route and the even keys to the second one,
+/// failing once to write `fail` to its route.
+#[derive(Default)]
+struct RoutedWriter {
+    routes: Mutex<[Vec<String>; 2]>,
+    fail: Mutex<Option<&'static str>>,
+}
+
+impl BatchWriter for RoutedWriter {
+    fn write<'a>(
+        &'a self,
+        messages: &'a [(Vec<u8>, Vec<u8>)],
+        written: &'a mut Vec<usize>,
+    ) -> BoxFuture<'a, Result<ExportStats>> {
+        let mut routes = self.routes.lock().unwrap();
+        let mut stats = ExportStats {
+            read: messages.len(),
+            ..ExportStats::default()
+        };
+
+        for (index, route) in routes.iter_mut().enumerate() {
+            if written.contains(&index) {
+                continue;
+            }
+
+            let keys: Vec<String> = messages
+                .iter()
+                .map(|(key, _)| String::from_utf8(key.clone()).unwrap())
+                .filter(|key| key.ends_with(['1', '3', '5', '7', '9']) == (index == 0))
+                .collect();
+
+            let mut fail = self.fail.lock().unwrap();
+            if fail.is_some_and(|fail| keys.iter().any(|key| key == fail)) {
+                *fail = None;
+                let error = Error::ClickhouseInsert("connection reset".to_string());
+                return futures::future::ready(Err(error)).boxed();
+            }
+
+            stats.written += keys.len();
+            route.extend(keys);
+            written.push(index);
+        }
+
+        futures::future::ready(Ok(stats)).boxed()
+    }
+}
+
+#[tokio::test]
+async fn resumes_batch_written_to_some_routes() {
+    let path = state_file("export-routes");
+    let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+    let source = Cluster::users(6);
+
+    // The second batch is written to the first route only
+    let writer = RoutedWriter {
+        fail: Mutex::new(Some("users/004")),
+        ..RoutedWriter::default()
+    };
+    let result = export_range(
+        Some(&checkpoints),
+        "users",
+        users_range(),
+        false,
+        &source,
+        &writer,
+    )
+    .await;
+    assert!(result.is_err());
+
+    let checkpoint = checkpoints.get("users").await.unwrap().unwrap();
+    assert_eq!(checkpoint.last_key, b"users/002");
+    assert_eq!(
+        checkpoint.partial,
+        Some(PartialBatch {
+            last_key: b"users/004".to_vec(),
+            routes: vec![0],
+        })
+    );
+
+    export_range(
+        Some(&checkpoints),
+        "users",
+        users_range(),
+        true,
+        &source,
+        &writer,
+    )
+    .await
+    .unwrap();
+
+    // Every key is written once to its route
+    assert_eq!(
+        *writer.routes.lock().unwrap(),
+        [
+            vec!["users/001", "users/003", "users/005"],
+            vec!["users/002", "users/004", "users/006"],
+        ]
+    );
+
+    let checkpoint = checkpoints.get("users").await.unwrap().unwrap();
+    assert!(checkpoint.completed);
+    assert_eq!(checkpoint.partial, None);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn sample(id: u32) -> Vec<(String, String)> {
+    vec![
+        ("id".to_string(), id.to_string()),
+        ("name".to_string(), format!("'user {}'", id)),
+    ]
+}
+
+fn shard_report(rows: usize, samples: Vec<Vec<(String, String)>>) -> DryRun {
+    let mut report = DryRun::default();
+    report.rows.insert("db.users".to_string(), rows);
+    report.failures.insert("ParseError", 1);
+    report.missing.insert("db.users.email".to_string(), rows);
+    report.samples.insert("db.users".to_string(), samples);
+    report
+}
+
+#[test]
+fn merges_shard_reports() {
+    let mut report = shard_report(2, vec![sample(1), sample(2)]);
+    report += shard_report(5, vec![sample(3), sample(4)]);
+
+    assert_eq!(report.rows["db.users"], 7);
+    assert_eq!(report.failures["ParseError"], 2);
+    assert_eq!(report.missing["db.users.email"], 7);
+
+    // Samples are capped, keeping the first rows
+    assert_eq!(
+        report.samples["db.users"],
+        vec![sample(1), sample(2), sample(3)]
+    );
+}
+
+#[test]
+fn renders_dry_run_report() {
+    let summaries = vec![
+        MappingSummary {
+            mapping: "users".to_string(),
+            stats: ExportStats::default(),
+            duration: Duration::from_secs(1),
+            error: None,
+            dry_run: Some(shard_report(2, vec![sample(1)])),
+        },
+        MappingSummary {
+            mapping: "empty".to_string(),
+            stats: ExportStats::default(),
+            duration: Duration::from_secs(1),
+            error: None,
+            dry_run: Some(DryRun::default()),
+        },
+        MappingSummary {
+            mapping: "failed".to_string(),
+            stats: ExportStats::default(),
+            duration: Duration::from_secs(1),
+            error: None,
+            dry_run: None,
+        },
+    ];
+
+    assert_eq!(
+        dry_run_report(&summaries),
+        "users:
+  rows:
+    db.users: 2
+  decode failures:
+    ParseError: 1
+  missing fields:
+    db.users.email: 2 rows
+  sample rows:
+    db.users: id = 1, name = 'user 1'
+empty:
+  rows:
+    none"
+    );
+}
+
+#[test]
+fn renders_summary_table() {
+    let summaries = vec![
+        MappingSummary {
+            mapping: "protos.User:default.users".to_string(),
+            stats: ExportStats {
+                read: 1200,
+                written: 1150,
+                skipped: 40,
+                unmatched: 10,
+            },
+            duration: Duration::from_millis(12340),
+            error: None,
+            dry_run: None,
+        },
+        // A failed mapping reports what it wrote before failing
+        MappingSummary {
+            mapping: "protos.Order:default.orders".to_string(),
+            stats: ExportStats {
+                read: 20,
+                written: 20,
+                ..ExportStats::default()
+            },
+            duration: Duration::from_millis(500),
+            error: Some(Error::ExportFailed(1)),
+            dry_run: None,
+        },
+    ];
+
+    assert_eq!(
+        summary_table(&summaries),
+        "mapping                      read  written  skipped  unmatched  duration  status
+protos.User:default.users    1200  1150     40       10         12.3s     ok
+protos.Order:default.orders  20    20       0        0          0.5s      failed: 1 mappings failed to export"
+    );
+
+    assert_eq!(
+        summary_table(&[]),
+        "mapping  read  written  skipped  unmatched  duration  status"
+    );
+}
+
+const CLICK: &str = r#"
+syntax = "proto3";
+package events;
+
+message Click {
+  string user = 1;
+  int32 x = 2;
+}
+"#;
+
+#[tokio::test]
+async fn dry_runs_routed_messages() {
+    let ctx = Context::parse(&[CLICK]).unwrap();
+    let mapping: Mapping = serde_json::from_str(r#"{ "key": { "elements": ["kind"] } }"#).unwrap();
+    let route: Route = serde_json::from_str(
+        r#"{ "proto": "events.Click", "table": "events.clicks", "key_pattern": "^\\x02click\\x00" }"#,
+    )
+    .unwrap();
+
+    let columns = [("kind", "String"), ("user", "String"), ("x", "Int32")]
+        .iter()
+        .enumerate()
+        .map(|(i, (name, r#type))| {
+            ClickhouseTableColumnRow {
+                name: name.to_string(),
+                position: i as u64 + 1,
+                r#type: r#type.to_string(),
+                default_expression: String::new(),
+            }
+            .try_into()
+            .unwrap()
+        })
+        .collect();
+    let table = Table::new(
+        ClickhouseTableParts::from_string("events.clicks").unwrap(),
+        columns,
+    );
+    let click = ctx.get_message("events.Click").unwrap();
+    let converters = Converters::default();
+    let router = Router {
+        routes: vec![BoundRoute {
+            matcher: RouteMatcher::new(&route, mapping.key.as_ref()).unwrap(),
+            binding: bind_proto_message(&ctx, &converters, click, table, &mapping).unwrap(),
+        }],
+        key_prefix: vec![],
+        log_unmatched: false,
+    };
+
+    // user = "u1", x = 3
+    let message = vec![0x0a, 2, b'u', b'1', 0x10, 3];
+    let messages = vec![
+        (b"\x02click\x00".to_vec(), message.clone()),
+        (b"\x02click\x00".to_vec(), message[..4].to_vec()),
+        // A key that isn't a tuple fails to bind its elements
+        (b"\x02click\x00\xff".to_vec(), message.clone()),
+        (b"\x02view\x00".to_vec(), message),
+    ];
+
+    let writer = DryRunWriter::new(&ctx, &router);
+    let mut written = vec![];
+    let stats = writer.write(&messages, &mut written).await.unwrap();
+    assert_eq!(
+        (stats.read, stats.written, stats.skipped, stats.unmatched),
+        (4, 0, 1, 1)
+    );
+    assert!(written.is_empty());
+
+    let report = writer.into_report();
+    assert_eq!(report.rows, [("events.clicks".to_string(), 2)].into());
+    assert_eq!(report.failures, [("ParseError", 1)].into());
+    assert_eq!(report.missing, [("events.clicks.x".to_string(), 1)].into());
+    assert_eq!(report.samples["events.clicks"].len(), 2);
+}
@@ -0,0 +1,171 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::error::Error;
+use fdb_ch_proto_export::expression::{Expression, Scope};
+use protofish::prelude::Context;
+
+const PROTO: &str = r#"
+syntax = "proto3";
+package shop;
+
+message Address {
+  string city = 1;
+}
+
+message User {
+  string email = 1;
+  Address address = 2;
+  repeated int32 scores = 3;
+  bytes token = 4;
+  string name = 5;
+}
+"#;
+
+/// A user with `email`, `address.city`, `scores` and `token` set.
+const USER: &[u8] = b"\x0a\x11 Ann@Example.COM \x12\x07\x0a\x05Paris\x1a\x02\x01\x02\x22\x01\xff";
+
+fn context() -> Context {
+    Context::parse(&[PROTO]).unwrap()
+}
+
+fn parse(input: &str) -> Result<(), String> {
+    let ctx = context();
+    let user = ctx.get_message("shop.User").unwrap();
+
+    Expression::parse(&ctx, user, input)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn evaluate(input: &str) -> Cell {
+    let ctx = context();
+    let user = ctx.get_message("shop.User").unwrap();
+    let message = user.decode(USER, &ctx);
+
+    let scope = Scope {
+        key: b"users/acme/42",
+        key_separator: "/",
+        message: &message,
+        converters: &Converters::default(),
+        now: UNIX_EPOCH + Duration::new(1700000000, 5),
+    };
+
+    Expression::parse(&ctx, user, input)
+        .unwrap()
+        .evaluate(&ctx, &scope)
+        .unwrap()
+}
+
+#[test]
+fn parses_expressions() {
+    let valid = [
+        "$key",
+        "$now",
+        "$key_part[2]",
+        "'it\\'s'",
+        "'caf\u{e9}'",
+        "-1.5",
+        "null",
+        "address.city",
+        "concat( email , '-', $key_part[0])",
+        "coalesce(lower(email), upper(address.city), 'none')",
+        // Whitespace is not only ASCII
+        "\u{a0}lower(\u{3000}email)\u{2003}",
+    ];
+
+    for input in valid {
+        assert_eq!(parse(input), Ok(()), "{}", input);
+    }
+}
+
+#[test]
+fn parse_errors() {
+    let invalid = [
+        ("", "expected an expression"),
+        ("\u{a0}", "expected an expression"),
+        ("$unknown", "unknown variable"),
+        ("$key_part[x]", "expected a key part index"),
+        ("$key_part[1", "expected ]"),
+        ("'unterminated", "unterminated string"),
+        ("1.2.3", "invalid number"),
+        ("missing", "no field missing in shop.User"),
+        ("email.city", "email.city is not a message field"),
+        ("address.street", "no field street in shop.Address"),
+        ("unknown(email)", "unknown function unknown"),
+        ("lower(email, name)", "lower does not take 2 arguments"),
+        ("lower(email", "expected , or )"),
+        ("email name", "unexpected input"),
+    ];
+
+    for (input, message) in invalid {
+        let error = parse(input).unwrap_err();
+        assert!(error.contains(message), "{}: {}", input, error);
+    }
+
+    let ctx = context();
+    let user = ctx.get_message("shop.User").unwrap();
+    assert!(matches!(
+        Expression::parse(&ctx, user, "lower("),
+        Err(Error::InvalidMappingConfig(_))
+    ));
+}
+
+#[test]
+fn evaluates_variables_and_fields() {
+    assert_eq!(evaluate("$key"), Cell::Bytes(b"users/acme/42".to_vec()));
+    assert_eq!(evaluate("$key_part[1]"), Cell::String("acme".to_string()));
+    assert_eq!(evaluate("$key_part[3]"), Cell::Null);
+    assert_eq!(
+        evaluate("$now"),
+        Cell::DateTime {
+            seconds: 1700000000,
+            nanos: 5
+        }
+    );
+
+    assert_eq!(evaluate("'text'"), Cell::String("text".to_string()));
+    assert_eq!(evaluate("42"), Cell::Int(42));
+    assert_eq!(evaluate("-0.5"), Cell::Float(-0.5));
+    assert_eq!(evaluate("true"), Cell::Bool(true));
+    assert_eq!(evaluate("null"), Cell::Null);
+
+    assert_eq!(evaluate("address.city"), Cell::String("Paris".to_string()));
+    assert_eq!(
+        evaluate("scores"),
+        Cell::Array(vec![Cell::Int(1), Cell::Int(2)])
+    );
+    assert_eq!(evaluate("token"), Cell::Bytes(vec![0xff]));
+    assert_eq!(evaluate("name"), Cell::Null);
+}
+
+#[test]
+fn evaluates_functions() {
+    let text = |value: &str| Cell::String(value.to_string());
+
+    assert_eq!(evaluate("lower(address.city)"), text("paris"));
+    assert_eq!(evaluate("upper(address.city)"), text("PARIS"));
+    assert_eq!(evaluate("trim(email)"), text("Ann@Example.COM"));
+    assert_eq!(
+        evaluate("concat($key_part[1], '-', 42, '-', true)"),
+        text("acme-42-true")
+    );
+    assert_eq!(
+        evaluate("coalesce(name, null, address.city)"),
+        text("Paris")
+    );
+    assert_eq!(evaluate("coalesce(name)"), Cell::Null);
+
+    // FNV-1a hashes of the text or the bytes
+    assert_eq!(evaluate("hash('a')"), Cell::UInt(0xaf63dc4c8601ec8c));
+    assert_eq!(evaluate("hash(token)"), Cell::UInt(0xaf64724c8602eb6e));
+    assert_eq!(
+        evaluate("hash(lower(trim(email)))"),
+        Cell::UInt(0x140369096a6caa05)
+    );
+
+    // Functions other than coalesce are NULL for a NULL argument
+    assert_eq!(evaluate("lower(name)"), Cell::Null);
+    assert_eq!(evaluate("concat(email, name)"), Cell::Null);
+}
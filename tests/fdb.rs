@@ -0,0 +1,190 @@
+mod common;
+
+use common::Cluster;
+use fdb_ch_proto_export::error::Error;
+use fdb_ch_proto_export::fdb::{
+    directory_node, resolve_directory, split_by_size, split_range, subdirectory_key,
+};
+
+#[test]
+fn directory_layer_keys() {
+    let root = directory_node(b"\xfe", b"\xfe");
+    assert_eq!(root, b"\xfe\x01\xfe\x00");
+
+    assert_eq!(
+        subdirectory_key(&root, "app"),
+        b"\xfe\x01\xfe\x00\x14\x02app\x00"
+    );
+    assert_eq!(
+        directory_node(b"\xfe", b"\x15\x03"),
+        b"\xfe\x01\x15\x03\x00"
+    );
+    assert_eq!(directory_node(b"\xfe", b"\x00"), b"\xfe\x01\x00\xff\x00");
+
+    // Partitions hold their nodes after their own prefix
+    assert_eq!(
+        directory_node(b"\x15\x07\xfe", b"\x15\x07\xfe"),
+        b"\x15\x07\xfe\x01\x15\x07\xfe\x00"
+    );
+}
+
+fn path(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+/// Creates the directory `name` with the given prefix under the directory node
+/// `parent`, in the node subspace `nodes`, returning the node of the directory.
+fn create_directory(
+    cluster: &Cluster,
+    nodes: &[u8],
+    parent: &[u8],
+    name: &str,
+    prefix: &[u8],
+) -> Vec<u8> {
+    let mut keys = cluster.0.lock().unwrap();
+    keys.insert(subdirectory_key(parent, name), prefix.to_vec());
+
+    directory_node(nodes, prefix)
+}
+
+#[tokio::test]
+async fn resolves_directories() {
+    let cluster = Cluster::default();
+    let root = directory_node(b"\xfe", b"\xfe");
+
+    let app = create_directory(&cluster, b"\xfe", &root, "app", b"\x15\x01");
+    create_directory(&cluster, b"\xfe", &app, "users", b"\x15\x02");
+
+    assert_eq!(
+        resolve_directory(&cluster, &path(&["app"])).await.unwrap(),
+        b"\x15\x01"
+    );
+    assert_eq!(
+        resolve_directory(&cluster, &path(&["app", "users"]))
+            .await
+            .unwrap(),
+        b"\x15\x02"
+    );
+    assert_eq!(resolve_directory(&cluster, &[]).await.unwrap(), b"");
+
+    // Subdirectories of a partition are in the node subspace after its prefix
+    let tenant = create_directory(&cluster, b"\xfe", &root, "tenant", b"\x15\x03");
+    cluster.0.lock().unwrap().insert(
+        [&tenant[..], b"\x02layer\x00"].concat(),
+        b"partition".to_vec(),
+    );
+
+    let nodes = b"\x15\x03\xfe";
+    let partition = directory_node(nodes, nodes);
+    create_directory(&cluster, nodes, &partition, "orders", b"\x15\x03\x15\x01");
+
+    assert_eq!(
+        resolve_directory(&cluster, &path(&["tenant", "orders"]))
+            .await
+            .unwrap(),
+        b"\x15\x03\x15\x01"
+    );
+
+    // Errors name the first directory on the path that is missing
+    for missing in [&["logs"][..], &["app", "logs", "old"], &["tenant", "users"]] {
+        match resolve_directory(&cluster, &path(missing)).await {
+            Err(Error::DirectoryNotFound(name)) => {
+                assert_eq!(name, missing[..2.min(missing.len())].join("/"))
+            }
+            other => panic!("{:?} resolved to {:?}", missing, other),
+        }
+    }
+}
+
+fn covers(ranges: &[(Vec<u8>, Vec<u8>)], from: &[u8], to: &[u8]) -> bool {
+    ranges.first().map(|(start, _)| start.as_slice()) == Some(from)
+        && ranges.last().map(|(_, end)| end.as_slice()) == Some(to)
+        && ranges.windows(2).all(|pair| pair[0].1 == pair[1].0)
+        && ranges.iter().all(|(start, end)| start <= end)
+}
+
+#[test]
+fn splits_ranges_evenly() {
+    let ranges = split_range(b"users/\x00", b"users/\x80", 4);
+
+    assert_eq!(ranges.len(), 4);
+    assert!(covers(&ranges, b"users/\x00", b"users/\x80"));
+    assert_eq!(ranges[1].0, b"users/\x20\0\0\0\0\0\0\0");
+    assert_eq!(ranges[2].0, b"users/\x40\0\0\0\0\0\0\0");
+
+    // Ranges that can't be split are kept whole
+    assert_eq!(split_range(b"users/", b"users0", 1).len(), 1);
+    assert_eq!(split_range(b"users0", b"users/", 4).len(), 1);
+    assert_eq!(
+        split_range(b"users/\x00", b"users/\x00\0\0\0\0\0\0\0\x01", 4),
+        vec![(
+            b"users/\x00".to_vec(),
+            b"users/\x00\0\0\0\0\0\0\0\x01".to_vec()
+        )]
+    );
+}
+
+fn keys_in(cluster: &Cluster, (from, to): &(Vec<u8>, Vec<u8>)) -> usize {
+    cluster
+        .0
+        .lock()
+        .unwrap()
+        .range(from.clone()..to.clone())
+        .count()
+}
+
+#[tokio::test]
+async fn splits_ranges_by_size() {
+    let cluster = Cluster::users(8);
+
+    let ranges = split_by_size(&cluster, b"users/", b"users0", 4)
+        .await
+        .unwrap();
+    assert!(covers(&ranges, b"users/", b"users0"));
+    assert_eq!(
+        ranges
+            .iter()
+            .map(|range| keys_in(&cluster, range))
+            .collect::<Vec<_>>(),
+        vec![2, 2, 2, 2]
+    );
+
+    // Keys holding most of the data get a shard of their own
+    cluster.set("users/001", &[0; 1000]);
+
+    let ranges = split_by_size(&cluster, b"users/", b"users0", 2)
+        .await
+        .unwrap();
+    assert!(covers(&ranges, b"users/", b"users0"));
+    assert_eq!(
+        ranges
+            .iter()
+            .map(|range| keys_in(&cluster, range))
+            .collect::<Vec<_>>(),
+        vec![1, 7]
+    );
+}
+
+#[tokio::test]
+async fn splits_ranges_without_estimate() {
+    let cluster = Cluster::default();
+
+    let ranges = split_by_size(&cluster, b"users/", b"users0", 4)
+        .await
+        .unwrap();
+    assert_eq!(ranges, split_range(b"users/", b"users0", 4));
+
+    // Ranges too small to split get empty shards, so there are always as many
+    // shards as workers
+    let ranges = split_by_size(&cluster, b"users/", b"users/\x00", 3)
+        .await
+        .unwrap();
+    assert_eq!(
+        ranges,
+        vec![
+            (b"users/".to_vec(), b"users/\x00".to_vec()),
+            (b"users/\x00".to_vec(), b"users/\x00".to_vec()),
+            (b"users/\x00".to_vec(), b"users/\x00".to_vec()),
+        ]
+    );
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::config::{ElementPath, KeyTuple, Mapping};
+use fdb_ch_proto_export::key::{element_at, element_to_cell, unpack_key};
+
+/// `("users", 42, -1, b"\x00\x01", ("eu", 7), <uuid>, <versionstamp>)`
+fn user_key() -> Vec<u8> {
+    let mut key =
+        b"\x02users\x00\x15\x2a\x13\xfe\x01\x00\xff\x01\x00\x05\x02eu\x00\x15\x07\x00\x30".to_vec();
+    key.extend_from_slice(&[
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ]);
+    key.push(0x33);
+    key.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0, 3]);
+    key
+}
+
+fn cells(key: &[u8], prefix: &[u8]) -> Vec<Cell> {
+    unpack_key(key, prefix)
+        .unwrap()
+        .iter()
+        .map(element_to_cell)
+        .collect()
+}
+
+#[test]
+fn decodes_elements() {
+    assert_eq!(
+        cells(&user_key(), b""),
+        vec![
+            Cell::String("users".to_string()),
+            Cell::Int(42),
+            Cell::Int(-1),
+            Cell::Bytes(vec![0, 1]),
+            Cell::Tuple(vec![Cell::String("eu".to_string()), Cell::Int(7)]),
+            Cell::String("00112233-4455-6677-8899-aabbccddeeff".to_string()),
+            Cell::Bytes(vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0, 3]),
+        ]
+    );
+}
+
+#[test]
+fn decodes_after_prefix() {
+    let mut key = b"\x15\x01".to_vec();
+    key.extend_from_slice(b"\x02a\x00\x16\x01\x00");
+
+    assert_eq!(
+        cells(&key, b"\x15\x01"),
+        vec![Cell::String("a".to_string()), Cell::Int(256)]
+    );
+    assert!(unpack_key(&key, b"\x15\x02").is_err());
+    assert!(unpack_key(b"\x02unterminated", b"").is_err());
+}
+
+#[test]
+fn selects_nested_elements() {
+    let key = user_key();
+    let elements = unpack_key(&key, b"").unwrap();
+
+    assert_eq!(
+        element_at(&elements, &[4, 0]).map(element_to_cell),
+        Some(Cell::String("eu".to_string()))
+    );
+    assert_eq!(
+        element_at(&elements, &[1]).map(element_to_cell),
+        Some(Cell::Int(42))
+    );
+    assert!(element_at(&elements, &[1, 0]).is_none());
+    assert!(element_at(&elements, &[9]).is_none());
+}
+
+#[test]
+fn resolves_element_names() {
+    let key = KeyTuple {
+        elements: vec!["kind".to_string(), "user_id".to_string()],
+        columns: HashMap::from([
+            ("region".to_string(), ElementPath::Path("4.0".to_string())),
+            ("id".to_string(), ElementPath::Path("user_id".to_string())),
+            (
+                "missing".to_string(),
+                ElementPath::Path("other".to_string()),
+            ),
+        ]),
+    };
+
+    assert_eq!(key.column_path("user_id").unwrap().unwrap(), vec![1]);
+    assert_eq!(key.column_path("id").unwrap().unwrap(), vec![1]);
+    assert_eq!(key.column_path("region").unwrap().unwrap(), vec![4, 0]);
+    assert!(key.column_path("missing").unwrap().is_err());
+    assert!(key.column_path("name").is_none());
+    assert_eq!(key.resolve(&ElementPath::Index(3)), Some(vec![3]));
+}
+
+fn mapping(spec: serde_json::Value) -> Mapping {
+    let mut mapping = serde_json::json!({
+        "proto": "protos.User",
+        "table": "default.users"
+    });
+    mapping
+        .as_object_mut()
+        .unwrap()
+        .extend(spec.as_object().unwrap().clone());
+
+    serde_json::from_value(mapping).unwrap()
+}
+
+fn range(spec: serde_json::Value) -> (Vec<u8>, Vec<u8>) {
+    mapping(spec).range(b"").unwrap()
+}
+
+#[test]
+fn unpacks_after_mapping_prefix() {
+    let directory = b"\x15\x01";
+    let tuple = b"\x15\x2a\x02a\x00";
+    let unpacked = vec![Cell::Int(42), Cell::String("a".to_string())];
+
+    let prefixes = [
+        (json!({"prefix": "users/"}), b"users/".to_vec()),
+        (json!({"prefix": {"hex": "fe01"}}), vec![0xfe, 0x01]),
+        (json!({"prefix": ["users"]}), b"\x02users\x00".to_vec()),
+        (json!({"to": "\\xff"}), vec![]),
+    ];
+
+    for (spec, prefix) in prefixes {
+        let key_prefix = mapping(spec).key_prefix(directory).unwrap();
+        assert_eq!(key_prefix, [&directory[..], &prefix].concat());
+
+        let key = [&key_prefix[..], tuple].concat();
+        assert_eq!(cells(&key, &key_prefix), unpacked);
+    }
+
+    // Keys of the directory outside the prefix of the mapping are not unpacked
+    let key_prefix = mapping(json!({"prefix": "users/"}))
+        .key_prefix(directory)
+        .unwrap();
+    assert!(unpack_key(&[&directory[..], tuple].concat(), &key_prefix).is_err());
+}
+
+#[test]
+fn escaped_range() {
+    assert_eq!(
+        range(json!({"from": "users", "to": "users\\xFF"})),
+        (b"users".to_vec(), b"users\xff".to_vec())
+    );
+    assert_eq!(
+        range(json!({"from": "a\\\\b\\x00", "to": "a\\x5C\\x7e"})),
+        (b"a\\b\x00".to_vec(), b"a\\~".to_vec())
+    );
+
+    assert!(mapping(json!({"to": "users\\xF"})).range(b"").is_err());
+    assert!(mapping(json!({"to": "users\\n"})).range(b"").is_err());
+}
+
+#[test]
+fn hex_range() {
+    assert_eq!(
+        range(json!({"from": {"hex": "00ff"}, "to": {"hex": "0100"}})),
+        (vec![0x00, 0xff], vec![0x01, 0x00])
+    );
+    assert!(mapping(json!({"to": {"hex": "0"}})).range(b"").is_err());
+    assert!(mapping(json!({"to": {"hex": "zz"}})).range(b"").is_err());
+}
+
+#[test]
+fn tuple_range() {
+    assert_eq!(
+        range(json!({"from": ["users", 1], "to": ["users", 256]})),
+        (
+            b"\x02users\x00\x15\x01".to_vec(),
+            b"\x02users\x00\x16\x01\x00".to_vec()
+        )
+    );
+    assert_eq!(
+        range(json!({"from": [null, true, -1, ["a"]], "to": [{"bytes": "\\x00"}, {"hex": "ff"}]})),
+        (
+            b"\x00\x27\x13\xfe\x05\x02a\x00\x00".to_vec(),
+            b"\x01\x00\xff\x00\x01\xff\x00".to_vec()
+        )
+    );
+    assert!(mapping(json!({"to": [{"uuid": "x"}]})).range(b"").is_err());
+    assert!(mapping(json!({"to": [u64::MAX]})).range(b"").is_err());
+}
+
+#[test]
+fn prefix_range() {
+    assert_eq!(
+        range(json!({"prefix": "users/"})),
+        (b"users/".to_vec(), b"users0".to_vec())
+    );
+    assert_eq!(
+        range(json!({"prefix": ["users"]})),
+        (b"\x02users\x00".to_vec(), b"\x02users\x01".to_vec())
+    );
+    assert_eq!(
+        range(json!({"prefix": {"hex": "01ff"}, "from": [5]})),
+        (b"\x01\xff\x15\x05".to_vec(), b"\x02".to_vec())
+    );
+}
+
+#[test]
+fn directory_range() {
+    let directory = mapping(json!({"directory": ["app", "users"]}));
+    assert_eq!(
+        directory.range(b"\x15\x03").unwrap(),
+        (b"\x15\x03".to_vec(), b"\x15\x04".to_vec())
+    );
+
+    let nested = mapping(json!({"directory": ["app"], "prefix": ["users"], "to": [10]}));
+    assert_eq!(
+        nested.range(b"\x15\x03").unwrap(),
+        (
+            b"\x15\x03\x02users\x00".to_vec(),
+            b"\x15\x03\x02users\x00\x15\x0a".to_vec()
+        )
+    );
+}
+
+#[test]
+fn invalid_ranges() {
+    assert!(mapping(json!({})).range(b"").is_err());
+    assert!(mapping(json!({"from": "b", "to": "a"})).range(b"").is_err());
+    assert!(mapping(json!({"from": "a", "to": "a"})).range(b"").is_err());
+}
+
+#[test]
+fn mapping_ids_include_range() {
+    assert_eq!(
+        mapping(json!({"directory": ["app", "users"], "from": ["users", 1], "to": "users\\xFF"}))
+            .id(),
+        "protos.User:default.users@directory=app/users,from=[\"users\",1],to=\"users\\\\xFF\""
+    );
+
+    // Mappings of the same messages and table over different ranges are checkpointed apart
+    assert_ne!(
+        mapping(json!({"prefix": "eu/"})).id(),
+        mapping(json!({"prefix": "us/"})).id()
+    );
+}
+
+#[test]
+fn changelog_prefix() {
+    let prefix = |changelog: serde_json::Value| {
+        mapping(json!({"prefix": "users/", "changelog": changelog}))
+            .changelog_prefix()
+            .unwrap()
+    };
+
+    assert_eq!(
+        prefix(json!("changes\\x00users")),
+        Some(b"changes\x00users".to_vec())
+    );
+    assert_eq!(
+        prefix(json!(["changes", "users"])),
+        Some(b"\x02changes\x00\x02users\x00".to_vec())
+    );
+    assert_eq!(prefix(json!({"hex": "fe01"})), Some(b"\xfe\x01".to_vec()));
+    assert_eq!(
+        mapping(json!({"prefix": "users/"}))
+            .changelog_prefix()
+            .unwrap(),
+        None
+    );
+
+    assert!(
+        mapping(json!({"prefix": "users/", "changelog": "changes\\x"}))
+            .changelog_prefix()
+            .is_err()
+    );
+}
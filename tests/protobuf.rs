@@ -0,0 +1,195 @@
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_message_binding::bind_proto_message;
+use fdb_ch_proto_export::clickhouse_table::{ClickhouseTableParts, Table};
+use fdb_ch_proto_export::config::Mapping;
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::protobuf::{decode_any, unwrap_any, value_to_cell};
+use protofish::prelude::{Context, MessageValue, Value};
+
+#[test]
+fn any_type_url_and_value() {
+    // type_url = "type.googleapis.com/events.Click", value = { 1: 150 }
+    let mut any = vec![0x0a, 32];
+    any.extend_from_slice(b"type.googleapis.com/events.Click");
+    any.extend_from_slice(&[0x12, 3, 0x08, 0x96, 0x01]);
+
+    let (type_url, value) = decode_any(&any).unwrap();
+    assert_eq!(type_url, "type.googleapis.com/events.Click");
+    assert_eq!(value, [0x08, 0x96, 0x01]);
+}
+
+#[test]
+fn any_skips_unknown_fields() {
+    // 3: 1 (varint), 4: fixed64, 5: fixed32, then value = "ab"
+    let any = [
+        0x18, 0x01, 0x21, 0, 0, 0, 0, 0, 0, 0, 0, 0x2d, 0, 0, 0, 0, 0x12, 2, b'a', b'b',
+    ];
+
+    let (type_url, value) = decode_any(&any).unwrap();
+    assert_eq!(type_url, "");
+    assert_eq!(value, b"ab");
+}
+
+#[test]
+fn invalid_any() {
+    assert!(decode_any(&[0x0a, 5, b'a']).is_err());
+    assert!(decode_any(&[0x12, 0x80]).is_err());
+    assert!(decode_any(&[0x0b]).is_err());
+    assert!(decode_any(&[0x21, 0]).is_err());
+}
+
+const EVENTS: &str = r#"
+syntax = "proto3";
+package events;
+
+import "google/protobuf/any.proto";
+
+message Click {
+  int32 x = 1;
+}
+
+message Batch {
+  google.protobuf.Any inner = 1;
+}
+
+message Envelope {
+  string id = 1;
+  google.protobuf.Any details = 2;
+}
+"#;
+
+fn context() -> Context {
+    let any = std::fs::read_to_string("google_protobuf/any.proto").unwrap();
+
+    Context::parse(&[EVENTS, &any]).unwrap()
+}
+
+/// A length delimited field.
+fn bytes_field(number: u8, bytes: &[u8]) -> Vec<u8> {
+    [&[number << 3 | 2, bytes.len() as u8][..], bytes].concat()
+}
+
+/// A google.protobuf.Any wrapping the encoded message of the type.
+fn any(type_name: &str, message: &[u8]) -> Vec<u8> {
+    let type_url = format!("type.googleapis.com/{}", type_name);
+
+    [bytes_field(1, type_url.as_bytes()), bytes_field(2, message)].concat()
+}
+
+fn decode_any_message(ctx: &Context, bytes: &[u8]) -> MessageValue {
+    ctx.get_message("google.protobuf.Any")
+        .unwrap()
+        .decode(bytes, ctx)
+}
+
+fn any_cell(ctx: &Context, bytes: &[u8]) -> Cell {
+    let any = decode_any_message(ctx, bytes);
+
+    value_to_cell(ctx, &Converters::default(), &Value::Message(Box::new(any))).unwrap()
+}
+
+fn type_field(type_name: &str) -> (String, Cell) {
+    (
+        "@type".to_string(),
+        Cell::String(format!("type.googleapis.com/{}", type_name)),
+    )
+}
+
+#[test]
+fn any_of_known_type() {
+    let ctx = context();
+    let click = any("events.Click", &[0x08, 0x96, 0x01]);
+
+    let (type_url, wrapped) = unwrap_any(&ctx, &decode_any_message(&ctx, &click));
+    assert_eq!(type_url, "type.googleapis.com/events.Click");
+    assert_eq!(wrapped.unwrap().0.full_name, "events.Click");
+
+    assert_eq!(
+        any_cell(&ctx, &click),
+        Cell::Message(vec![
+            type_field("events.Click"),
+            ("x".to_string(), Cell::Int(150))
+        ])
+    );
+}
+
+#[test]
+fn any_of_unknown_type() {
+    let ctx = context();
+    let missing = any("events.Missing", &[0x08, 0x96, 0x01]);
+
+    let (type_url, wrapped) = unwrap_any(&ctx, &decode_any_message(&ctx, &missing));
+    assert_eq!(type_url, "type.googleapis.com/events.Missing");
+    assert!(wrapped.is_none());
+
+    assert_eq!(
+        any_cell(&ctx, &missing),
+        Cell::Message(vec![type_field("events.Missing")])
+    );
+}
+
+#[test]
+fn nested_any() {
+    let ctx = context();
+    let click = any("events.Click", &[0x08, 0x01]);
+    let batch = any("events.Batch", &bytes_field(1, &click));
+
+    assert_eq!(
+        any_cell(&ctx, &batch),
+        Cell::Message(vec![
+            type_field("events.Batch"),
+            (
+                "inner".to_string(),
+                Cell::Message(vec![
+                    type_field("events.Click"),
+                    ("x".to_string(), Cell::Int(1))
+                ])
+            ),
+        ])
+    );
+}
+
+#[test]
+fn columns_bound_to_any_of_unknown_type() {
+    let ctx = context();
+    let envelope = ctx.get_message("events.Envelope").unwrap();
+    let mapping: Mapping =
+        serde_json::from_str(r#"{ "any": { "details": "events.Click" } }"#).unwrap();
+    let columns = [("id", "String"), ("details_x", "Int32")]
+        .iter()
+        .enumerate()
+        .map(|(i, (name, r#type))| {
+            ClickhouseTableColumnRow {
+                name: name.to_string(),
+                position: i as u64 + 1,
+                r#type: r#type.to_string(),
+                default_expression: String::new(),
+            }
+            .try_into()
+            .unwrap()
+        })
+        .collect();
+    let table = Table::new(
+        ClickhouseTableParts::from_string("events.envelopes").unwrap(),
+        columns,
+    );
+    let converters = Converters::default();
+    let binding = bind_proto_message(&ctx, &converters, envelope, table, &mapping).unwrap();
+
+    let id = bytes_field(1, b"e1");
+    let row = |details: &[u8]| {
+        let message = [&id[..], &bytes_field(2, details)].concat();
+        binding.prepare(&ctx, b"key", &message).unwrap()
+    };
+
+    let click = row(&any("events.Click", &[0x08, 0x07]));
+    assert_eq!(
+        click.values().cloned().collect::<Vec<_>>(),
+        [Cell::String("e1".to_string()), Cell::Int(7)]
+    );
+
+    let unset = binding.prepare(&ctx, b"key", &id).unwrap();
+    assert_eq!(row(&any("events.Missing", &[0x08, 0x07])), unset);
+    assert_eq!(row(&any("events.Batch", &[])), unset);
+}
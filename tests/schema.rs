@@ -0,0 +1,381 @@
+use fdb_ch_proto_export::clickhouse::ClickhouseTableColumnRow;
+use fdb_ch_proto_export::clickhouse_table::{quote_name, ClickhouseTableParts, Table};
+use fdb_ch_proto_export::clickhouse_type::ColumnType;
+use fdb_ch_proto_export::config::Mapping;
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::schema::{
+    create_table, diff_report, diff_table, message_columns, nullable, MismatchedColumn, SchemaDiff,
+    TableOptions,
+};
+use protofish::prelude::Context;
+
+const PROTO: &str = r#"
+syntax = "proto3";
+package shop;
+
+import "google/protobuf/timestamp.proto";
+import "google/protobuf/wrappers.proto";
+
+enum Status {
+  UNKNOWN = 0;
+  PAID = 1;
+}
+
+message Address {
+  string city = 1;
+  Point location = 2;
+}
+
+message Point {
+  double x = 1;
+  double y = 2;
+}
+
+message Order {
+  uint64 id = 1;
+  string customerName = 2;
+  Status status = 3;
+  repeated string tags = 4;
+  map<string, int32> counts = 5;
+  google.protobuf.Timestamp created = 6;
+  google.protobuf.StringValue note = 7;
+  Address address = 8;
+  repeated Point path = 9;
+}
+"#;
+
+fn context() -> Context {
+    let timestamp = std::fs::read_to_string("google_protobuf/timestamp.proto").unwrap();
+    let wrappers = std::fs::read_to_string("google_protobuf/wrappers.proto").unwrap();
+
+    Context::parse(&[PROTO, &timestamp, &wrappers]).unwrap()
+}
+
+fn columns(options: &TableOptions, mapping: &Mapping) -> Vec<(String, String)> {
+    let ctx = context();
+    let order = ctx.get_message("shop.Order").unwrap();
+
+    message_columns(&ctx, &Converters::default(), order, mapping, options)
+        .into_iter()
+        .map(|column| (column.name, column.r#type.to_string()))
+        .collect()
+}
+
+fn column(name: &str, r#type: &str) -> (String, String) {
+    (name.to_string(), r#type.to_string())
+}
+
+#[test]
+fn message_types() {
+    assert_eq!(
+        columns(&TableOptions::default(), &Mapping::default()),
+        [
+            column("id", "UInt64"),
+            column("customerName", "String"),
+            column("status", "Enum8('UNKNOWN' = 0, 'PAID' = 1)"),
+            column("tags", "Array(String)"),
+            column("counts", "Map(String, Int32)"),
+            column("created", "DateTime64(9)"),
+            column("note", "String"),
+            column("address", "String"),
+            column("path", "String"),
+        ]
+    );
+}
+
+#[test]
+fn flattened_messages() {
+    let mapping: Mapping = serde_json::from_str(r#"{ "naming": "snake_case" }"#).unwrap();
+    let options = TableOptions {
+        nullable: true,
+        flatten: 1,
+        ..TableOptions::default()
+    };
+
+    let columns = columns(&options, &mapping);
+    assert_eq!(columns[1], column("customer_name", "String"));
+    assert_eq!(columns[6], column("note", "Nullable(String)"));
+    assert_eq!(columns[7], column("address_city", "Nullable(String)"));
+    assert_eq!(columns[8], column("address_location", "Nullable(String)"));
+    assert_eq!(columns[9], column("path", "String"));
+}
+
+#[test]
+fn mapped_columns() {
+    let mapping: Mapping = serde_json::from_value(serde_json::json!({
+        "columns": {
+            "order_id": "id",
+            "city": "address.city",
+            "loc": "address.location",
+        },
+        "expressions": { "status": "'PAID'" },
+        "key": { "elements": ["tags"] },
+    }))
+    .unwrap();
+    let options = TableOptions {
+        flatten: 1,
+        ..TableOptions::default()
+    };
+
+    // Columns of expressions and key elements are not generated for the fields
+    assert_eq!(
+        columns(&options, &mapping),
+        [
+            column("order_id", "UInt64"),
+            column("customerName", "String"),
+            column("counts", "Map(String, Int32)"),
+            column("created", "DateTime64(9)"),
+            column("note", "String"),
+            column("city", "String"),
+            column("loc", "String"),
+            column("path", "String"),
+        ]
+    );
+}
+
+#[test]
+fn nullable_types() {
+    assert_eq!(
+        nullable(ColumnType::String),
+        ColumnType::Nullable(Box::new(ColumnType::String))
+    );
+    assert_eq!(
+        nullable(ColumnType::Array(Box::new(ColumnType::String))),
+        ColumnType::Array(Box::new(ColumnType::String))
+    );
+}
+
+#[test]
+fn create_table_query() {
+    let table = ClickhouseTableParts::from_string("shop.orders").unwrap();
+    let columns = [
+        ("id".to_string(), ColumnType::UInt64),
+        (
+            "customer name".to_string(),
+            ColumnType::Nullable(Box::new(ColumnType::String)),
+        ),
+    ];
+
+    assert_eq!(
+        create_table(&table, &columns, &TableOptions::default()),
+        "CREATE TABLE `shop`.`orders`\n(\n    `id` UInt64,\n    `customer name` Nullable(String)\n)\nENGINE = MergeTree\nORDER BY tuple()"
+    );
+
+    let options = TableOptions {
+        engine: Some("ReplacingMergeTree".to_string()),
+        order_by: Some("id".to_string()),
+        partition_by: Some("intDiv(id, 1000)".to_string()),
+        ..TableOptions::default()
+    };
+    assert_eq!(
+        create_table(&table, &columns[..1], &options),
+        "CREATE TABLE `shop`.`orders`\n(\n    `id` UInt64\n)\nENGINE = ReplacingMergeTree\nPARTITION BY intDiv(id, 1000)\nORDER BY id"
+    );
+
+    let options = TableOptions {
+        engine: Some("Memory".to_string()),
+        ..TableOptions::default()
+    };
+    assert_eq!(
+        create_table(&table, &columns[..1], &options),
+        "CREATE TABLE `shop`.`orders`\n(\n    `id` UInt64\n)\nENGINE = Memory"
+    );
+}
+
+#[test]
+fn quoted_names() {
+    let table = ClickhouseTableParts {
+        database: "shop".to_string(),
+        table: "order`s".to_string(),
+    };
+    assert_eq!(table.quoted(), "`shop`.`order\\`s`");
+    assert_eq!(quote_name("a\\b"), "`a\\\\b`");
+
+    let columns = [("id".to_string(), ColumnType::UInt64)];
+    assert!(create_table(&table, &columns, &TableOptions::default())
+        .starts_with("CREATE TABLE `shop`.`order\\`s`\n"));
+}
+
+fn table(columns: &[(&str, &str)]) -> Table {
+    let columns = columns
+        .iter()
+        .enumerate()
+        .map(|(i, (name, r#type))| {
+            ClickhouseTableColumnRow {
+                name: name.to_string(),
+                position: i as u64 + 1,
+                r#type: r#type.to_string(),
+                default_expression: String::new(),
+            }
+            .try_into()
+            .unwrap()
+        })
+        .collect();
+
+    Table::new(
+        ClickhouseTableParts::from_string("shop.orders").unwrap(),
+        columns,
+    )
+}
+
+#[test]
+fn table_differences() {
+    let ctx = context();
+    let order = ctx.get_message("shop.Order").unwrap();
+    let mapping: Mapping = serde_json::from_str(
+        r#"{ "columns": { "customer": "customerName" }, "expressions": { "day": "now()" } }"#,
+    )
+    .unwrap();
+
+    let table = table(&[
+        ("id", "Int8"),
+        ("customer", "String"),
+        ("status", "Enum8('UNKNOWN' = 0, 'PAID' = 1)"),
+        ("tags", "Array(String)"),
+        ("address_city", "Nullable(String)"),
+        ("day", "Date"),
+        ("legacy", "UInt8"),
+    ]);
+    let diff = diff_table(
+        &ctx,
+        &Converters::default(),
+        order,
+        &table,
+        &mapping,
+        &TableOptions::default(),
+    );
+
+    let missing: Vec<&str> = diff.missing.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(missing, ["counts", "created", "note", "path"]);
+    assert_eq!(diff.mismatched.len(), 1);
+    assert_eq!(diff.mismatched[0].name, "id");
+    assert_eq!(diff.mismatched[0].expected, ColumnType::UInt64);
+    assert_eq!(diff.orphaned, ["legacy"]);
+}
+
+#[test]
+fn enum_differences() {
+    let ctx = context();
+    let order = ctx.get_message("shop.Order").unwrap();
+    let mapping = Mapping::default();
+    let differences = |columns: &[(&str, &str)]| {
+        diff_table(
+            &ctx,
+            &Converters::default(),
+            order,
+            &table(columns),
+            &mapping,
+            &TableOptions::default(),
+        )
+    };
+    let status = ColumnType::Enum8(vec![("UNKNOWN".to_string(), 0), ("PAID".to_string(), 1)]);
+
+    let diff = differences(&[("id", "UInt64"), ("status", "Enum8('UNKNOWN' = 0)")]);
+    assert_eq!(diff.mismatched.len(), 1);
+    assert_eq!(diff.mismatched[0].name, "status");
+    assert_eq!(diff.mismatched[0].reason, "missing enum values PAID");
+    assert_eq!(diff.mismatched[0].expected, status);
+    assert!(!diff.mismatched[0].lossy);
+    assert_eq!(
+        diff.statements(false),
+        ["ALTER TABLE `shop`.`orders` MODIFY COLUMN `status` Enum8('UNKNOWN' = 0, 'PAID' = 1)"]
+    );
+
+    // Values removed from the proto are lost by the modification
+    let diff = differences(&[("status", "Enum8('UNKNOWN' = 0, 'SHIPPED' = 2)")]);
+    assert_eq!(diff.mismatched[0].expected, status);
+    assert!(diff.mismatched[0].lossy);
+
+    let diff = differences(&[("status", "Enum8('UNKNOWN' = 0, 'PAID' = 1)")]);
+    assert!(diff.mismatched.is_empty());
+}
+
+#[test]
+fn diff_statements() {
+    let diff = SchemaDiff {
+        proto: "shop.Order".to_string(),
+        table: ClickhouseTableParts::from_string("shop.orders").unwrap(),
+        exists: true,
+        missing: vec![("note".to_string(), ColumnType::String)],
+        mismatched: vec![MismatchedColumn {
+            name: "id".to_string(),
+            r#type: ColumnType::Int8,
+            expected: ColumnType::UInt64,
+            reason: "uint64 values don't fit in Int8".to_string(),
+            lossy: true,
+        }],
+        orphaned: vec!["legacy".to_string()],
+        options: TableOptions::default(),
+    };
+
+    assert_eq!(
+        diff.statements(false),
+        ["ALTER TABLE `shop`.`orders` ADD COLUMN `note` String"]
+    );
+    assert_eq!(
+        diff.statements(true),
+        [
+            "ALTER TABLE `shop`.`orders` ADD COLUMN `note` String",
+            "ALTER TABLE `shop`.`orders` MODIFY COLUMN `id` UInt64",
+        ]
+    );
+    assert_eq!(
+        diff_report(&[diff]),
+        "shop.orders (shop.Order)
+  missing column note String
+  mismatched column id Int8: uint64 values don't fit in Int8
+  orphaned column legacy
+  ALTER TABLE `shop`.`orders` ADD COLUMN `note` String;
+  ALTER TABLE `shop`.`orders` MODIFY COLUMN `id` UInt64; -- may lose values"
+    );
+
+    let missing_table = SchemaDiff {
+        proto: "shop.Order".to_string(),
+        table: ClickhouseTableParts::from_string("shop.orders").unwrap(),
+        exists: false,
+        missing: vec![("id".to_string(), ColumnType::UInt64)],
+        mismatched: vec![],
+        orphaned: vec![],
+        options: TableOptions {
+            order_by: Some("id".to_string()),
+            ..TableOptions::default()
+        },
+    };
+    assert!(missing_table.statements(true).is_empty());
+    assert_eq!(
+        diff_report(&[missing_table]),
+        "shop.orders (shop.Order): missing table
+  CREATE TABLE `shop`.`orders`
+  (
+      `id` UInt64
+  )
+  ENGINE = MergeTree
+  ORDER BY id;"
+    );
+}
+
+#[test]
+fn missing_table_options() {
+    let ctx = context();
+    let order = ctx.get_message("shop.Order").unwrap();
+    let options = TableOptions {
+        nullable: true,
+        flatten: 1,
+        ..TableOptions::default()
+    };
+    let diff = diff_table(
+        &ctx,
+        &Converters::default(),
+        order,
+        &table(&[]),
+        &Mapping::default(),
+        &options,
+    );
+
+    // The table is created with the columns of the diff options
+    assert!(!diff.exists);
+    let report = diff_report(&[diff]);
+    assert!(report.contains("`note` Nullable(String)"));
+    assert!(report.contains("`address_city` Nullable(String)"));
+    assert!(!report.contains("`address` "));
+}
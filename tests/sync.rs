@@ -0,0 +1,103 @@
+mod common;
+
+use common::{state_file, Cluster, Writer};
+use fdb_ch_proto_export::checkpoint::{CheckpointStore, FileCheckpointStore};
+use fdb_ch_proto_export::sync::ChangelogTail;
+
+fn users_range() -> (Vec<u8>, Vec<u8>) {
+    (b"users/".to_vec(), b"users0".to_vec())
+}
+
+async fn open_tail(checkpoints: &CheckpointStore, cluster: &Cluster) -> ChangelogTail {
+    ChangelogTail::open(
+        checkpoints,
+        cluster,
+        "users:changelog".to_string(),
+        b"changes/".to_vec(),
+        users_range(),
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn writes_changes_in_changelog_order() {
+    let path = state_file("sync-order");
+    let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+    let cluster = Cluster::users(3);
+
+    // Changes made before the tail is opened are covered by the initial export
+    cluster.set("changes/00", b"users/003");
+
+    let mut tail = open_tail(&checkpoints, &cluster).await;
+
+    cluster.set("changes/01", b"users/002");
+    cluster.set("changes/02", b"users/001");
+    cluster.set("changes/03", b"users/002");
+    cluster.set("changes/04", b"orders/001");
+    cluster.set("changes/05", b"users/009");
+
+    let writer = Writer::default();
+    let written = tail.poll(&checkpoints, &cluster, &writer).await.unwrap();
+
+    // Keys changed twice in a batch are written once, keys out of the range of
+    // the mapping and deleted keys are skipped
+    assert_eq!(written, 3);
+    assert_eq!(
+        *writer.batches.lock().unwrap(),
+        vec![
+            vec!["users/002", "users/001"],
+            vec!["users/002"],
+            Vec::<&str>::new()
+        ]
+    );
+
+    // Nothing is written again until the changelog grows
+    let writer = Writer::default();
+    assert_eq!(tail.poll(&checkpoints, &cluster, &writer).await.unwrap(), 0);
+
+    cluster.set("changes/06", b"users/003");
+    tail.poll(&checkpoints, &cluster, &writer).await.unwrap();
+    assert_eq!(writer.keys(), vec!["users/003"]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn resumes_tail_from_checkpoint() {
+    let path = state_file("sync-resume");
+    let cluster = Cluster::users(3);
+
+    {
+        let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+
+        // The tail of an empty changelog is checkpointed when opened, so changes
+        // made before a restart are not missed
+        open_tail(&checkpoints, &cluster).await;
+    }
+
+    cluster.set("changes/01", b"users/001");
+    cluster.set("changes/02", b"users/002");
+    cluster.set("changes/03", b"users/003");
+
+    {
+        let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+        let mut tail = open_tail(&checkpoints, &cluster).await;
+
+        // The second batch of changes fails to write
+        let writer = Writer::failing_at(1);
+        assert!(tail.poll(&checkpoints, &cluster, &writer).await.is_err());
+        assert_eq!(writer.keys(), vec!["users/001", "users/002"]);
+    }
+
+    let checkpoints = CheckpointStore::File(FileCheckpointStore::open(&path).unwrap());
+    let mut tail = open_tail(&checkpoints, &cluster).await;
+    assert_eq!(tail.messages_written, 2);
+
+    let writer = Writer::default();
+    tail.poll(&checkpoints, &cluster, &writer).await.unwrap();
+    assert_eq!(writer.keys(), vec!["users/003"]);
+    assert_eq!(tail.messages_written, 3);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,215 @@
+use fdb_ch_proto_export::cell::Cell;
+use fdb_ch_proto_export::converter::Converters;
+use fdb_ch_proto_export::protobuf::value_to_cell;
+use protofish::prelude::{Context, Value};
+
+/// A context of the well-known types bundled in `google_protobuf`.
+fn context() -> Context {
+    let mut protos = vec![];
+    for entry in std::fs::read_dir("google_protobuf").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("proto") {
+            protos.push(std::fs::read_to_string(path).unwrap());
+        }
+    }
+
+    Context::parse(&protos).unwrap()
+}
+
+fn decode(ctx: &Context, name: &str, bytes: &[u8]) -> Cell {
+    let message = ctx.get_message(name).unwrap().decode(bytes, ctx);
+
+    value_to_cell(
+        ctx,
+        &Converters::default(),
+        &Value::Message(Box::new(message)),
+    )
+    .unwrap()
+}
+
+/// A length delimited field.
+fn bytes_field(number: u8, bytes: &[u8]) -> Vec<u8> {
+    [&[number << 3 | 2, bytes.len() as u8][..], bytes].concat()
+}
+
+#[test]
+fn timestamp() {
+    let ctx = context();
+
+    // seconds = 1600000000, nanos = 123456789
+    let timestamp = [
+        0x08, 0x80, 0xa0, 0xf8, 0xfa, 0x05, 0x10, 0x95, 0x9a, 0xef, 0x3a,
+    ];
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Timestamp", &timestamp),
+        Cell::DateTime {
+            seconds: 1_600_000_000,
+            nanos: 123_456_789
+        }
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Timestamp", &[]),
+        Cell::DateTime {
+            seconds: 0,
+            nanos: 0
+        }
+    );
+}
+
+#[test]
+fn duration() {
+    let ctx = context();
+
+    // seconds = 90, nanos = 500000000
+    assert_eq!(
+        decode(
+            &ctx,
+            "google.protobuf.Duration",
+            &[0x08, 90, 0x10, 0x80, 0xca, 0xb5, 0xee, 0x01]
+        ),
+        Cell::Duration {
+            seconds: 90,
+            nanos: 500_000_000
+        }
+    );
+}
+
+#[test]
+fn wrappers() {
+    let ctx = context();
+
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Int32Value", &[0x08, 0x7f]),
+        Cell::Int(127)
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.UInt64Value", &[0x08, 0x01]),
+        Cell::UInt(1)
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.BoolValue", &[0x08, 0x01]),
+        Cell::Bool(true)
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.StringValue", &bytes_field(1, b"abc")),
+        Cell::String("abc".into())
+    );
+    assert_eq!(
+        decode(
+            &ctx,
+            "google.protobuf.DoubleValue",
+            &[&[0x09][..], &1.5f64.to_le_bytes()].concat()
+        ),
+        Cell::Float(1.5)
+    );
+
+    // A wrapper that is set holds its value even when it is the default
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Int64Value", &[]),
+        Cell::Int(0)
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.StringValue", &[]),
+        Cell::String(String::new())
+    );
+}
+
+#[test]
+fn field_mask() {
+    let ctx = context();
+
+    let mask = [bytes_field(1, b"user.name"), bytes_field(1, b"user.age")].concat();
+    assert_eq!(
+        decode(&ctx, "google.protobuf.FieldMask", &mask),
+        Cell::Array(vec!["user.name".into(), "user.age".into()])
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.FieldMask", &[]),
+        Cell::Array(vec![])
+    );
+}
+
+#[test]
+fn struct_value() {
+    let ctx = context();
+
+    // {"name": "a", "count": 2, "tags": [true, null], "none": null}
+    let list = [bytes_field(1, &[0x20, 0x01]), bytes_field(1, &[0x08, 0x00])].concat();
+    let entries = [
+        bytes_field(
+            1,
+            &[
+                bytes_field(1, b"name"),
+                bytes_field(2, &bytes_field(3, b"a")),
+            ]
+            .concat(),
+        ),
+        bytes_field(
+            1,
+            &[
+                bytes_field(1, b"count"),
+                bytes_field(2, &[&[0x11][..], &2f64.to_le_bytes()].concat()),
+            ]
+            .concat(),
+        ),
+        bytes_field(
+            1,
+            &[
+                bytes_field(1, b"tags"),
+                bytes_field(2, &bytes_field(6, &list)),
+            ]
+            .concat(),
+        ),
+        bytes_field(
+            1,
+            &[bytes_field(1, b"none"), bytes_field(2, &[0x08, 0x00])].concat(),
+        ),
+    ]
+    .concat();
+
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Struct", &entries),
+        Cell::Message(vec![
+            ("name".to_string(), "a".into()),
+            ("count".to_string(), Cell::Float(2.0)),
+            (
+                "tags".to_string(),
+                Cell::Array(vec![Cell::Bool(true), Cell::Null])
+            ),
+            ("none".to_string(), Cell::Null),
+        ])
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Struct", &[]),
+        Cell::Message(vec![])
+    );
+}
+
+#[test]
+fn value() {
+    let ctx = context();
+
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Value", &bytes_field(3, b"text")),
+        Cell::String("text".into())
+    );
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Value", &bytes_field(5, &[])),
+        Cell::Message(vec![])
+    );
+    assert_eq!(decode(&ctx, "google.protobuf.Value", &[]), Cell::Null);
+    assert_eq!(
+        decode(&ctx, "google.protobuf.ListValue", &[]),
+        Cell::Array(vec![])
+    );
+}
+
+#[test]
+fn empty() {
+    let ctx = context();
+
+    assert_eq!(
+        decode(&ctx, "google.protobuf.Empty", &[]),
+        Cell::Message(vec![])
+    );
+}